@@ -0,0 +1,203 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use chrono::Timelike;
+
+use crate::error::{self, error, function_message, ErrorKind};
+use crate::shared::dbt;
+
+const NEW_ORDER_SOUND: &[u8] = include_bytes!("../sounds/new_order.wav");
+const OVERDUE_SOUND:   &[u8] = include_bytes!("../sounds/overdue.wav");
+const DENIED_SOUND:    &[u8] = include_bytes!("../sounds/denied.wav");
+
+const APP_NAME: &str = "OBY Desktop";
+
+/// Players tried in order until one starts, the first one
+/// installed wins. Each gets the path of a WAV file.
+#[cfg(target_os = "linux")]
+const PLAYERS: &[(&str, &[&str])] = &[("paplay", &[]), ("pw-play", &[]), ("aplay", &["-q"])];
+#[cfg(target_os = "macos")]
+const PLAYERS: &[(&str, &[&str])] = &[("afplay", &[])];
+#[cfg(target_os = "windows")]
+const PLAYERS: &[(&str, &[&str])] = &[("powershell", &["-NoProfile", "-Command", "(New-Object Media.SoundPlayer $args[0]).PlaySync()"])];
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+const PLAYERS: &[(&str, &[&str])] = &[];
+
+/// Something staff should notice while looking elsewhere.
+#[derive(Debug, Clone)]
+pub enum Alert {
+    /// `count` is unknown when only polled tables are compared.
+    NewOrder { table: dbt::VirtualTableID, count: Option<u32> },
+    Overdue { order: dbt::OrderID, minutes: u64 },
+    Denied { order: dbt::OrderID, reason: String },
+}
+
+impl Alert {
+
+    pub fn title(&self) -> String {
+        match self {
+            Alert::NewOrder { table, count: Some(count) } => format!("New order {} #{}", table, count),
+            Alert::NewOrder { table, count: None } => format!("New order at {}", table),
+            Alert::Overdue { order, .. } => format!("Order {} #{} is overdue", order.table, order.count),
+            Alert::Denied { order, .. } => format!("Order {} #{} was denied", order.table, order.count),
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            Alert::NewOrder { .. } => String::new(),
+            Alert::Overdue { minutes, .. } => format!("Waiting for over {} min", minutes),
+            Alert::Denied { reason, .. } => reason.clone(),
+        }
+    }
+
+    fn sound(&self) -> (&'static str, &'static [u8]) {
+        match self {
+            Alert::NewOrder { .. } => ("new_order", NEW_ORDER_SOUND),
+            Alert::Overdue { .. } => ("overdue", OVERDUE_SOUND),
+            Alert::Denied { .. } => ("denied", DENIED_SOUND),
+        }
+    }
+
+}
+
+/// Which alerts reach staff and how, from the user settings.
+#[derive(Debug, Clone, Copy)]
+pub struct AlertSettings {
+    pub new_orders: bool,
+    pub overdue:    bool,
+    pub denied:     bool,
+    /// Volume of the sounds, in percent.
+    pub volume:     u32,
+    pub muted:      bool,
+    pub desktop:    bool,
+    /// Hours from and until which there are no sounds and no
+    /// desktop notifications, e.g. `(22, 7)` over midnight.
+    pub quiet_hours: Option<(u32, u32)>,
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        crate::user::Settings::default().alerts()
+    }
+}
+
+impl AlertSettings {
+
+    pub fn wants(&self, alert: &Alert) -> bool {
+        match alert {
+            Alert::NewOrder { .. } => self.new_orders,
+            Alert::Overdue { .. } => self.overdue,
+            Alert::Denied { .. } => self.denied,
+        }
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        let hour = chrono::Local::now().hour();
+        match self.quiet_hours {
+            Some((from, until)) if from <= until => (from..until).contains(&hour),
+            Some((from, until)) => hour >= from || hour < until,
+            None => false
+        }
+    }
+
+}
+
+/// Plays the sound and shows the desktop notification of `alert`
+/// as far as `settings` allow, without waiting for either.
+pub fn sound_and_notify(alert: &Alert, settings: &AlertSettings) {
+
+    if settings.is_quiet() {
+        return
+    }
+
+    if !settings.muted && settings.volume > 0 {
+        play(alert, settings.volume);
+    }
+
+    if settings.desktop {
+        let (title, detail) = (alert.title(), alert.detail());
+        std::thread::spawn(move || {
+            // Not every desktop runs a notification server.
+            if let Err(err) = notify_rust::Notification::new()
+                .appname(APP_NAME)
+                .summary(&title)
+                .body(&detail)
+                .show() {
+                log::info!("No desktop notification: {}", err);
+            }
+        });
+    }
+
+}
+
+/// Plays the sound of `alert` at `volume` percent.
+pub fn play(alert: &Alert, volume: u32) {
+    let (name, sound) = alert.sound();
+    std::thread::spawn(move || {
+        match scaled(name, sound, volume) {
+            Ok(file) => play_file(&file),
+            Err(err) => log::warn!("{}", err),
+        }
+    });
+}
+
+/// Writes `sound` at `volume` percent to a temporary file, the
+/// players know no volume of their own.
+fn scaled(name: &str, sound: &[u8], volume: u32) -> Result<PathBuf, error::Error> {
+
+    let file = std::env::temp_dir().join(format!("oby-{}-{}.wav", name, volume));
+    if file.exists() {
+        return Ok(file)
+    }
+
+    let mut reader = match hound::WavReader::new(Cursor::new(sound)) {
+        Ok(reader) => reader,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: format!("The `{}` sound is broken", name),
+            message: function_message!("hound::WavReader::new", err.to_string())
+        )
+    };
+
+    let factor = volume.min(100) as f32 / 100.0;
+    let written = hound::WavWriter::create(&file, reader.spec()).and_then(|mut writer| {
+        for sample in reader.samples::<i16>() {
+            writer.write_sample((sample? as f32 * factor) as i16)?;
+        }
+        writer.finalize()
+    });
+
+    match written {
+        Ok(()) => Ok(file),
+        Err(err) => {
+            // Half written, it would be played next time.
+            let _ = std::fs::remove_file(&file);
+            error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("hound::WavWriter::write_sample", err.to_string())
+            )
+        }
+    }
+
+}
+
+fn play_file(file: &std::path::Path) {
+    for (player, args) in PLAYERS {
+        let status = Command::new(player)
+            .args(*args)
+            .arg(file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => return,
+            Ok(status) => log::info!("`{}` failed to play the alert: {}", player, status),
+            Err(err) => log::info!("Cannot run `{}`: {}", player, err),
+        }
+    }
+    log::warn!("Cannot play the alert sound, no player could play it. Install e.g. `paplay` or `aplay`.");
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+const LOG_FILE: &str = "audit.log";
+
+/// Staff actions that change data on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditAction {
+    FinishOrder,
+    /// A station prepared its part of an order.
+    StationDone,
+    /// An item of an order moved on, e.g. to served.
+    ItemStatus,
+    MoveOrder,
+    MergeTables,
+    /// A QR order waiting for confirmation was let through.
+    AcceptOrder,
+    DenyOrder,
+    AddVirtualTable,
+    DeleteVirtualTable,
+    /// Guests may order from their phones until it is closed.
+    OpenTable,
+    CloseTable,
+    /// New signing key for the QR codes of the tables.
+    RotateKey,
+    AddOffer,
+    DeleteOffer,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 14] = [
+        AuditAction::FinishOrder,
+        AuditAction::StationDone,
+        AuditAction::ItemStatus,
+        AuditAction::MoveOrder,
+        AuditAction::MergeTables,
+        AuditAction::AcceptOrder,
+        AuditAction::DenyOrder,
+        AuditAction::AddVirtualTable,
+        AuditAction::DeleteVirtualTable,
+        AuditAction::OpenTable,
+        AuditAction::CloseTable,
+        AuditAction::RotateKey,
+        AuditAction::AddOffer,
+        AuditAction::DeleteOffer,
+    ];
+}
+
+impl std::fmt::Display for AuditAction {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditAction::FinishOrder        => write!(f, "Finish order"),
+            AuditAction::StationDone        => write!(f, "Station done"),
+            AuditAction::ItemStatus         => write!(f, "Item status"),
+            AuditAction::MoveOrder          => write!(f, "Move order"),
+            AuditAction::MergeTables        => write!(f, "Merge tables"),
+            AuditAction::AcceptOrder        => write!(f, "Accept order"),
+            AuditAction::DenyOrder          => write!(f, "Deny order"),
+            AuditAction::AddVirtualTable    => write!(f, "Add table"),
+            AuditAction::DeleteVirtualTable => write!(f, "Delete table"),
+            AuditAction::OpenTable          => write!(f, "Open table"),
+            AuditAction::CloseTable         => write!(f, "Close table"),
+            AuditAction::RotateKey          => write!(f, "Rotate QR key"),
+            AuditAction::AddOffer           => write!(f, "Add offer"),
+            AuditAction::DeleteOffer        => write!(f, "Delete offer"),
+        }
+    }
+
+}
+
+/// One line of `audit.log`.
+///
+/// Built where the request is sent and written once the
+/// server accepted it, so the log only holds real changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// RFC 3339 local time.
+    pub time:     String,
+    pub username: String,
+    pub action:   AuditAction,
+    /// What was changed, e.g. the table name.
+    pub target:   String,
+    pub before:   Option<serde_json::Value>,
+    pub after:    Option<serde_json::Value>,
+}
+
+impl AuditEntry {
+
+    pub fn new<T: Serialize>(username: &str, action: AuditAction, target: &str, before: Option<&T>, after: Option<&T>) -> Self {
+        Self {
+            time: chrono::Local::now().to_rfc3339(),
+            username: username.to_string(),
+            action,
+            target: target.to_string(),
+            before: before.and_then(|value| serde_json::to_value(value).ok()),
+            after: after.and_then(|value| serde_json::to_value(value).ok()),
+        }
+    }
+
+    /// Appends the entry to `audit.log` in the data directory.
+    pub fn commit(&self) {
+        log::info!("{}: `{}` {}", self.action, self.username, self.target);
+        if let Err(err) = crate::storage::append_json_line(&crate::storage::data_dir().join(LOG_FILE), self) {
+            log::error!("{}", err);
+        }
+    }
+
+}
+
+/// All entries of `audit.log`, oldest first.
+pub fn read_log() -> Result<Vec<AuditEntry>, crate::error::Error> {
+    crate::storage::read_json_lines(&crate::storage::data_dir().join(LOG_FILE))
+}
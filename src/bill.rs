@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::error::{self, error, function_message, ErrorKind};
+use crate::shared::dbt;
+
+/// Folder in the data directory the bills are exported to.
+const BILLS_FOLDER: &str = "bills";
+
+/// One offer of the bill, over all orders.
+#[derive(Debug, Clone)]
+pub struct BillLine {
+    pub offer: dbt::OfferID,
+    pub count: u32,
+    /// Prices in cents.
+    pub unit:  u64,
+    pub total: u64,
+}
+
+/// What a table owes over all its unpaid orders.
+#[derive(Debug, Clone)]
+pub struct Bill {
+    pub table:    dbt::VirtualTableID,
+    pub orders:   usize,
+    pub lines:    Vec<BillLine>,
+    /// Ordered items whose offer no longer exists, without a price.
+    pub unknown:  Vec<dbt::OfferID>,
+    pub tax:      crate::user::Tax,
+    /// In cents, `subtotal + tax_amount == total`.
+    pub subtotal: u64,
+    pub tax_amount: u64,
+    pub total:    u64,
+}
+
+/// `1234` as `12.34`.
+pub fn money(cents: u64) -> String {
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+impl Bill {
+
+    pub fn new(table: &str, orders: &[dbt::Order], offers: &[dbt::Offer], tax: crate::user::Tax) -> Self {
+
+        let prices: HashMap<&str, u64> = offers
+            .iter()
+            .map(|offer| (offer.name.as_str(), offer.price_integer as u64 * 100 + offer.price_fraction as u64))
+            .collect();
+
+        let unpaid: Vec<&dbt::Order> = orders.iter().filter(|order| !order.paid).collect();
+
+        // In the order the offers were first ordered.
+        let mut lines: Vec<BillLine> = Vec::new();
+        let mut unknown: Vec<dbt::OfferID> = Vec::new();
+        for item in unpaid.iter().flat_map(|order| order.items.iter()) {
+            let Some(unit) = prices.get(item.id.as_str()) else {
+                if !unknown.contains(&item.id) {
+                    unknown.push(item.id.clone());
+                }
+                continue
+            };
+            match lines.iter_mut().find(|line| line.offer == item.id) {
+                Some(line) => line.count = line.count.saturating_add(item.count),
+                None => lines.push(BillLine { offer: item.id.clone(), count: item.count, unit: *unit, total: 0 }),
+            }
+        }
+        for line in lines.iter_mut() {
+            line.total = line.unit.saturating_mul(line.count as u64);
+        }
+
+        // Rounded half up, in u128 so no realistic bill overflows.
+        let sum = lines.iter().fold(0u64, |sum, line| sum.saturating_add(line.total));
+        let percent = tax.percent as u128;
+        let (subtotal, tax_amount) = match tax.included {
+            true => {
+                let net = ((sum as u128 * 100 * 2 + (100 + percent)) / ((100 + percent) * 2)) as u64;
+                (net, sum - net)
+            },
+            false => (sum, u64::try_from((sum as u128 * percent + 50) / 100).unwrap_or(u64::MAX)),
+        };
+
+        Self {
+            table: table.to_string(),
+            orders: unpaid.len(),
+            lines,
+            unknown,
+            tax,
+            subtotal,
+            tax_amount,
+            total: subtotal.saturating_add(tax_amount),
+        }
+
+    }
+
+    /// The bill as plain text, for printing and exporting.
+    pub fn to_text(&self) -> String {
+
+        let mut text = format!(
+            "Table {}\n{}\n{} orders\n\n",
+            self.table,
+            chrono::Local::now().format("%Y-%m-%d %H:%M"),
+            self.orders
+        );
+
+        for line in self.lines.iter() {
+            text += &format!("{:<24} {:>4} x {:>8} {:>10}\n", line.offer, line.count, money(line.unit), money(line.total));
+        }
+        for offer in self.unknown.iter() {
+            text += &format!("{:<24} (no longer offered, not billed)\n", offer);
+        }
+
+        text += &format!(
+            "\n{:<39} {:>10}\n{:<39} {:>10}\n{:<39} {:>10}\n",
+            "Subtotal", money(self.subtotal),
+            format!("Tax {} %", self.tax.percent), money(self.tax_amount),
+            "Total", money(self.total)
+        );
+        text
+
+    }
+
+}
+
+/// Writes `bill` to the bills folder of the data directory.
+pub fn export(bill: &Bill) -> Result<PathBuf, error::Error> {
+
+    let folder = crate::storage::data_dir().join(BILLS_FOLDER);
+    let file = folder.join(format!(
+        "{}-{}.txt",
+        bill.table.replace(|c: char| !c.is_alphanumeric() && c != '-', "_"),
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+
+    match std::fs::create_dir_all(&folder).and_then(|_| crate::storage::write_atomic(&file, &bill.to_text())) {
+        Ok(()) => Ok(file),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("storage::write_atomic", err.to_string())
+        )
+    }
+
+}
+
+/// Exports `bill` and sends it to the default printer.
+pub fn print(bill: &Bill) -> Result<PathBuf, error::Error> {
+
+    let file = export(bill)?;
+
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut command = Command::new("notepad");
+        command.arg("/p");
+        command
+    };
+    #[cfg(not(target_os = "windows"))]
+    let mut command = Command::new("lp");
+
+    match command.arg(&file).status() {
+        Ok(status) if status.success() => Ok(file),
+        Ok(status) => error!(
+            kind: ErrorKind::Storage,
+            name: "Printing the bill failed",
+            message: format!("the print command exited with {}, the bill was saved to `{}`", status, file.display())
+        ),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: "Cannot print the bill",
+            message: format!("{}, the bill was saved to `{}`", function_message!("Command::status", err.to_string()), file.display())
+        ),
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::user::Tax;
+
+    fn offer(name: &str, price_integer: u32, price_fraction: u32) -> dbt::Offer {
+        dbt::Offer { name: name.to_string(), price_integer, price_fraction, ..Default::default() }
+    }
+
+    fn order(items: &[(&str, u32)], paid: bool) -> dbt::Order {
+        dbt::Order {
+            items: items
+                .iter()
+                .map(|(id, count)| dbt::OrderItem { id: id.to_string(), count: *count, ..Default::default() })
+                .collect(),
+            paid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lines_add_up_the_unpaid_orders() {
+        let offers = [offer("Beer", 3, 50), offer("Soup", 5, 0)];
+        let orders = [
+            order(&[("Beer", 2), ("Soup", 1)], false),
+            order(&[("Beer", 1), ("Gone", 1)], false),
+            order(&[("Soup", 4)], true),
+        ];
+        let bill = Bill::new("T1", &orders, &offers, Tax { percent: 0, included: false });
+        assert_eq!(bill.orders, 2);
+        let lines: Vec<_> = bill.lines.iter().map(|line| (line.offer.as_str(), line.count, line.total)).collect();
+        assert_eq!(lines, [("Beer", 3, 1050), ("Soup", 1, 500)]);
+        assert_eq!(bill.unknown, ["Gone"]);
+        assert_eq!(bill.total, 1550);
+    }
+
+    #[test]
+    fn tax_on_top_is_rounded_half_up() {
+        let offers = [offer("Beer", 3, 50), offer("Soup", 5, 0)];
+        let orders = [order(&[("Beer", 3), ("Soup", 1)], false)];
+        // 19 % of 15.50 is 2.945.
+        let bill = Bill::new("T1", &orders, &offers, Tax { percent: 19, included: false });
+        assert_eq!((bill.subtotal, bill.tax_amount, bill.total), (1550, 295, 1845));
+    }
+
+    #[test]
+    fn included_tax_is_taken_out_of_the_sum() {
+        let offers = [offer("Menu", 10, 0), offer("Water", 1, 90)];
+        let bill = Bill::new("T1", &[order(&[("Menu", 1)], false)], &offers, Tax { percent: 19, included: true });
+        // 10.00 / 1.19 is 8.4033.
+        assert_eq!((bill.subtotal, bill.tax_amount, bill.total), (840, 160, 1000));
+        let bill = Bill::new("T1", &[order(&[("Menu", 1), ("Water", 1)], false)], &offers, Tax { percent: 19, included: true });
+        assert_eq!((bill.subtotal, bill.tax_amount, bill.total), (1000, 190, 1190));
+    }
+
+    #[test]
+    fn huge_bills_do_not_overflow() {
+        let offers = [offer("Gold", u32::MAX, 99)];
+        let orders = [order(&[("Gold", u32::MAX)], false), order(&[("Gold", 1)], false)];
+        let bill = Bill::new("T1", &orders, &offers, Tax { percent: 19, included: false });
+        assert_eq!(bill.lines[0].count, u32::MAX);
+        assert_eq!(bill.total, u64::MAX);
+    }
+
+}
@@ -0,0 +1,90 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind};
+
+/// Folder in the server directory holding the cached server data.
+const CACHE_FOLDER: &str = "cache";
+
+pub const OFFERS: &str = "offers";
+pub const TABLES: &str = "tables";
+/// Open orders by table.
+pub const ORDERS: &str = "orders";
+
+/// Server data as it was last fetched.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot<T> {
+    /// RFC 3339 local time.
+    pub saved: String,
+    pub data:  T,
+}
+
+fn folder() -> std::path::PathBuf {
+    crate::storage::server_dir().join(CACHE_FOLDER)
+}
+
+fn file(name: &str) -> std::path::PathBuf {
+    folder().join(format!("{}.json", name))
+}
+
+/// Saves `data` as the last known state of `name`.
+pub fn store<T: Serialize>(name: &str, data: &T) {
+    write(name, &Snapshot { saved: chrono::Local::now().to_rfc3339(), data });
+}
+
+fn write<T: Serialize>(name: &str, snapshot: &Snapshot<T>) {
+
+    let raw = match serde_json::to_string(snapshot) {
+        Ok(raw) => raw,
+        Err(err) => return log::error!("Failed to serialize the `{}` cache: {}", name, err)
+    };
+
+    let file = file(name);
+    let result = std::fs::create_dir_all(folder())
+        .and_then(|_| crate::storage::write_atomic(&file, &raw));
+
+    if let Err(err) = result {
+        log::error!("Failed to write `{}`: {}", file.display(), err);
+    }
+
+}
+
+pub fn load<T: DeserializeOwned>(name: &str) -> Option<Snapshot<T>> {
+
+    let file = file(name);
+
+    let raw = std::fs::read_to_string(&file).ok()?;
+
+    match serde_json::from_str(&raw) {
+        Ok(snapshot) => Some(snapshot),
+        Err(err) => {
+            log::error!("Ignoring the invalid cache `{}`: {}", file.display(), err);
+            None
+        }
+    }
+
+}
+
+/// Caches a successful fetch. On a network error the cached
+/// data is returned instead, with the time it was saved.
+pub fn fallback<T: Serialize + DeserializeOwned>(name: &str, result: Result<T, Error>) -> (Result<T, Error>, Option<String>) {
+    match result {
+        Ok(data) => {
+            store(name, &data);
+            (Ok(data), None)
+        },
+        Err(err) if err.kind == ErrorKind::Network => match load::<T>(name) {
+            Some(snapshot) => (Ok(snapshot.data), Some(snapshot.saved)),
+            None => (Err(err), None)
+        },
+        Err(err) => (Err(err), None)
+    }
+}
+
+/// Changes the cached data of `name` in place, e.g. after
+/// queueing a change while offline.
+pub fn modify<T: Serialize + DeserializeOwned>(name: &str, change: impl FnOnce(&mut T)) {
+    if let Some(mut snapshot) = load::<T>(name) {
+        change(&mut snapshot.data);
+        write(name, &snapshot);
+    }
+}
@@ -10,7 +10,7 @@ macro_rules! function {
 }
 pub(crate) use function;
 
-#[derive(Default)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct Position {
 
     pub line: u32,
@@ -36,7 +36,47 @@ macro_rules! file_position {
 }
 pub(crate) use file_position;
 
+/// What part of the app failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Connecting to or talking with the server failed.
+    Network,
+    /// The server answered with invalid HTTP or an error status.
+    Protocol,
+    /// Data could not be serialized or deserialized.
+    Decode,
+    /// Reading or writing local files failed.
+    Storage,
+    /// Wrong credentials or a lockout.
+    Auth,
+    /// The user typed something invalid.
+    Input,
+    /// Someone else changed the data first.
+    Conflict,
+    /// Nothing was fetched yet, the message says why.
+    Pending,
+}
+
+impl std::fmt::Display for ErrorKind {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Network  => write!(f, "Network"),
+            ErrorKind::Protocol => write!(f, "Protocol"),
+            ErrorKind::Decode   => write!(f, "Decode"),
+            ErrorKind::Storage  => write!(f, "Storage"),
+            ErrorKind::Auth     => write!(f, "Auth"),
+            ErrorKind::Input    => write!(f, "Input"),
+            ErrorKind::Conflict => write!(f, "Conflict"),
+            ErrorKind::Pending  => write!(f, "Pending"),
+        }
+    }
+
+}
+
+#[derive(Debug, Clone)]
 pub struct Error {
+    pub kind:    ErrorKind,
     pub place:   Vec<(&'static str, Position)>,
     pub name:    String,
     pub message: String
@@ -75,8 +115,28 @@ impl Error {
 
     }
 
+    /// Placeholder for data that was not fetched yet.
+    pub fn pending(message: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::Pending,
+            place: Vec::new(),
+            name: String::new(),
+            message: message.into()
+        }
+    }
+
+    /// Short text for the UI, without the place stack.
+    pub fn summary(&self) -> String {
+        match self.name.is_empty() {
+            true => self.message.clone(),
+            false => format!("{}: {}", self.name, self.message)
+        }
+    }
+
 }
 
+impl std::error::Error for Error {}
+
 impl std::fmt::Display for Error {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -105,7 +165,7 @@ impl std::fmt::Display for Error {
             accumulate.push_str(format!("Called by `{}` at {}\n", func, pos).as_str());
         }
 
-        accumulate.push_str(format!("{}!\n", self.name).as_str());
+        accumulate.push_str(format!("{} error: {}!\n", self.kind, self.name).as_str());
 
         accumulate.push_str(format!("{}.\n", self.message).as_str());
 
@@ -1,9 +1,12 @@
-use std::{default, path::PathBuf};
-
 use iced::{
     alignment::{Horizontal, Vertical}, theme, widget::{button, center, column, container, text, text_input}, window, Alignment::{self, Center}, Element, Length, Renderer, Task, Theme
 };
 
+use std::time::Duration;
+
+use crate::error::{self, error, error_forward, function_message, ErrorKind};
+use crate::security::{self, LoginGuard, SecurityEventKind};
+use crate::ui::settings::Settings;
 use crate::user::UserEntry;
 use crate::Message;
 
@@ -11,12 +14,42 @@ use crate::Message;
 pub struct Login {
 
     state:    LoginState,
+    mode:     LoginMode,
     entry:    UserEntry,
     password: String,
+    pin:      String,
     issue:    String,
+    /// Users that can log in with a PIN.
+    pin_users: Vec<String>,
+    /// Bumped on every login attempt, results of older
+    /// attempts are ignored.
+    attempt:  u64,
+    /// Aborts the running login task.
+    abort:    Option<iced::task::Handle>,
+
+    /// Data and settings of the user once logged in.
+    pub logged_in: Option<Box<Settings>>
+
+}
 
-    pub found_entry: Option<UserEntry>
+/// How long a login may take before it is given up.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything a login attempt needs, moved to the background task.
+#[derive(Debug, Clone)]
+struct LoginRequest {
+    mode:     LoginMode,
+    entry:    UserEntry,
+    password: String,
+    pin:      String,
+}
 
+#[derive(Debug, Clone)]
+pub enum LoginFailure {
+    /// Wrong or unknown credentials, counted by the `LoginGuard`.
+    Credentials(error::Error),
+    TimedOut,
+    Internal(String),
 }
 
 #[derive(Debug, Clone, Default)]
@@ -26,11 +59,25 @@ enum LoginState {
     AwaitingServer, // Waiting for a response from the server.
 }
 
+/// How the user proves who they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum LoginMode {
+    #[default]
+    Password,
+    Pin,        // Quick switch between users with a short PIN.
+}
+
 #[derive(Debug, Clone)]
 pub enum LoginMessage {
     UsernameFieldChanged(String),
     PasswordFieldChanged(String),
+    PinFieldChanged(String),
+    PinUserSelected(String),
+    QuickSwitchPressed,
+    UsePasswordPressed,
     LoginButtonPressed,
+    CancelButtonPressed,
+    LoginFinished(u64, Result<Box<Settings>, LoginFailure>),
 }
 
 impl Into<crate::Message> for LoginMessage {
@@ -39,38 +86,108 @@ impl Into<crate::Message> for LoginMessage {
     }
 }
 
-impl Login {
-    pub fn set_username(&mut self, username: String) {
-        self.entry.username = username;
-    }
-
-    pub fn set_password(&mut self, password: String) {
-        self.password = password;
-    }
+impl LoginRequest {
 
-    pub fn find_match_and_login(&mut self) -> Result<UserEntry, String> {
+    fn find_match_and_login(mut self) -> Result<UserEntry, error::Error> {
 
         let found_entry = match Login::fetch_username_from_database(&self.entry.username) {
             Ok(entry) => entry,
-            Err(err) => return Err(err)
+            Err(err) => return Err(error_forward!(err))
         };
 
+        if self.mode == LoginMode::Pin {
+            return match found_entry.pin_matches(&self.pin) {
+                true => Ok(found_entry),
+                false => error!(
+                    kind: ErrorKind::Auth,
+                    name: "Wrong PIN",
+                    message: format!("the PIN of `{}` does not match", found_entry.username)
+                )
+            }
+        }
+
         self.entry.calculate(self.password.clone(), found_entry.salt.clone());
 
         if found_entry.is_equal(&self.entry) {
-            return Ok(self.entry.clone())
+            Ok(self.entry)
         } else {
-            return Err(format!("Passwords do not match!"))
+            error!(
+                kind: ErrorKind::Auth,
+                name: "Passwords do not match",
+                message: format!("the password of `{}` does not match", self.entry.username)
+            )
         }
 
     }
 
-    pub fn fetch_username_from_database(username: &str) -> Result<UserEntry, String> {
+    /// Checks the credentials and loads the user data and
+    /// settings. Blocking, runs on a worker thread.
+    fn run(self) -> Result<Box<Settings>, LoginFailure> {
+
+        let entry = match self.find_match_and_login() {
+            Ok(entry) => entry,
+            Err(err) => return Err(LoginFailure::Credentials(err))
+        };
+
+        let settings = match Settings::from_database(&entry) {
+            Ok(settings) => settings,
+            Err(err) => {
+                log::error!("{}", err);
+                Settings::fallback(&entry, &err)
+            }
+        };
+
+        Ok(Box::new(settings))
+
+    }
+
+}
+
+impl Login {
 
-        // for now just my users directory :p
-        let file = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("users")
-            .join(format!("{username}.toml"));
+    /// Login page in PIN mode, used when switching users or
+    /// after the idle auto-lock. `username` is preselected.
+    pub fn quick_switch(username: Option<String>) -> Self {
+        let mut login = Self::default();
+        login.enter_pin_mode();
+        if let Some(username) = username {
+            if login.pin_users.contains(&username) {
+                login.entry.username = username;
+            }
+        }
+        login
+    }
+
+    fn enter_pin_mode(&mut self) {
+        self.mode = LoginMode::Pin;
+        self.issue = String::new();
+        self.pin = String::new();
+        self.pin_users = crate::storage::usernames()
+            .into_iter()
+            .filter(|username| Login::fetch_username_from_database(username)
+                .is_ok_and(|entry| entry.pin.is_some()))
+            .collect();
+        if !self.pin_users.contains(&self.entry.username) {
+            self.entry.username = String::new();
+        }
+    }
+
+    pub fn set_username(&mut self, username: String) {
+        self.entry.username = username;
+    }
+
+    pub fn set_password(&mut self, password: String) {
+        self.password = password;
+    }
+
+    /// Shows `issue` under the login form, e.g. a startup error.
+    pub fn set_issue(&mut self, issue: String) {
+        self.issue = issue;
+    }
+
+    pub fn fetch_username_from_database(username: &str) -> Result<UserEntry, error::Error> {
+
+        let file = crate::storage::user_entry_file(username);
 
         match file.exists() {
             true => match std::fs::read_to_string(file) {
@@ -78,32 +195,116 @@ impl Login {
                     Ok(found_entry) => Ok(UserEntry { 
                         username: username.to_owned(), 
                         md5: found_entry.md5, 
-                        salt: found_entry.salt 
+                        salt: found_entry.salt,
+                        pin: found_entry.pin
                     }),
-                    Err(err) => Err(format!("User '{username}' contains invalid data!\n{}", err.message()))
+                    Err(err) => error!(
+                        kind: ErrorKind::Decode,
+                        name: format!("User '{username}' contains invalid data"),
+                        message: function_message!("toml::from_str", err.message())
+                    )
                 },
-                Err(_) => Err(format!("Failed to read '{username}.toml'."))
+                Err(err) => error!(
+                    kind: ErrorKind::Storage,
+                    name: format!("Failed to read '{username}.toml'"),
+                    message: function_message!("std::fs::read_to_string", err.to_string())
+                )
             },
-            false => Err(format!("User '{username}' does not exist."))
+            false => error!(
+                kind: ErrorKind::Auth,
+                name: format!("User '{username}' does not exist"),
+                message: "no matching user entry was found"
+            )
         } 
         
 
 
     }
 
-    pub fn update(&mut self, message: LoginMessage) -> Task<Message> {
+    pub fn update(&mut self, message: LoginMessage, guard: &mut LoginGuard) -> Task<Message> {
         match message {
             LoginMessage::UsernameFieldChanged(username) => self.set_username(username),
             LoginMessage::PasswordFieldChanged(password) => self.set_password(password),
+            LoginMessage::PinFieldChanged(pin) => {
+                if pin.chars().all(|c| c.is_ascii_digit()) {
+                    self.pin = pin
+                }
+            },
+            LoginMessage::PinUserSelected(username) => self.set_username(username),
+            LoginMessage::QuickSwitchPressed => self.enter_pin_mode(),
+            LoginMessage::UsePasswordPressed => {
+                self.mode = LoginMode::Password;
+                self.issue = String::new();
+                self.pin = String::new();
+            },
             LoginMessage::LoginButtonPressed => {
                 self.issue = String::new();
+                let username = self.entry.username.clone();
+                if let Err(err) = guard.check(&username) {
+                    security::log_event(SecurityEventKind::LoginWhileLocked, &username, &err.message);
+                    self.issue = err.summary();
+                    return Task::none()
+                }
                 self.state = LoginState::AwaitingServer;
-                match self.find_match_and_login() {
-                    Ok(found_entry) => self.found_entry = Some(found_entry),
-                    Err(err) => {
-                        self.issue = err;
-                        self.state = LoginState::AwaitingUser;
-                    }
+                self.attempt += 1;
+
+                let attempt = self.attempt;
+                let request = LoginRequest {
+                    mode: self.mode,
+                    entry: self.entry.clone(),
+                    password: self.password.clone(),
+                    pin: self.pin.clone(),
+                };
+
+                let (task, handle) = Task::perform(
+                    async move {
+                        match tokio::time::timeout(
+                            LOGIN_TIMEOUT,
+                            tokio::task::spawn_blocking(move || request.run())
+                        ).await {
+                            Ok(Ok(result)) => result,
+                            Ok(Err(err)) => Err(LoginFailure::Internal(err.to_string())),
+                            Err(_) => Err(LoginFailure::TimedOut)
+                        }
+                    },
+                    move |result| LoginMessage::LoginFinished(attempt, result).into()
+                ).abortable();
+
+                self.abort = Some(handle);
+                return task
+            },
+            LoginMessage::CancelButtonPressed => {
+                if let Some(handle) = self.abort.take() {
+                    handle.abort();
+                }
+                self.attempt += 1;
+                self.state = LoginState::AwaitingUser;
+                self.issue = "Login cancelled.".to_string();
+            },
+            LoginMessage::LoginFinished(attempt, result) => {
+                if attempt != self.attempt {
+                    log::info!("Ignoring the result of login attempt {}.", attempt);
+                    return Task::none()
+                }
+                self.abort = None;
+                self.state = LoginState::AwaitingUser;
+                let username = self.entry.username.clone();
+                match result {
+                    Ok(settings) => {
+                        guard.record_success(&username);
+                        self.logged_in = Some(settings);
+                    },
+                    Err(LoginFailure::Credentials(err)) => {
+                        guard.record_failure(&username, &err.summary());
+                        self.issue = security::GENERIC_LOGIN_ERROR.to_string();
+                    },
+                    Err(LoginFailure::TimedOut) => {
+                        self.issue = "The login took too long, please try again.".to_string();
+                    },
+                    Err(LoginFailure::Internal(err)) => {
+                        log::error!("Login task failed: {}", err);
+                        self.issue = "Something went wrong, please try again.".to_string();
+                    },
                 }
             },
         }
@@ -112,25 +313,68 @@ impl Login {
 
     pub fn view(&self) -> Element<Message> {
         
+        let credentials: iced::widget::Column<'_, Message, Theme, Renderer> = match self.mode {
+            LoginMode::Password => column![
+                text("Enter your credentials:")
+                    .align_x(Horizontal::Center)
+                    .width(Length::Fill),
+                text_input("Username...", &self.entry.username)
+                    .size(20)
+                    .on_input_maybe(match self.state.clone() {
+                        LoginState::AwaitingUser =>
+                            Some(|s| { LoginMessage::UsernameFieldChanged(s).into() }),
+                        LoginState::AwaitingServer => None,
+                    }),
+                text_input("Password...", &self.password)
+                    .size(20)
+                    .secure(true)
+                    .on_input_maybe(match self.state.clone() {
+                        LoginState::AwaitingUser =>
+                            Some(|s| { LoginMessage::PasswordFieldChanged(s).into() }),
+                        LoginState::AwaitingServer => None,
+                    }),
+            ],
+            LoginMode::Pin => {
+                let users = self.pin_users.iter().fold(
+                    column![].spacing(5),
+                    |users, username| users.push(
+                        button(text(username.clone()))
+                            .width(Length::Fill)
+                            .style(if *username == self.entry.username {
+                                button::primary
+                            } else {
+                                button::secondary
+                            })
+                            .on_press_maybe(match self.state.clone() {
+                                LoginState::AwaitingUser =>
+                                    Some(LoginMessage::PinUserSelected(username.clone()).into()),
+                                LoginState::AwaitingServer => None,
+                            })
+                    )
+                );
+                column![
+                    text(if self.pin_users.is_empty() {
+                        "Nobody has a PIN yet, set one in the settings tab."
+                    } else {
+                        "Pick your user and enter your PIN:"
+                    })
+                        .align_x(Horizontal::Center)
+                        .width(Length::Fill),
+                    users,
+                    text_input("PIN...", &self.pin)
+                        .size(20)
+                        .secure(true)
+                        .on_input_maybe(match self.state.clone() {
+                            LoginState::AwaitingUser =>
+                                Some(|s| { LoginMessage::PinFieldChanged(s).into() }),
+                            LoginState::AwaitingServer => None,
+                        }),
+                ]
+            }
+        }.spacing(20);
+
         let col: iced::widget::Column<'_, Message, Theme, Renderer> = column![
-            text("Enter your credentials:")
-                .align_x(Horizontal::Center)
-                .width(Length::Fill),
-            text_input("Username...", &self.entry.username)
-                .size(20)
-                .on_input_maybe(match self.state.clone() {
-                    LoginState::AwaitingUser =>
-                        Some(|s| { LoginMessage::UsernameFieldChanged(s).into() }),
-                    LoginState::AwaitingServer => None,
-                }),
-            text_input("Password...", &self.password)
-                .size(20)
-                .secure(true)
-                .on_input_maybe(match self.state.clone() {
-                    LoginState::AwaitingUser =>
-                        Some(|s| { LoginMessage::PasswordFieldChanged(s).into() }),
-                    LoginState::AwaitingServer => None,
-                }),
+            credentials
         ].push_maybe(match self.state.clone() {
             LoginState::AwaitingUser => Some(
                 button("Login")
@@ -142,6 +386,13 @@ impl Login {
                 text("Please wait while we fetch your data...")),
             _ => None
         })
+        .push_maybe(match self.state.clone() {
+            LoginState::AwaitingServer => Some(
+                button("Cancel")
+                    .style(button::secondary)
+                    .on_press(LoginMessage::CancelButtonPressed.into())),
+            _ => None
+        })
         .push_maybe(match self.state.clone() {
             LoginState::AwaitingUser => match self.issue.is_empty() {
                 false => Some(text(format!("{}", self.issue))),
@@ -149,6 +400,27 @@ impl Login {
             },
             _ => None
         })
+        .push_maybe(match self.state.clone() {
+            LoginState::AwaitingUser => Some(match self.mode {
+                LoginMode::Password => button("Quick switch with a PIN")
+                    .style(button::text)
+                    .on_press(LoginMessage::QuickSwitchPressed.into()),
+                LoginMode::Pin => button("Log in with a password")
+                    .style(button::text)
+                    .on_press(LoginMessage::UsePasswordPressed.into()),
+            }),
+            _ => None
+        })
+        .push_maybe(match self.state.clone() {
+            LoginState::AwaitingUser => Some(
+                button(text(match crate::shared::discovery::selected() {
+                    Some(server) => format!("Server: {} (change)", server.name),
+                    None => "Pick a server".to_string()
+                }))
+                    .style(button::text)
+                    .on_press(Message::ChooseServer)),
+            _ => None
+        })
         .padding(20)
         .spacing(20)
         .align_x(Horizontal::Center);
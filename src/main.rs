@@ -4,16 +4,27 @@ mod table;
 mod user;
 mod error;
 mod shared;
+mod storage;
+mod security;
+mod audit;
+mod cache;
+mod outbox;
+mod servers;
+mod stations;
+mod tls;
+mod alerts;
+mod bill;
 
-use std::process::exit;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use clap::{command, arg};
+use clap::{command, arg, value_parser};
 use iced::{Element, Task, Theme};
 
 const ICON_BYTES: &[u8] = include_bytes!("../fonts/icomoon.ttf");
 
-const EXIT_FAILURE: i32 = 1;
-const EXIT_SUCCESS: i32 = 0;
+/// How often the idle auto-lock is checked.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
 
 use crate::login::{
     LoginMessage, 
@@ -24,7 +35,9 @@ use crate::ui::{
     UIMessage
 };
 
-use crate::ui::settings::Settings;
+use crate::security::LoginGuard;
+use crate::servers::{ServerPicker, ServerPickerMessage};
+use crate::shared::discovery;
 
 /// Messages from this file or other files.
 /// 
@@ -36,8 +49,19 @@ use crate::ui::settings::Settings;
 /// file or another to decide what to do with the message.
 #[derive(Debug, Clone)]
 enum Message {
+    Servers(ServerPickerMessage),
+    /// Back to the server picker, from the login page.
+    ChooseServer,
     Login(LoginMessage),
-    UI(UIMessage)
+    UI(UIMessage),
+    /// Clear everything and return to the login page.
+    Logout,
+    /// Like `Logout` but opens the PIN quick-login.
+    SwitchUser,
+    /// Mouse or keyboard input, resets the idle auto-lock.
+    UserActivity,
+    /// Periodic check for the idle auto-lock.
+    IdleCheck
 }
 
 /// What part of the UI are we currently on.
@@ -47,6 +71,8 @@ enum Message {
 /// #[default] sets what part of the UI to load first.
 #[derive(Default, PartialEq, Eq)]
 enum Page {
+    /// Shown first while no server was picked yet.
+    Servers,
     #[default]
     Login,
     UI
@@ -55,6 +81,7 @@ enum Page {
 /// The data of the various parts of the UI.
 #[derive(Default)]
 struct Parts {
+    pub servers: ServerPicker,
     pub login: Login,
     pub ui: UI
 }
@@ -66,6 +93,10 @@ struct Parts {
 struct App {
     pub page:  Page,
     pub part:  Parts,
+    /// Last time the user touched the mouse or keyboard.
+    pub last_activity: Option<Instant>,
+    /// Failed login counters, survives logging out.
+    pub guard: LoginGuard,
 }
 
 impl App {
@@ -74,34 +105,84 @@ impl App {
         Self {
             part:  Parts::default(),
             page:   Page::default(),
+            last_activity: None,
+            guard: LoginGuard::load(),
         }
     }
 
+    /// Drops all the data of the current user and shows `login`.
+    fn log_out(&mut self, login: Login) {
+        self.part = Parts {
+            login,
+            ..Parts::default()
+        };
+        self.page = Page::Login;
+        self.last_activity = None;
+    }
+
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
+            Message::Servers(servers_message) => {
+                let task = self.part.servers.update(servers_message);
+                if self.part.servers.picked {
+                    self.part.servers.picked = false;
+                    self.page = Page::Login;
+                }
+                return task;
+            }
+
+            Message::ChooseServer => {
+                self.page = Page::Servers;
+                return self.part.servers.search();
+            }
+
             Message::Login(login_message) => {
-                let task = Login::update(&mut self.part.login, login_message);
-                if self.part.login.found_entry.is_some() {
+                let task = Login::update(&mut self.part.login, login_message, &mut self.guard);
+                if let Some(settings) = self.part.login.logged_in.take() {
                     self.page = Page::UI;
-                    let user_entry = self.part.login.found_entry.clone().unwrap();
-                    self.part.ui.settings = match Settings::from_database(&user_entry) {
-                        Ok(user_data_settings) => user_data_settings,
-                        Err(err) => {
-                            eprintln!("{}", err);
-                            exit(EXIT_FAILURE);
-                        }
-                    };
+                    self.last_activity = Some(Instant::now());
+                    self.part.ui.settings = *settings;
+                    self.part.ui.apply_settings();
+                    if self.part.ui.settings.user_settings.start_mode == crate::user::SettingStartMode::KitchenDisplay {
+                        return Task::batch([
+                            task,
+                            Task::done(UIMessage::TabSelected(crate::ui::UITabID::Kitchen).into()),
+                        ]);
+                    }
                 }
                 return task;
             }
 
             Message::UI(ui_message) => return UI::update(&mut self.part.ui, ui_message),
+
+            Message::Logout => self.log_out(Login::default()),
+
+            Message::SwitchUser => {
+                let username = self.part.ui.settings.user_data.username.clone();
+                self.log_out(Login::quick_switch(Some(username)));
+            }
+
+            Message::UserActivity => self.last_activity = Some(Instant::now()),
+
+            Message::IdleCheck => {
+                let minutes = self.part.ui.settings.user_settings.auto_lock_minutes;
+                if self.page == Page::UI
+                && minutes != 0
+                && self.last_activity.is_some_and(|last| last.elapsed() >= Duration::from_secs(minutes as u64 * 60)) {
+                    log::info!("Locked after {} idle minutes.", minutes);
+                    let username = self.part.ui.settings.user_data.username.clone();
+                    self.log_out(Login::quick_switch(Some(username)));
+                }
+            }
         }
+
+        Task::none()
     }
 
     fn view(&self) -> Element<Message> {
 
         match self.page {
+            Page::Servers => return self.part.servers.view(),
             Page::Login  => return Login::view(&self.part.login).into(),
             Page::UI     => return    UI::view(&self.part.ui).into()
         };
@@ -113,17 +194,71 @@ impl App {
     }
 
     fn subscription(&self) -> iced::Subscription<Message> {
-        // Combine subscriptions if there are multiple tabs
-        if self.page == Page::UI
-        && self.part.ui.active_tab == crate::ui::UITabID::Orders {
-            return self.part.ui.orders.subscription()
-        } else {
+        if self.page != Page::UI {
             return iced::Subscription::none()
         }
+
+        let mut subscriptions = vec![];
+
+        // Combine subscriptions if there are multiple tabs.
+        // While offline the health check takes over, the data is
+        // refetched once the server is back. Polling is only needed
+        // while the live sync is not following the server's events.
+        if self.part.ui.active_tab == crate::ui::UITabID::Orders
+        && !self.part.ui.connection.is_offline()
+        && !self.part.ui.sync.is_live() {
+            subscriptions.push(self.part.ui.orders.subscription());
+        }
+
+        if self.part.ui.active_tab == crate::ui::UITabID::Orders {
+            subscriptions.push(self.part.ui.orders.clock());
+        }
+
+        if self.part.ui.active_tab == crate::ui::UITabID::Kitchen {
+            subscriptions.push(self.part.ui.kitchen.subscription(
+                self.part.ui.sync.is_live() || self.part.ui.connection.is_offline()
+            ));
+        }
+
+        if !self.part.ui.sync.is_running() && !self.part.ui.connection.is_offline() {
+            subscriptions.push(iced::time::every(crate::ui::sync::RETRY_INTERVAL)
+                .map(|_| crate::ui::sync::SyncMessage::Follow.into()));
+        }
+
+        subscriptions.push(iced::time::every(self.part.ui.connection.interval())
+            .map(|_| crate::ui::connection::ConnectionMessage::CheckNow.into()));
+
+        if self.part.ui.notifications.has_toasts() || self.part.ui.notifications.is_flashing() {
+            subscriptions.push(iced::time::every(crate::ui::notify::TICK_INTERVAL)
+                .map(|_| crate::ui::notify::NotificationMessage::Tick.into()));
+        }
+
+        if self.part.ui.notifications.is_flashing() {
+            subscriptions.push(iced::event::listen_with(|event, _status, _window| match event {
+                iced::Event::Window(iced::window::Event::Focused) => Some(crate::ui::notify::NotificationMessage::Attended.into()),
+                _ => None
+            }));
+        }
+
+        // Recorded while auto-lock is off too, turning it on
+        // must not lock out the user who just did so.
+        subscriptions.push(iced::event::listen_with(|event, _status, _window| match event {
+            iced::Event::Mouse(iced::mouse::Event::ButtonPressed(_))     |
+            iced::Event::Mouse(iced::mouse::Event::WheelScrolled { .. }) |
+            iced::Event::Keyboard(iced::keyboard::Event::KeyPressed { .. })
+            => Some(Message::UserActivity),
+            _ => None
+        }));
+
+        if self.part.ui.settings.user_settings.auto_lock_minutes != 0 {
+            subscriptions.push(iced::time::every(IDLE_CHECK_INTERVAL).map(|_| Message::IdleCheck));
+        }
+
+        iced::Subscription::batch(subscriptions)
     }
 
     fn title(&self) -> String {
-        "OBY Desktop".to_string()
+        self.part.ui.notifications.title("OBY Desktop")
     }
 
     // fn title()
@@ -134,35 +269,75 @@ fn main() -> iced::Result {
 
     env_logger::init();
 
+    let mut command = command!()
+        .arg(arg!(data_dir: --"data-dir" <DIR> "Directory holding users and their settings")
+            .value_parser(value_parser!(PathBuf)))
+        .arg(arg!(announce: --announce <NAME> "Answer server discovery as NAME instead of starting, to try the discovery from a second process"))
+        .arg(arg!(tls: --tls "With --announce, announce the server as speaking HTTPS"))
+        .arg(arg!(generate_certificate: --"generate-certificate" <DIR> "Write a self-signed certificate and key for the server into DIR and print its fingerprint")
+            .value_parser(value_parser!(PathBuf)));
+
     if cfg!(debug_assertions) {
+        command = command.arg(arg!(ui: --ui "Debug: Display the UI (skip login)"));
+    }
 
-        let matches = command!()
-            .arg(arg!(ui: --ui "Debug: Display the UI (skip login)"))
-            .get_matches();
-    
-        let app: App = if matches.get_flag("ui") == true {
-            let mut app = App::new();
-            app.page = Page::UI;
-            app
-        } else {
-            App::new()
-        };
+    let matches = command.get_matches();
 
-        iced::application(App::title, App::update, App::view)
-            .theme(App::theme)
-            .centered()
-            .font(ICON_BYTES)
-            .subscription(App::subscription)
-            .run_with(|| (app, Task::none()))
+    if let Some(name) = matches.get_one::<String>("announce") {
+        if let Err(err) = discovery::announce(name, shared::req_resp::DB_PORT, shared::req_resp::HTML_PORT, matches.get_flag("tls")) {
+            log::error!("Failed to announce `{}`: {}", name, err);
+            std::process::exit(1);
+        }
+        return Ok(())
+    }
 
-    } else {
+    if let Some(folder) = matches.get_one::<PathBuf>("generate_certificate") {
+        let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+        names.extend(if_addrs::get_if_addrs()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|interface| !interface.is_loopback())
+            .map(|interface| interface.ip().to_string()));
+        match tls::generate_certificate(folder, names) {
+            Ok(fingerprint) => println!(
+                "Wrote `{}` and `{}` to `{}`.\nSHA-256 fingerprint to pin on the clients:\n{}",
+                tls::CERTIFICATE_FILE, tls::KEY_FILE, folder.display(), fingerprint
+            ),
+            Err(err) => {
+                log::error!("{}", err);
+                eprintln!("{}", err.summary());
+                std::process::exit(1);
+            }
+        }
+        return Ok(())
+    }
+
+    let init = storage::init(matches.get_one::<PathBuf>("data_dir").cloned());
 
-        iced::application(App::title, App::update, App::view)
-            .theme(App::theme)
-            .centered()
-            .font(ICON_BYTES)
-            .subscription(App::subscription)
-            .run_with(|| (App::new(), Task::none()))
+    let mut app = App::new();
+    let mut startup = Task::none();
 
+    // Keep running, the login page tells the user what went wrong.
+    if let Err(err) = init {
+        log::error!("{}", err);
+        app.part.login.set_issue(err.summary());
+    } else {
+        stations::init();
+        if cfg!(debug_assertions) && matches.get_flag("ui") {
+            app.page = Page::UI;
+        } else if let Some(server) = discovery::remembered() {
+            discovery::select(server);
+        } else {
+            app.page = Page::Servers;
+            startup = app.part.servers.search();
+        }
     }
+
+    iced::application(App::title, App::update, App::view)
+        .theme(App::theme)
+        .centered()
+        .font(ICON_BYTES)
+        .subscription(App::subscription)
+        .run_with(|| (app, startup))
+
 }
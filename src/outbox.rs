@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditEntry;
+use crate::error::{self, error, error_forward, function_message, ErrorKind};
+use crate::shared::req_resp::{self, RequestKind};
+
+const OUTBOX_FILE: &str = "outbox.json";
+
+/// A change made while the server was unreachable, sent
+/// again once the connection returns.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedChange {
+    /// Sent as `Idempotency-Key`, the same key as the first
+    /// attempt, so a change that did arrive is not applied twice.
+    pub key:     String,
+    /// RFC 3339 local time.
+    pub queued:  String,
+    pub kind:    RequestKind,
+    pub id:      String,
+    pub payload: Option<serde_json::Value>,
+    pub audit:   AuditEntry,
+}
+
+impl QueuedChange {
+
+    pub fn new(key: String, request: &req_resp::Request, id: String, audit: AuditEntry) -> Self {
+        Self {
+            key,
+            queued: chrono::Local::now().to_rfc3339(),
+            kind: request.kind,
+            id,
+            payload: request.payload.clone(),
+            audit,
+        }
+    }
+
+    /// Appends the change to the end of the queue.
+    pub fn enqueue(self) -> Result<(), error::Error> {
+        enqueue_in(&file(), self)
+    }
+
+    /// Sends the change with its idempotency key.
+    pub async fn send(self) -> Result<serde_json::Value, error::Error> {
+        let mut request = req_resp::Request {
+            kind: self.kind,
+            payload: self.payload
+        };
+        request.send_request_with_key(self.id, Some(self.key)).await
+    }
+
+}
+
+/// What became of a replayed change.
+#[derive(Debug)]
+pub enum Replayed {
+    /// The server applied it.
+    Sent,
+    /// Still offline, the change stays first in the queue.
+    Offline,
+    /// The server refused it, e.g. on a conflict. It is dropped
+    /// so it does not block the changes queued after it.
+    Rejected(error::Error),
+}
+
+fn file() -> std::path::PathBuf {
+    crate::storage::server_dir().join(OUTBOX_FILE)
+}
+
+/// Queued changes, oldest first.
+pub fn load() -> Result<Vec<QueuedChange>, error::Error> {
+    let file = file();
+    // Before the outbox was kept per server there was a single
+    // one, its changes were queued for the server used now.
+    let legacy = crate::storage::data_dir().join(OUTBOX_FILE);
+    if !file.exists() && legacy.exists() {
+        let moved = match file.parent() {
+            Some(folder) => std::fs::create_dir_all(folder).and_then(|_| std::fs::rename(&legacy, &file)),
+            None => Ok(())
+        };
+        if let Err(err) = moved {
+            log::error!("Failed to move `{}` to `{}`: {}", legacy.display(), file.display(), err);
+        }
+    }
+    load_from(&file)
+}
+
+/// The oldest queued change, replayed before any other.
+pub fn next() -> Result<Option<QueuedChange>, error::Error> {
+    load().map(|queue| queue.into_iter().next())
+}
+
+/// Takes `change` out of the queue unless replaying it failed
+/// for lack of a connection.
+pub fn settle(change: &QueuedChange, result: Result<serde_json::Value, error::Error>) -> Result<Replayed, error::Error> {
+    settle_in(&file(), change, result)
+}
+
+fn settle_in(file: &std::path::Path, change: &QueuedChange, result: Result<serde_json::Value, error::Error>) -> Result<Replayed, error::Error> {
+    let replayed = match result {
+        Ok(_) => Replayed::Sent,
+        Err(err) if err.kind == ErrorKind::Network => return Ok(Replayed::Offline),
+        Err(err) => Replayed::Rejected(err)
+    };
+    match remove_from(file, &change.key) {
+        Ok(()) => Ok(replayed),
+        Err(err) => Err(error_forward!(err))
+    }
+}
+
+fn enqueue_in(file: &std::path::Path, change: QueuedChange) -> Result<(), error::Error> {
+    let mut queue = match load_from(file) {
+        Ok(queue) => queue,
+        Err(err) => return Err(error_forward!(err))
+    };
+    log::info!("Queued {} `{}` as `{}`.", change.audit.action, change.audit.target, change.key);
+    queue.push(change);
+    save_to(file, &queue)
+}
+
+/// Drops the change with `key` from the queue, once it was
+/// sent or rejected by the server.
+fn remove_from(file: &std::path::Path, key: &str) -> Result<(), error::Error> {
+    let mut queue = match load_from(file) {
+        Ok(queue) => queue,
+        Err(err) => return Err(error_forward!(err))
+    };
+    queue.retain(|change| change.key != key);
+    save_to(file, &queue)
+}
+
+fn load_from(file: &std::path::Path) -> Result<Vec<QueuedChange>, error::Error> {
+
+    if !file.exists() {
+        return Ok(Vec::new())
+    }
+
+    let raw = match std::fs::read_to_string(file) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to read `{}`", file.display()),
+            message: function_message!("std::fs::read_to_string", err.to_string())
+        )
+    };
+
+    match serde_json::from_str(&raw) {
+        Ok(queue) => Ok(queue),
+        Err(err) => error!(
+            kind: ErrorKind::Decode,
+            name: format!("Failed to parse `{}`", file.display()),
+            message: function_message!("serde_json::from_str", err.to_string())
+        )
+    }
+
+}
+
+fn save_to(file: &std::path::Path, queue: &[QueuedChange]) -> Result<(), error::Error> {
+
+    let raw = match serde_json::to_string_pretty(queue) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the outbox",
+            message: function_message!("serde_json::to_string_pretty", err.to_string())
+        )
+    };
+
+    let written = match file.parent() {
+        Some(folder) => std::fs::create_dir_all(folder).and_then(|_| crate::storage::write_atomic(file, &raw)),
+        None => crate::storage::write_atomic(file, &raw)
+    };
+
+    match written {
+        Ok(()) => Ok(()),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("storage::write_atomic", err.to_string())
+        )
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::audit::{AuditAction, AuditEntry};
+
+    fn queue_file(name: &str) -> std::path::PathBuf {
+        let file = std::env::temp_dir().join(format!("oby-outbox-{}-{}.json", std::process::id(), name));
+        let _ = std::fs::remove_file(&file);
+        file
+    }
+
+    fn change(key: &str) -> QueuedChange {
+        QueuedChange {
+            key: key.to_string(),
+            queued: String::new(),
+            kind: RequestKind::OrdersFinish,
+            id: String::new(),
+            payload: None,
+            audit: AuditEntry::new::<()>("anna", AuditAction::FinishOrder, key, None, None),
+        }
+    }
+
+    fn failure(kind: ErrorKind) -> Result<serde_json::Value, error::Error> {
+        error!(kind: kind, name: "failed", message: "")
+    }
+
+    fn keys(file: &std::path::Path) -> Vec<String> {
+        load_from(file).unwrap().into_iter().map(|change| change.key).collect()
+    }
+
+    #[test]
+    fn changes_are_replayed_in_the_order_they_were_queued() {
+        let file = queue_file("order");
+        for key in ["a", "b", "c"] {
+            enqueue_in(&file, change(key)).unwrap();
+        }
+        assert_eq!(keys(&file), ["a", "b", "c"]);
+
+        assert!(matches!(settle_in(&file, &change("a"), Ok(serde_json::Value::Null)), Ok(Replayed::Sent)));
+        assert_eq!(keys(&file), ["b", "c"]);
+
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn offline_changes_stay_first_in_the_queue() {
+        let file = queue_file("offline");
+        enqueue_in(&file, change("a")).unwrap();
+        enqueue_in(&file, change("b")).unwrap();
+
+        assert!(matches!(settle_in(&file, &change("a"), failure(ErrorKind::Network)), Ok(Replayed::Offline)));
+        assert_eq!(keys(&file), ["a", "b"]);
+
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn rejected_changes_are_dropped() {
+        let file = queue_file("conflict");
+        enqueue_in(&file, change("a")).unwrap();
+        enqueue_in(&file, change("b")).unwrap();
+
+        match settle_in(&file, &change("a"), failure(ErrorKind::Conflict)) {
+            Ok(Replayed::Rejected(err)) => assert_eq!(err.kind, ErrorKind::Conflict),
+            other => panic!("expected a rejection, got {:?}", other)
+        }
+        assert_eq!(keys(&file), ["b"]);
+
+        let _ = std::fs::remove_file(&file);
+    }
+
+}
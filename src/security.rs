@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Failures of one user before the lockout starts.
+const USER_FREE_ATTEMPTS: u32 = 3;
+
+/// Failures across all users before everyone is locked out.
+const GLOBAL_FREE_ATTEMPTS: u32 = 10;
+
+/// The first lockout, doubled with every further failure.
+const BASE_LOCKOUT_SECS: i64 = 5;
+
+const MAX_LOCKOUT_SECS: i64 = 15 * 60;
+
+const GUARD_FILE: &str = "login-guard.json";
+const LOG_FILE: &str = "security.log";
+
+/// Shown for every failed login so usernames are not revealed.
+pub const GENERIC_LOGIN_ERROR: &str = "Login failed, check your credentials.";
+
+/// Failed attempts of one user or of all users together.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Attempts {
+    pub failures:     u32,
+    /// Unix timestamp, `0` when not locked.
+    pub locked_until: i64,
+    /// Unix timestamp of the last failure.
+    #[serde(default)]
+    pub last_failure: i64
+}
+
+impl Attempts {
+
+    fn remaining(&self, now: i64) -> Option<i64> {
+        match self.locked_until > now {
+            true => Some(self.locked_until - now),
+            false => None
+        }
+    }
+
+    /// Counts a failure, returns the lockout length if one starts.
+    fn fail(&mut self, now: i64, free_attempts: u32) -> Option<i64> {
+        self.failures += 1;
+        self.last_failure = now;
+        if self.failures < free_attempts {
+            return None
+        }
+        let exponent = (self.failures - free_attempts).min(16);
+        let lockout = (BASE_LOCKOUT_SECS << exponent).min(MAX_LOCKOUT_SECS);
+        self.locked_until = now + lockout;
+        Some(lockout)
+    }
+
+}
+
+/// Per-user and global failed login counters with an
+/// exponential lockout. Kept in the data directory so
+/// restarting the app does not reset them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoginGuard {
+    users:  HashMap<String, Attempts>,
+    global: Attempts
+}
+
+impl LoginGuard {
+
+    pub fn load() -> Self {
+
+        let file = crate::storage::data_dir().join(GUARD_FILE);
+
+        if !file.exists() {
+            return Self::default()
+        }
+
+        match std::fs::read_to_string(&file).map(|raw| serde_json::from_str::<Self>(&raw)) {
+            Ok(Ok(guard)) => guard,
+            Ok(Err(err)) => {
+                log::error!("Failed to parse `{}`: {}", file.display(), err);
+                Self::default()
+            }
+            Err(err) => {
+                log::error!("Failed to read `{}`: {}", file.display(), err);
+                Self::default()
+            }
+        }
+
+    }
+
+    fn save(&self) {
+
+        let file = crate::storage::data_dir().join(GUARD_FILE);
+
+        let raw = match serde_json::to_string(self) {
+            Ok(raw) => raw,
+            Err(err) => {
+                log::error!("Failed to serialize the login guard: {}", err);
+                return
+            }
+        };
+
+        if let Err(err) = crate::storage::write_atomic(&file, &raw) {
+            log::error!("Failed to write `{}`: {}", file.display(), err);
+        }
+
+    }
+
+    /// `Err` with a message for the user while `username`
+    /// or everyone is locked out.
+    pub fn check(&self, username: &str) -> Result<(), crate::error::Error> {
+        match self.locked_for(username, chrono::Utc::now().timestamp()) {
+            Some(seconds) => crate::error::error!(
+                kind: crate::error::ErrorKind::Auth,
+                name: "Too many failed attempts",
+                message: format!("Try again in {} seconds.", seconds)
+            ),
+            None => Ok(())
+        }
+    }
+
+    /// Seconds `username` is still locked out at `now`.
+    fn locked_for(&self, username: &str, now: i64) -> Option<i64> {
+        [
+            self.global.remaining(now),
+            self.users.get(username).and_then(|attempts| attempts.remaining(now))
+        ].into_iter().flatten().max()
+    }
+
+    pub fn record_failure(&mut self, username: &str, reason: &str) {
+
+        log_event(SecurityEventKind::LoginFailed, username, reason);
+
+        let (user, global) = self.fail(username, chrono::Utc::now().timestamp());
+
+        if let Some(seconds) = user {
+            log_event(SecurityEventKind::UserLockedOut, username, &format!("Locked for {} seconds.", seconds));
+        }
+
+        if let Some(seconds) = global {
+            log_event(SecurityEventKind::GlobalLockedOut, username, &format!("Everyone locked for {} seconds.", seconds));
+        }
+
+        self.save();
+
+    }
+
+    /// Counts a failure at `now`, returns the lockouts of the user
+    /// and of everyone that start. Names without an account count
+    /// the same, so a lockout does not tell which names exist. The
+    /// global lockout and `prune` keep made up names from piling up.
+    fn fail(&mut self, username: &str, now: i64) -> (Option<i64>, Option<i64>) {
+
+        self.prune(now);
+
+        let user = self.users.entry(username.to_string()).or_default().fail(now, USER_FREE_ATTEMPTS);
+
+        (user, self.global.fail(now, GLOBAL_FREE_ATTEMPTS))
+
+    }
+
+    /// Forgets users no longer locked out who did not fail for as
+    /// long as the longest lockout.
+    fn prune(&mut self, now: i64) {
+        self.users.retain(|_, attempts| attempts.remaining(now).is_some() || now - attempts.last_failure < MAX_LOCKOUT_SECS);
+    }
+
+    pub fn record_success(&mut self, username: &str) {
+        self.users.remove(username);
+        self.global = Attempts::default();
+        self.save();
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SecurityEventKind {
+    LoginFailed,
+    /// A login was tried while locked out.
+    LoginWhileLocked,
+    UserLockedOut,
+    GlobalLockedOut
+}
+
+impl std::fmt::Display for SecurityEventKind {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecurityEventKind::LoginFailed      => write!(f, "Login failed"),
+            SecurityEventKind::LoginWhileLocked => write!(f, "Login while locked"),
+            SecurityEventKind::UserLockedOut    => write!(f, "User locked out"),
+            SecurityEventKind::GlobalLockedOut  => write!(f, "Everyone locked out"),
+        }
+    }
+
+}
+
+/// One line of `security.log`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityEvent {
+    /// RFC 3339 local time.
+    pub time:     String,
+    pub kind:     SecurityEventKind,
+    pub username: String,
+    pub detail:   String
+}
+
+/// Appends an event to `security.log` in the data directory.
+pub fn log_event(kind: SecurityEventKind, username: &str, detail: &str) {
+
+    let event = SecurityEvent {
+        time: chrono::Local::now().to_rfc3339(),
+        kind,
+        username: username.to_string(),
+        detail: detail.to_string()
+    };
+
+    log::warn!("{}: `{}` {}", event.kind, event.username, event.detail);
+
+    if let Err(err) = crate::storage::append_json_line(&crate::storage::data_dir().join(LOG_FILE), &event) {
+        log::error!("{}", err);
+    }
+
+}
+
+/// All events of `security.log`, oldest first. Lines that
+/// fail to parse are skipped.
+pub fn read_log() -> Result<Vec<SecurityEvent>, crate::error::Error> {
+    crate::storage::read_json_lines(&crate::storage::data_dir().join(LOG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn user_is_locked_after_the_free_attempts() {
+        let mut guard = LoginGuard::default();
+        for _ in 1..USER_FREE_ATTEMPTS {
+            assert_eq!(guard.fail("anna", NOW), (None, None));
+        }
+        assert_eq!(guard.locked_for("anna", NOW), None);
+        assert_eq!(guard.fail("anna", NOW), (Some(BASE_LOCKOUT_SECS), None));
+        assert_eq!(guard.locked_for("anna", NOW), Some(BASE_LOCKOUT_SECS));
+        assert_eq!(guard.locked_for("anna", NOW + BASE_LOCKOUT_SECS), None);
+        assert_eq!(guard.locked_for("bob", NOW), None);
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        let mut attempts = Attempts::default();
+        let lockouts: Vec<Option<i64>> = (0..USER_FREE_ATTEMPTS + 12)
+            .map(|_| attempts.fail(NOW, USER_FREE_ATTEMPTS))
+            .collect();
+        let started: Vec<i64> = lockouts.into_iter().flatten().collect();
+        assert_eq!(&started[..4], &[5, 10, 20, 40]);
+        assert_eq!(*started.last().unwrap(), MAX_LOCKOUT_SECS);
+        assert!(started.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    fn known_and_unknown_names_lock_out_alike() {
+        // `anna` has an account, `nobody` does not, the guard
+        // must not answer differently for them.
+        let mut known = LoginGuard::default();
+        let mut unknown = LoginGuard::default();
+        for _ in 0..USER_FREE_ATTEMPTS + 2 {
+            assert_eq!(known.fail("anna", NOW), unknown.fail("nobody", NOW));
+            assert_eq!(known.locked_for("anna", NOW), unknown.locked_for("nobody", NOW));
+        }
+        assert!(unknown.locked_for("nobody", NOW).is_some());
+    }
+
+    #[test]
+    fn everyone_is_locked_after_the_global_attempts() {
+        let mut guard = LoginGuard::default();
+        for number in 1..GLOBAL_FREE_ATTEMPTS {
+            assert_eq!(guard.fail(&format!("nobody{}", number), NOW).1, None);
+        }
+        assert_eq!(guard.fail("nobody", NOW), (None, Some(BASE_LOCKOUT_SECS)));
+        assert_eq!(guard.locked_for("anna", NOW), Some(BASE_LOCKOUT_SECS));
+    }
+
+    #[test]
+    fn old_failures_are_forgotten() {
+        let mut guard = LoginGuard::default();
+        for _ in 0..USER_FREE_ATTEMPTS {
+            guard.fail("anna", NOW);
+        }
+        guard.fail("bob", NOW);
+        let later = NOW + MAX_LOCKOUT_SECS;
+        guard.fail("carl", later);
+        assert!(!guard.users.contains_key("anna"));
+        assert!(!guard.users.contains_key("bob"));
+        assert!(guard.users.contains_key("carl"));
+    }
+
+}
@@ -0,0 +1,183 @@
+use std::net::{IpAddr, SocketAddr};
+
+use iced::{
+    alignment::Horizontal, widget::{button, center, column, container, row, text, text_input, Column}, Element, Length, Task
+};
+
+use crate::error::{self, error, function_message, ErrorKind};
+use crate::shared::discovery::{self, Server};
+use crate::shared::req_resp;
+use crate::Message;
+
+#[derive(Debug, Clone)]
+pub enum ServerPickerMessage {
+    Search,
+    Found(Result<Vec<Server>, error::Error>),
+    Pick(Server),
+    AddressFieldChanged(String),
+    PinFieldChanged(String),
+    AddressSubmitted,
+}
+
+impl Into<crate::Message> for ServerPickerMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::Servers(self)
+    }
+}
+
+/// Shown before the login, lists the order servers found on
+/// the LAN. An address can be entered by hand when the
+/// network drops the discovery broadcasts.
+#[derive(Debug, Default)]
+pub struct ServerPicker {
+    servers:   Vec<Server>,
+    searching: bool,
+    issue:     String,
+    address:   String,
+    /// Certificate fingerprint for an address entered by hand.
+    pin:       String,
+    /// A server was picked, the login comes next.
+    pub picked: bool,
+}
+
+impl ServerPicker {
+
+    pub fn search(&mut self) -> Task<Message> {
+        if self.searching {
+            return Task::none()
+        }
+        self.searching = true;
+        self.issue.clear();
+        Task::perform(
+            discovery::discover(),
+            |result| ServerPickerMessage::Found(result).into()
+        )
+    }
+
+    pub fn update(&mut self, message: ServerPickerMessage) -> Task<Message> {
+        match message {
+            ServerPickerMessage::Search => return self.search(),
+            ServerPickerMessage::Found(result) => {
+                self.searching = false;
+                match result {
+                    Ok(servers) => self.servers = servers,
+                    Err(err) => {
+                        log::error!("{}", err);
+                        self.issue = err.summary();
+                    }
+                }
+            },
+            ServerPickerMessage::Pick(server) => self.pick(server),
+            ServerPickerMessage::AddressFieldChanged(address) => self.address = address,
+            ServerPickerMessage::PinFieldChanged(pin) => self.pin = pin,
+            ServerPickerMessage::AddressSubmitted => match parse_address(&self.address) {
+                Ok((address, tls)) => self.pick(Server {
+                    name: address.to_string(),
+                    address,
+                    html_port: req_resp::HTML_PORT,
+                    tls,
+                    pin: match crate::tls::normalize(&self.pin) {
+                        pin if pin.is_empty() => None,
+                        pin => Some(pin)
+                    },
+                }),
+                Err(err) => self.issue = err.summary(),
+            },
+        }
+        Task::none()
+    }
+
+    fn pick(&mut self, server: Server) {
+        // Not remembering it only means picking again next time.
+        if let Err(err) = discovery::remember(&server) {
+            log::error!("{}", err);
+        }
+        discovery::select(server);
+        self.picked = true;
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+
+        let selected = discovery::selected();
+
+        let servers = self.servers.iter().fold(
+            Column::new().spacing(5),
+            |servers, server| servers.push(
+                button(text!("{} ({}{})", server.name, server.address, if server.tls { ", HTTPS" } else { "" }))
+                    .width(Length::Fill)
+                    .style(match selected.as_ref() == Some(server) {
+                        true => button::primary,
+                        false => button::secondary
+                    })
+                    .on_press(ServerPickerMessage::Pick(server.clone()).into())
+            )
+        );
+
+        let status = match (self.searching, self.servers.is_empty()) {
+            (true, _) => Some("Searching the network..."),
+            (false, true) => Some("No servers found. Is the server running on this network?"),
+            _ => None
+        };
+
+        let col = column![
+            text("Pick the order server:")
+                .align_x(Horizontal::Center)
+                .width(Length::Fill),
+            servers,
+        ]
+        .push_maybe(status.map(text))
+        .push_maybe((!self.issue.is_empty()).then(|| text!("{}", self.issue).style(text::danger)))
+        .push(
+            button("Search again")
+                .style(button::secondary)
+                .on_press_maybe((!self.searching).then(|| ServerPickerMessage::Search.into()))
+        )
+        .push(
+            row![
+                text_input("Address, e.g. https://192.168.1.10:8656", &self.address)
+                    .on_input(|s| ServerPickerMessage::AddressFieldChanged(s).into())
+                    .on_submit(ServerPickerMessage::AddressSubmitted.into()),
+                button("Connect")
+                    .on_press_maybe((!self.address.is_empty()).then(|| ServerPickerMessage::AddressSubmitted.into())),
+            ].spacing(10)
+        )
+        .push_maybe(self.address.trim().starts_with("https://").then(||
+            text_input("Certificate fingerprint (optional, trusted on first use otherwise)", &self.pin)
+                .on_input(|s| ServerPickerMessage::PinFieldChanged(s).into())
+                .on_submit(ServerPickerMessage::AddressSubmitted.into())
+        ))
+        .padding(20)
+        .spacing(20)
+        .align_x(Horizontal::Center);
+
+        center(container(col)
+            .height(Length::Shrink)
+            .width(Length::Fixed(460.0))
+            .style(container::rounded_box)
+        ).into()
+
+    }
+
+}
+
+/// `host:port`, or only the host for the default port, with
+/// `https://` in front for TLS.
+fn parse_address(address: &str) -> Result<(SocketAddr, bool), error::Error> {
+    let address = address.trim();
+    let (address, tls) = match address.strip_prefix("https://") {
+        Some(address) => (address, true),
+        None => (address.strip_prefix("http://").unwrap_or(address), false)
+    };
+    let address = address.trim_end_matches('/');
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Ok((address, tls))
+    }
+    match address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => Ok((SocketAddr::new(ip, req_resp::DB_PORT), tls)),
+        Err(err) => error!(
+            kind: ErrorKind::Input,
+            name: format!("`{}` is not an address", address),
+            message: function_message!("SocketAddr::from_str", err.to_string())
+        )
+    }
+}
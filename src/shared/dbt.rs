@@ -2,6 +2,22 @@ use serde::{Deserialize, Serialize};
 
 pub type VirtualTableID = String;
 pub type OfferID        = String;
+/// Where an offer is prepared, e.g. `bar` or `kitchen`.
+pub type StationID      = String;
+
+/// Station of offers and items saved before stations existed.
+pub const DEFAULT_STATION: &str = "kitchen";
+
+fn default_station() -> StationID {
+    DEFAULT_STATION.to_string()
+}
+
+/// Time passed since the RFC 3339 `time`, zero if it lies in
+/// the future because the clocks differ.
+pub fn elapsed_since(time: &str) -> Option<std::time::Duration> {
+    let time = chrono::DateTime::parse_from_rfc3339(time).ok()?;
+    Some((chrono::Utc::now() - time.with_timezone(&chrono::Utc)).to_std().unwrap_or_default())
+}
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct OrderID {
@@ -13,19 +29,182 @@ pub struct OrderID {
 pub struct VirtualTable {
     pub name: VirtualTableID,
     pub order_count: u32,
+    /// Open while the guests may order from their phones.
+    #[serde(default)]
+    pub session: Option<TableSession>,
+}
+
+/// Lets the guests of a table order from their phones until staff
+/// close it at payment or it expires. The server refuses orders
+/// without the token or PIN of an open session.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct TableSession {
+    /// Carried by the QR code.
+    pub token: String,
+    /// Typed in on the ordering page instead of scanning.
+    pub pin: String,
+    /// RFC 3339 times, set by the server.
+    pub opened: String,
+    pub expires: String,
+    /// Signing key the server signed the QR code with, see
+    /// `SigningKey`. Signed again when the key is rotated.
+    #[serde(default)]
+    pub key_id: u32,
+    /// Hex encoded, empty if the server did not sign it.
+    #[serde(default)]
+    pub sig: String,
+}
+
+impl TableSession {
+
+    /// Not expired yet, closed sessions are dropped by the server.
+    pub fn is_open(&self) -> bool {
+        chrono::DateTime::parse_from_rfc3339(&self.expires).is_ok_and(|expires| expires > chrono::Utc::now())
+    }
+
+    /// Local `HH:MM` the session expires at.
+    pub fn expires_at(&self) -> String {
+        chrono::DateTime::parse_from_rfc3339(&self.expires)
+            .map(|expires| expires.with_timezone(&chrono::Local).format("%H:%M").to_string())
+            .unwrap_or_else(|_| self.expires.clone())
+    }
+
+}
+
+/// Key the QR codes of the tables are signed with, by HMAC-SHA256
+/// over the table name, a newline and the session token. Only the
+/// server knows the secret, it signs the sessions and refuses QR
+/// orders not signed with the current key, so rotating it voids
+/// every printed code.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SigningKey {
+    /// Carried by the QR code next to the signature.
+    pub id: u32,
+    /// RFC 3339 time the server made it.
+    pub created: String,
+}
+
+/// Progress of one item, dishes are served as they come out.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    #[default]
+    Queued,
+    Preparing,
+    Ready,
+    Served,
+}
+
+impl ItemStatus {
+    pub const ALL: [ItemStatus; 4] = [
+        ItemStatus::Queued,
+        ItemStatus::Preparing,
+        ItemStatus::Ready,
+        ItemStatus::Served,
+    ];
+}
+
+impl std::fmt::Display for ItemStatus {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItemStatus::Queued    => write!(f, "Queued"),
+            ItemStatus::Preparing => write!(f, "Preparing"),
+            ItemStatus::Ready     => write!(f, "Ready"),
+            ItemStatus::Served    => write!(f, "Served"),
+        }
+    }
+
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct OrderItem {
     pub id: OfferID,
     pub count: u32,
+    /// Copied from the offer when the order is placed.
+    #[serde(default = "default_station")]
+    pub station: StationID,
+    #[serde(default)]
+    pub status: ItemStatus,
+    /// RFC 3339 time the server set `status`.
+    #[serde(default)]
+    pub changed: Option<String>,
+}
+
+/// A move of an order to another table, recorded by the server.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OrderTransfer {
+    /// Id the order had before.
+    pub from: OrderID,
+    /// RFC 3339 time of the move.
+    pub time: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct Order {
     pub id: OrderID,
     pub finished: bool,
-    pub items: Vec<OrderItem>
+    pub items: Vec<OrderItem>,
+    /// Bumped by the server on every change. Changes carry the
+    /// version they were based on and are refused if it is stale.
+    #[serde(default)]
+    pub version: u32,
+    /// Stations that prepared their items. The server finishes the
+    /// order once every station of its items is done.
+    #[serde(default)]
+    pub stations_done: Vec<StationID>,
+    /// RFC 3339 time the server took the order, missing on
+    /// orders placed before the server kept it.
+    #[serde(default)]
+    pub created: Option<String>,
+    /// RFC 3339 time of the last change of state, by the server.
+    #[serde(default)]
+    pub changed: Option<String>,
+    /// Moves to other tables, oldest first.
+    #[serde(default)]
+    pub transfers: Vec<OrderTransfer>,
+    /// Settled, no longer on the table's bill. Finished orders
+    /// stay on it until they are paid.
+    #[serde(default)]
+    pub paid: bool,
+    /// Placed from a table QR while confirmation is required and
+    /// not accepted by staff yet. Left out of `Orders`, listed by
+    /// `OrdersPending` instead.
+    #[serde(default)]
+    pub pending: bool
+}
+
+impl Order {
+
+    /// Stations of the items, each once.
+    pub fn stations(&self) -> Vec<&StationID> {
+        let mut stations: Vec<&StationID> = Vec::new();
+        for item in self.items.iter() {
+            if !stations.contains(&&item.station) {
+                stations.push(&item.station);
+            }
+        }
+        stations
+    }
+
+    /// Time since the order was taken.
+    pub fn age(&self) -> Option<std::time::Duration> {
+        self.created.as_deref().and_then(elapsed_since)
+    }
+
+    /// Every item reached the table, the order can be finished.
+    pub fn all_served(&self) -> bool {
+        self.items.iter().all(|item| item.status == ItemStatus::Served)
+    }
+
+    /// Stations with items still to prepare.
+    pub fn open_stations(&self) -> Vec<&StationID> {
+        self.stations()
+            .into_iter()
+            .filter(|station| !self.stations_done.contains(station))
+            .collect()
+    }
+
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -34,4 +213,6 @@ pub struct Offer {
     pub description:    String,
     pub price_integer:  u32,
     pub price_fraction: u32,
+    #[serde(default = "default_station")]
+    pub station:        StationID,
 }
\ No newline at end of file
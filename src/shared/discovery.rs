@@ -0,0 +1,321 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+use crate::error::{self, error, function_message, ErrorKind};
+use super::req_resp;
+
+/// UDP port the server answers discovery requests on.
+pub const DISCOVERY_PORT: u16 = 8657;
+/// How long answers are collected after asking.
+pub const DISCOVERY_WAIT: Duration = Duration::from_secs(2);
+/// IPv6 all-nodes link-local multicast group.
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const MAX_DATAGRAM: usize = 1024;
+/// The picked server, remembered in the data directory.
+const SERVER_FILE: &str = "server.json";
+
+/// Sent as JSON, one per UDP datagram. Clients send `Discover`
+/// to `DISCOVERY_PORT`, servers answer the sender with `Announce`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "oby", rename_all = "snake_case")]
+pub enum Datagram {
+    Discover,
+    Announce {
+        name:      String,
+        port:      u16,
+        html_port: u16,
+        /// The order API speaks HTTPS.
+        #[serde(default)]
+        tls:       bool,
+    },
+}
+
+/// An order server, found on the LAN or entered by hand.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub name:      String,
+    /// Address of the order API.
+    pub address:   SocketAddr,
+    /// Port of the web page the table QR codes point to.
+    pub html_port: u16,
+    /// Talk HTTPS to the order API.
+    #[serde(default)]
+    pub tls:       bool,
+    /// SHA-256 fingerprint the server certificate must have. Without
+    /// one the certificate is trusted on the first connection.
+    #[serde(default)]
+    pub pin:       Option<String>,
+}
+
+static SERVER: RwLock<Option<Server>> = RwLock::new(None);
+
+/// Sends all further requests to `server`.
+pub fn select(server: Server) {
+    log::info!("Using the server `{}` at {}.", server.name, server.address);
+    *SERVER.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(server);
+}
+
+pub fn selected() -> Option<Server> {
+    SERVER.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// The picked server or, if none was picked, plain HTTP to the
+/// first IPv4 address of this machine.
+pub fn server() -> Result<Server, error::Error> {
+    if let Some(server) = selected() {
+        return Ok(server)
+    }
+    match req_resp::get_local_ip_address() {
+        Ok(ip) => match ip.parse::<IpAddr>() {
+            Ok(ip) => Ok(Server {
+                name: ip.to_string(),
+                address: SocketAddr::new(ip, req_resp::DB_PORT),
+                html_port: req_resp::HTML_PORT,
+                tls: false,
+                pin: None,
+            }),
+            Err(err) => error!(
+                kind: ErrorKind::Network,
+                name: "Invalid local address",
+                message: function_message!("IpAddr::from_str", err.to_string())
+            )
+        },
+        Err(err) => error!(
+            kind: ErrorKind::Network,
+            name: "Not connected to a network",
+            message: function_message!("get_local_ip_address", err.to_string())
+        )
+    }
+}
+
+/// Address of the web page for the table QR codes.
+pub fn web_address() -> Result<SocketAddr, error::Error> {
+    server().map(|server| SocketAddr::new(server.address.ip(), server.html_port))
+}
+
+fn file() -> std::path::PathBuf {
+    crate::storage::data_dir().join(SERVER_FILE)
+}
+
+/// Saves `server` to be picked again on the next start.
+pub fn remember(server: &Server) -> Result<(), error::Error> {
+
+    let raw = match serde_json::to_string_pretty(server) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the server",
+            message: function_message!("serde_json::to_string_pretty", err.to_string())
+        )
+    };
+
+    let file = file();
+    match crate::storage::write_atomic(&file, &raw) {
+        Ok(()) => Ok(()),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("storage::write_atomic", err.to_string())
+        )
+    }
+
+}
+
+/// The server picked last time, if any.
+pub fn remembered() -> Option<Server> {
+    let file = file();
+    let raw = std::fs::read_to_string(&file).ok()?;
+    match serde_json::from_str(&raw) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            log::error!("Ignoring the invalid `{}`: {}", file.display(), err);
+            None
+        }
+    }
+}
+
+/// Where `Discover` is sent: the broadcast address of every IPv4
+/// network, the IPv6 all-nodes group of every interface and the
+/// loopback addresses, so a server on this machine is found too.
+fn targets() -> Vec<SocketAddr> {
+
+    let mut targets = vec![
+        SocketAddr::new(Ipv4Addr::BROADCAST.into(), DISCOVERY_PORT),
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DISCOVERY_PORT),
+        SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DISCOVERY_PORT),
+    ];
+
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            log::warn!("Failed to list the network interfaces: {}", err);
+            return targets
+        }
+    };
+
+    for interface in interfaces.into_iter().filter(|interface| !interface.is_loopback()) {
+        match interface.addr {
+            if_addrs::IfAddr::V4(addr) => if let Some(broadcast) = addr.broadcast {
+                targets.push(SocketAddr::new(broadcast.into(), DISCOVERY_PORT));
+            },
+            if_addrs::IfAddr::V6(_) => if let Some(index) = interface.index {
+                targets.push(SocketAddrV6::new(ALL_NODES, DISCOVERY_PORT, 0, index).into());
+            },
+        }
+    }
+
+    targets.sort();
+    targets.dedup();
+    targets
+
+}
+
+/// Asks the LAN for order servers and collects the answers
+/// for `DISCOVERY_WAIT`.
+pub async fn discover() -> Result<Vec<Server>, error::Error> {
+
+    let request = match serde_json::to_vec(&Datagram::Discover) {
+        Ok(request) => request,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the discovery request",
+            message: function_message!("serde_json::to_vec", err.to_string())
+        )
+    };
+
+    let v4 = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+        Ok(socket) => socket,
+        Err(err) => return error!(
+            kind: ErrorKind::Network,
+            name: "Cannot search for servers",
+            message: function_message!("UdpSocket::bind", err.to_string())
+        )
+    };
+    if let Err(err) = v4.set_broadcast(true) {
+        log::warn!("Failed to allow broadcasts: {}", err);
+    }
+    // Missing on machines without IPv6, IPv4 is enough then.
+    let v6 = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await
+        .inspect_err(|err| log::warn!("Searching without IPv6: {}", err))
+        .ok();
+
+    let mut sent = 0;
+    for target in targets() {
+        let socket = match (target, &v6) {
+            (SocketAddr::V4(_), _) => &v4,
+            (SocketAddr::V6(_), Some(v6)) => v6,
+            (SocketAddr::V6(_), None) => continue,
+        };
+        match socket.send_to(&request, target).await {
+            Ok(_) => sent += 1,
+            Err(err) => log::info!("Cannot ask {}: {}", target, err),
+        }
+    }
+
+    if sent == 0 {
+        return error!(
+            kind: ErrorKind::Network,
+            name: "Cannot search for servers",
+            message: "no network accepted the discovery request"
+        )
+    }
+
+    let deadline = tokio::time::Instant::now() + DISCOVERY_WAIT;
+    let mut servers = collect(&v4, deadline).await;
+    // Answers that arrived meanwhile are still buffered.
+    if let Some(v6) = &v6 {
+        servers.extend(collect(v6, deadline).await);
+    }
+
+    let mut found: Vec<Server> = Vec::new();
+    for server in servers {
+        if !found.iter().any(|known| known.address == server.address) {
+            found.push(server);
+        }
+    }
+    Ok(found)
+
+}
+
+async fn collect(socket: &UdpSocket, deadline: tokio::time::Instant) -> Vec<Server> {
+
+    let mut servers = Vec::new();
+    let mut buffer = [0; MAX_DATAGRAM];
+
+    loop {
+        let (length, source) = match tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+            Ok(Ok(received)) => received,
+            Ok(Err(err)) => {
+                log::warn!("Failed to receive a discovery answer: {}", err);
+                continue
+            },
+            Err(_) => return servers
+        };
+        match serde_json::from_slice::<Datagram>(&buffer[..length]) {
+            Ok(Datagram::Announce { name, port, html_port, tls }) => {
+                let mut address = source;
+                address.set_port(port);
+                log::info!("Found the server `{}` at {}.", name, address);
+                servers.push(Server { name, address, html_port, tls, pin: None });
+            },
+            Ok(Datagram::Discover) => {},
+            Err(err) => log::info!("Ignoring a datagram from {}: {}", source, err),
+        }
+    }
+
+}
+
+/// Answers discovery requests as `name` until an error occurs.
+/// Meant for the server, also run by `--announce` to try the
+/// discovery with a second process on one machine.
+pub fn announce(name: &str, port: u16, html_port: u16, tls: bool) -> std::io::Result<()> {
+
+    let answer = serde_json::to_vec(&Datagram::Announce {
+        name: name.to_string(),
+        port,
+        html_port,
+        tls,
+    })?;
+
+    let v4 = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))?;
+    match bind_v6_only(DISCOVERY_PORT) {
+        Ok(v6) => {
+            let answer = answer.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = answer_requests(&v6, &answer) {
+                    log::error!("Stopped answering over IPv6: {}", err);
+                }
+            });
+        },
+        Err(err) => log::warn!("Answering without IPv6: {}", err),
+    }
+
+    log::info!("Announcing `{}` on port {}.", name, DISCOVERY_PORT);
+    answer_requests(&v4, &answer)
+
+}
+
+/// Binds `[::]:port` for IPv6 alone. Dual stack systems would
+/// otherwise map IPv4 onto it too and refuse the bind after the
+/// IPv4 socket took the port.
+fn bind_v6_only(port: u16) -> std::io::Result<std::net::UdpSocket> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    Ok(socket.into())
+}
+
+fn answer_requests(socket: &std::net::UdpSocket, answer: &[u8]) -> std::io::Result<()> {
+    let mut buffer = [0; MAX_DATAGRAM];
+    loop {
+        let (length, source) = socket.recv_from(&mut buffer)?;
+        if let Ok(Datagram::Discover) = serde_json::from_slice(&buffer[..length]) {
+            log::info!("Discovered by {}.", source);
+            socket.send_to(answer, source)?;
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod dbt;
-pub mod req_resp;
\ No newline at end of file
+pub mod req_resp;
+pub mod discovery;
\ No newline at end of file
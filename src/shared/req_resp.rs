@@ -9,12 +9,15 @@ use tokio::net::TcpStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
 
 use crate::shared::dbt;
+use crate::shared::discovery;
+use crate::error::{self, error, error_forward, function_message, ErrorKind};
 
 use super::dbt::VirtualTableID;
 
 pub const IP: &str = "192.168.50.118";
 pub const DB_PORT: u16 = 8656;
 pub const HTML_PORT: u16 = 5000;
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
 
 
 pub fn get_local_ip_address() -> Result<String, std::io::Error> {
@@ -144,7 +147,10 @@ pub struct OrdersDeleteResponseData;
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OffersTablesResponseData {
     pub offers: Vec<dbt::Offer>,
-    pub tables: Vec<dbt::VirtualTable>
+    pub tables: Vec<dbt::VirtualTable>,
+    /// Whether QR orders wait for staff, see `OrdersConfirmation`.
+    #[serde(default)]
+    pub confirmation_required: bool
 }
 
 
@@ -158,6 +164,266 @@ pub struct OrdersFinishResponseData {
 }
 
 
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct OrdersStationDoneRequestData {
+        /// Id and the version the change is based on.
+        pub order: dbt::Order,
+        pub stations: Vec<dbt::StationID>
+    }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersStationDoneResponseData {
+    /// Finished if no station is left.
+    pub order: dbt::Order
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct OrdersItemStatusRequestData {
+        /// Id and the version the change is based on.
+        pub order: dbt::Order,
+        /// Place of the item in `order.items`.
+        pub item: usize,
+        pub status: dbt::ItemStatus
+    }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersItemStatusResponseData {
+    /// Finished if every item is served.
+    pub order: dbt::Order
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct OrdersMoveRequestData {
+        /// Id and the version the change is based on.
+        pub order: dbt::Order,
+        pub to: VirtualTableID
+    }
+/// The server numbers the order after the last one of the
+/// target table and records the move in `transfers`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersMoveResponseData {
+    pub order: dbt::Order
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct TablesMergeRequestData {
+        pub from: VirtualTableID,
+        pub into: VirtualTableID
+    }
+/// Every open order of `from`, moved as by `OrdersMove`
+/// in the order of their numbers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TablesMergeResponseData {
+    pub orders: Vec<dbt::Order>
+}
+
+
+/// QR orders waiting for confirmation, of every table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersPendingResponseData {
+    pub orders: Vec<dbt::Order>
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct OrdersAcceptRequestData {
+        /// As placed, or with the items edited by staff, and the
+        /// version the change is based on.
+        pub order: dbt::Order
+    }
+/// No longer pending, shown to the kitchen from now on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersAcceptResponseData {
+    pub order: dbt::Order
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct OrdersDenyRequestData {
+        /// Id and the version the change is based on.
+        pub order: dbt::Order,
+        /// Shown to the customer on the ordering page.
+        pub reason: String
+    }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersDenyResponseData {
+    pub order: dbt::OrderID
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct OrdersConfirmationRequestData {
+        /// QR orders wait in the pending queue if `true`, they go
+        /// straight to the open orders otherwise.
+        pub required: bool
+    }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersConfirmationResponseData {
+    pub required: bool
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct TablesOpenRequestData {
+        pub table: VirtualTableID,
+        /// How long the session lasts unless it is closed before.
+        pub minutes: u32
+    }
+/// With a new session, signed by the server. An open one is
+/// replaced and its QR code no longer takes orders.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TablesOpenResponseData {
+    pub table: dbt::VirtualTable
+}
+
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct TablesCloseRequestData {
+        pub table: VirtualTableID
+    }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TablesCloseResponseData {
+    pub table: dbt::VirtualTable
+}
+
+
+/// The current signing key of the QR codes, `TablesKeyRotate`
+/// answers with the new one after signing the open sessions again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TablesKeyResponseData {
+    pub key: dbt::SigningKey
+}
+
+
+//////////////////////////////////////////////////
+// Events
+
+/// A change made on the server, by any terminal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    TableAdded    { table: dbt::VirtualTable },
+    TableDeleted  { table: VirtualTableID },
+    /// Its session was opened or closed.
+    TableChanged  { table: dbt::VirtualTable },
+    OfferAdded    { offer: dbt::Offer },
+    OfferEdited   { offer: dbt::Offer },
+    OfferDeleted  { offer: dbt::OfferID },
+    OrderAdded    { order: dbt::Order },
+    OrderChanged  { order: dbt::Order },
+    OrderFinished { order: dbt::OrderID },
+    /// Taken back by the server or staff, `reason` tells why.
+    OrderDenied   { order: dbt::OrderID, reason: String },
+    /// The order got the new id `order.id` at another table.
+    OrderMoved    { from: dbt::OrderID, order: dbt::Order },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangeEvent {
+    /// Numbered by the server without gaps, starting after 0.
+    pub seq:    u64,
+    pub change: Change,
+}
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct EventsRequestData {
+        /// Last event seen. `None` answers right away with the
+        /// current `last` and no events, to start following.
+        pub since: Option<u64>
+    }
+/// Held open by the server until there are events after `since`
+/// or `EVENTS_WAIT` passed, in which case `events` is empty.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventsResponseData {
+    pub last:   u64,
+    pub events: Vec<ChangeEvent>
+}
+
+/// How long the server holds an `Events` request without events.
+pub const EVENTS_WAIT: Duration = Duration::from_secs(25);
+
+
+/// Where the body of `response` starts and how long it is, once
+/// the head arrived and gives the length.
+fn body_extent(response: &[u8]) -> Option<(usize, usize)> {
+    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let Ok(httparse::Status::Complete(start)) = parsed.parse(response) else {
+        return None
+    };
+    if matches!(parsed.code, Some(204) | Some(304)) {
+        return Some((start, 0))
+    }
+    let length = parsed.headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("content-length"))?;
+    let length = std::str::from_utf8(length.value).ok()?.trim().parse().ok()?;
+    Some((start, length))
+}
+
+/// Plain TCP or TLS stream to the server.
+trait Connection: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> Connection for T {}
+
+/// New key for `Request::send_request_with_key`.
+pub fn new_idempotency_key() -> String {
+    format!("{}-{}", chrono::Utc::now().timestamp_millis(), crate::storage::random_string(16))
+}
+
+/// How long connecting to the server may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long the health check waits for the server.
+pub const HEALTH_TIMEOUT: Duration = CONNECT_TIMEOUT;
+
+/// Health check, succeeds when the server accepts a connection
+/// and, over HTTPS, presents the trusted certificate.
+pub async fn check_connection() -> Result<(), error::Error> {
+
+    let server = match discovery::server() {
+        Ok(server) => server,
+        Err(err) => return Err(error_forward!(err))
+    };
+
+    match tokio::time::timeout(HEALTH_TIMEOUT, TcpStream::connect(server.address)).await {
+        Ok(Ok(stream)) if server.tls => match tokio::time::timeout(HEALTH_TIMEOUT, crate::tls::connect(&server, stream)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(err)) => Err(error_forward!(err)),
+            Err(_) => error!(
+                kind: ErrorKind::Network,
+                name: "Cannot reach the server",
+                message: format!("no TLS handshake within {} seconds", HEALTH_TIMEOUT.as_secs())
+            )
+        },
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => error!(
+            kind: ErrorKind::Network,
+            name: "Cannot reach the server",
+            message: function_message!("TcpStream::connect", err.to_string())
+        ),
+        Err(_) => error!(
+            kind: ErrorKind::Network,
+            name: "Cannot reach the server",
+            message: format!("no answer within {} seconds", HEALTH_TIMEOUT.as_secs())
+        )
+    }
+
+}
+
+/// Deserializes a response payload returned by `Request::send_request`.
+pub fn decode<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Result<T, error::Error> {
+    match serde_json::from_value::<T>(value) {
+        Ok(payload) => Ok(payload),
+        Err(err) => error!(
+            kind: ErrorKind::Decode,
+            name: format!("Unexpected `{}` payload", std::any::type_name::<T>().rsplit("::").next().unwrap_or("response")),
+            message: function_message!("serde_json::from_value", err.to_string())
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RequestKind {
 
     Tables,
@@ -175,9 +441,62 @@ pub enum RequestKind {
     OrdersInsert,
     OrdersDelete,
     OrdersFinish,
+    OrdersStationDone,
+    OrdersItemStatus,
+    OrdersMove,
+    TablesMerge,
+    OrdersPending,
+    OrdersAccept,
+    OrdersDeny,
+    OrdersConfirmation,
+    TablesOpen,
+    TablesClose,
+    TablesKey,
+    TablesKeyRotate,
 
     OffersTables,
 
+    Events,
+
+}
+
+impl RequestKind {
+
+    /// How long the whole request may take, connecting
+    /// excluded, before it is given up.
+    pub fn timeout(&self) -> Duration {
+        match self {
+            RequestKind::Tables         |
+            RequestKind::TablesSpecific |
+            RequestKind::Offers         |
+            RequestKind::OffersSpecific |
+            RequestKind::Orders         |
+            RequestKind::OrdersSpecific |
+            RequestKind::OrdersPending  |
+            RequestKind::TablesKey      |
+            RequestKind::OffersTables   => Duration::from_secs(5),
+            RequestKind::TablesInsert   |
+            RequestKind::TablesDelete   |
+            RequestKind::OffersInsert   |
+            RequestKind::OffersDelete   |
+            RequestKind::OrdersInsert   |
+            RequestKind::OrdersDelete   |
+            RequestKind::OrdersFinish   |
+            RequestKind::OrdersStationDone |
+            RequestKind::OrdersItemStatus |
+            RequestKind::OrdersMove |
+            RequestKind::TablesMerge |
+            RequestKind::OrdersAccept |
+            RequestKind::OrdersDeny |
+            RequestKind::OrdersConfirmation |
+            RequestKind::TablesOpen |
+            RequestKind::TablesClose |
+            RequestKind::TablesKeyRotate => Duration::from_secs(10),
+            // Long poll, the server answers within `EVENTS_WAIT`.
+            RequestKind::Events         => EVENTS_WAIT + Duration::from_secs(5),
+        }
+    }
+
 }
 
 pub struct Request {
@@ -195,7 +514,25 @@ pub enum Authority {
 
 impl Request {
 
-    pub async fn send_request(&mut self, id: String) -> Result<serde_json::Value, String> {
+    /// A request carrying `data` as its payload.
+    pub fn encode<T: Serialize>(kind: RequestKind, data: &T) -> Result<Self, error::Error> {
+        match serde_json::to_value(data) {
+            Ok(payload) => Ok(Self { kind, payload: Some(payload) }),
+            Err(err) => error!(
+                kind: ErrorKind::Decode,
+                name: format!("Cannot encode the {:?} request", kind),
+                message: function_message!("serde_json::to_value", err.to_string())
+            )
+        }
+    }
+
+    pub async fn send_request(&mut self, id: String) -> Result<serde_json::Value, error::Error> {
+        self.send_request_with_key(id, None).await
+    }
+
+    /// Like `send_request`, with an `Idempotency-Key` header so the
+    /// server applies a change only once, however often it is sent.
+    pub async fn send_request_with_key(&mut self, id: String, idempotency_key: Option<String>) -> Result<serde_json::Value, error::Error> {
 
 
         log::info!("Entered.");
@@ -204,10 +541,13 @@ impl Request {
         //     Some(payload) => println!("{}", payload.to_string()),
         //     None => println!("No payload")
         // }       
-        let LOCAL_IP = get_local_ip_address().expect("Not connected to a network dummy!");
+        let server = match discovery::server() {
+            Ok(server) => server,
+            Err(err) => return Err(error_forward!(err))
+        };
 
         // let client = Client::new();
-        let address = format!("http://{}:{}", LOCAL_IP, DB_PORT);
+        let address = format!("{}://{}", if server.tls { "https" } else { "http" }, server.address);
 
         const QUERY_ENCODE_SET: &AsciiSet = &CONTROLS
             .add(b' ')
@@ -220,7 +560,7 @@ impl Request {
             .add(b'/')
             .add(b'=');
 
-        let (request, body) = match self.kind {
+        let (mut request, body) = match self.kind {
 
             RequestKind::Tables => {
                 let uri = format!("{}/{}",
@@ -279,7 +619,11 @@ impl Request {
 
             RequestKind::TablesInsert => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -394,7 +738,11 @@ impl Request {
 
             RequestKind::OffersInsert => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -454,7 +802,11 @@ impl Request {
 
             RequestKind::Orders => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -487,7 +839,11 @@ impl Request {
 
             RequestKind::OrdersSpecific => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -519,7 +875,11 @@ impl Request {
 
             RequestKind::OrdersInsert => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -551,7 +911,11 @@ impl Request {
 
             RequestKind::OrdersDelete => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -620,7 +984,11 @@ impl Request {
 
             RequestKind::OrdersFinish => {
                 if self.payload.is_none() {
-                    return Err("Payload was empty.".to_string());
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
                 }
 
                 let payload = self.payload.clone().unwrap().to_string();
@@ -650,6 +1018,373 @@ impl Request {
                 )  
             }
 
+            RequestKind::OrdersStationDone => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-station-done", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::OrdersItemStatus => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-item-status", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::OrdersMove => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-move", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::TablesMerge => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("tables-merge", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::OrdersAccept => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-accept", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::OrdersDeny => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-deny", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::OrdersConfirmation => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-confirmation", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::TablesOpen => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("tables-open", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::TablesClose => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("tables-close", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )  
+            }
+
+            RequestKind::OrdersPending => {
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("orders-pending", QUERY_ENCODE_SET)
+                );
+                log::info!("{}", uri);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::GET)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    "".to_string()
+                )
+            }
+
+            RequestKind::TablesKey => {
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("tables-key", QUERY_ENCODE_SET)
+                );
+                log::info!("{}", uri);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::GET)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    "".to_string()
+                )
+            }
+
+            RequestKind::TablesKeyRotate => {
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("tables-key-rotate", QUERY_ENCODE_SET)
+                );
+                log::info!("{}", uri);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    "".to_string()
+                )
+            }
+
+            RequestKind::Events => {
+                if self.payload.is_none() {
+                    return error!(
+                        kind: ErrorKind::Protocol,
+                        name: "Payload was empty",
+                        message: format!("{:?} needs a payload", self.kind)
+                    );
+                }
+
+                let payload = self.payload.clone().unwrap().to_string();
+                let payload_length = payload.len();
+                let uri = format!("{}/{}",
+                    address,
+                    utf8_percent_encode("events", QUERY_ENCODE_SET)
+                );
+                log::info!("{} -> {}", uri, payload);
+                (
+                    hyper::Request::builder()
+                        .method(hyper::Method::GET)
+                        .uri(uri)
+                        .header("Accept", "*/*")
+                        .header("Content-Type", "application/json")
+                        .header("Content-Length", payload_length.to_string())
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("User-Agent", "Rust HTTP Client")
+                        .body(())
+                        .unwrap(),
+                    payload
+                )
+            }
+
             // _ => (
             //     hyper::Request::builder()
             //         .method(hyper::Method::GET)
@@ -661,77 +1396,115 @@ impl Request {
             // )  
         };
         
+        if let Some(key) = idempotency_key {
+            match hyper::header::HeaderValue::from_str(&key) {
+                Ok(value) => { request.headers_mut().insert(IDEMPOTENCY_KEY_HEADER, value); },
+                Err(err) => return error!(
+                    kind: ErrorKind::Protocol,
+                    name: "Invalid idempotency key",
+                    message: function_message!("HeaderValue::from_str", err.to_string())
+                )
+            }
+        }
+
+        let mut headers = Vec::new();
+        for (name, value) in request.headers() {
+            match value.to_str() {
+                Ok(value) => headers.push(format!("{}: {}", name, value)),
+                Err(err) => return error!(
+                    kind: ErrorKind::Protocol,
+                    name: format!("The `{}` header is not plain text", name),
+                    message: function_message!("HeaderValue::to_str", err.to_string())
+                )
+            }
+        }
+
         let request_bytes = format!(
             "{} {} {}\r\n{}\r\n\r\n{}",
             request.method(),
             request.uri().path(),
             "HTTP/1.1",
-            request.headers()
-                .iter()
-                .map(|(k, v)| format!("{}: {}", k, v.to_str().unwrap()))
-                .collect::<Vec<String>>()
-                .join("\r\n"),
+            headers.join("\r\n"),
             body
         );
 
         log::info!("{:#?}", request_bytes);
 
-        let mut stream = match  TcpStream::connect(
-            format!("{}:{}", LOCAL_IP.clone(), DB_PORT)
-        ).await {
+        let deadline = tokio::time::Instant::now() + self.kind.timeout();
+        let timed_out = |what: &str| error!(
+            kind: ErrorKind::Network,
+            name: "The server did not answer in time",
+            message: format!("{} of {:?} took longer than {} seconds", what, self.kind, self.kind.timeout().as_secs())
+        );
+
+        let tcp = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(server.address)).await {
 
-            Ok(stream) => stream,
-            Err(err) => return Err(err.to_string())
+            Ok(Ok(stream)) => stream,
+            Ok(Err(err)) => return error!(
+                kind: ErrorKind::Network,
+                name: "Cannot reach the server. Did you perhaps turn the server on?",
+                message: function_message!("TcpStream::connect", err.to_string())
+            ),
+            Err(_) => return timed_out("Connecting")
      
         };
 
-        stream.set_nodelay(true).expect("Failed to set no_delay");
+        if let Err(err) = tcp.set_nodelay(true) {
+            log::warn!("Failed to set no_delay: {}", err);
+        }
 
-        stream.write(request_bytes.as_bytes()).await.expect("FUCK");
+        let mut stream: Box<dyn Connection> = match server.tls {
+            true => match tokio::time::timeout_at(deadline, crate::tls::connect(&server, tcp)).await {
+                Ok(Ok(stream)) => Box::new(stream),
+                Ok(Err(err)) => return Err(error_forward!(err)),
+                Err(_) => return timed_out("The TLS handshake")
+            },
+            false => Box::new(tcp)
+        };
+
+        match tokio::time::timeout_at(deadline, stream.write_all(request_bytes.as_bytes())).await {
+            Ok(Ok(())) => {},
+            Ok(Err(err)) => return error!(
+                kind: ErrorKind::Network,
+                name: "Failed to send the request",
+                message: function_message!("TcpStream::write_all", err.to_string())
+            ),
+            Err(_) => return timed_out("Sending")
+        }
 
         const BUFFER_SIZE: usize = 16000;
         let mut buffer = [0; BUFFER_SIZE];
         let mut response_bytes = Vec::new();
-        let mut total_bytes_read = 0;
-        let mut headers_received = false;
+        let mut extent = None;
 
+        // A read can end anywhere, e.g. at a TLS record, so read
+        // until the server closes or the whole body arrived.
         loop {
-            let bytes_read = stream.read(&mut buffer).await.expect("Cannot read.");
+            let bytes_read = match tokio::time::timeout_at(deadline, stream.read(&mut buffer)).await {
+                Ok(Ok(bytes_read)) => bytes_read,
+                Ok(Err(err)) => return error!(
+                    kind: ErrorKind::Network,
+                    name: "Failed to read the response",
+                    message: function_message!("TcpStream::read", err.to_string())
+                ),
+                Err(_) => return timed_out("Reading the response")
+            };
 
             if bytes_read == 0 {
                 break; // Connection closed by the server
             }
 
             response_bytes.extend_from_slice(&buffer[..bytes_read]);
-            total_bytes_read += bytes_read;
-
-            if !headers_received {
-                if let Some(pos) = response_bytes.windows(4).position(|window| window == b"\r\n\r\n") {
-                    headers_received = true;
-                    let headers = &response_bytes[..pos + 4];
-                    log::info!("Headers: {:?}", String::from_utf8_lossy(headers));
-                    log::info!("{:?}", String::from_utf8(response_bytes.clone()));
-                }
-            }
 
-            // let mut read_buf = ReadBuf::new(&mut buffer);
-
-            if bytes_read != BUFFER_SIZE {
-                break;
+            if extent.is_none() {
+                extent = body_extent(&response_bytes);
             }
 
-            // match std::future::poll_fn(|cx|
-            //     stream.poll_peek(cx, &mut read_buf)
-            // ).await {
-            //     Ok(size) => {
-            //         if size != 0 {
-            //             continue;
-            //         }
-            //         break;
-            //     }
-            //     Err(err) => return Err(err.to_string())
-            // }
-
+            if let Some((start, length)) = extent {
+                if response_bytes.len() >= start + length {
+                    break;
+                }
+            }
         }
 
         let response_body = String::from_utf8_lossy(&response_bytes);
@@ -745,6 +1518,21 @@ impl Request {
         match result {
             Ok(httparse::Status::Complete(body_offset)) => {
                 let body = &response_body[body_offset..];
+                if let Some(code) = response.code.filter(|code| !(200..300).contains(code)) {
+                    log::error!("Error exit. Status {}.", code);
+                    return error!(
+                        kind: match code {
+                            // The version or idempotency key did not match.
+                            409 | 412 => ErrorKind::Conflict,
+                            _ => ErrorKind::Protocol
+                        },
+                        name: format!("Server answered with status {}", code),
+                        message: match body.trim().is_empty() {
+                            true => response.reason.unwrap_or("no reason given").to_string(),
+                            false => body.trim().to_string()
+                        }
+                    );
+                }
                 return match self.kind {
                     RequestKind::Tables         |
                     RequestKind::TablesSpecific |
@@ -753,7 +1541,20 @@ impl Request {
                     RequestKind::Orders         |
                     RequestKind::OrdersSpecific |
                     RequestKind::OrdersFinish   |
-                    RequestKind::OffersTables 
+                    RequestKind::OrdersStationDone |
+                    RequestKind::OrdersItemStatus |
+                    RequestKind::OrdersMove |
+                    RequestKind::TablesMerge |
+                    RequestKind::OrdersPending |
+                    RequestKind::OrdersAccept |
+                    RequestKind::OrdersDeny |
+                    RequestKind::OrdersConfirmation |
+                    RequestKind::TablesOpen |
+                    RequestKind::TablesClose |
+                    RequestKind::TablesKey |
+                    RequestKind::TablesKeyRotate |
+                    RequestKind::OffersTables   |
+                    RequestKind::Events
                     => {
                         log::info!("We outta here!");
                         match serde_json::from_str(body) {
@@ -763,7 +1564,11 @@ impl Request {
                             },
                             Err(err) => {
                                 log::error!("Error Exit. Borked body.");
-                                Err("Invalid data received. Did you perhaps change the database elements?".to_string())
+                                error!(
+                                    kind: ErrorKind::Decode,
+                                    name: "Invalid data received. Did you perhaps change the database elements?",
+                                    message: function_message!("serde_json::from_str", err.to_string())
+                                )
                             }
                         }
                     }
@@ -775,7 +1580,11 @@ impl Request {
             }
             _ => {
                 log::error!("Error exit. Borked http response.");
-                return Err("Server side issue. Did you perhaps turn the server on?".to_string())
+                return error!(
+                    kind: ErrorKind::Protocol,
+                    name: "Server side issue. Did you perhaps turn the server on?",
+                    message: "the response was not valid HTTP"
+                )
             }
         }
 
@@ -800,3 +1609,27 @@ impl Request {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn body_extent_waits_for_the_head() {
+        assert_eq!(body_extent(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n"), None);
+    }
+
+    #[test]
+    fn body_extent_reads_the_content_length() {
+        let response = b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n{\"a\"";
+        assert_eq!(body_extent(response), Some((38, 5)));
+    }
+
+    #[test]
+    fn body_extent_without_length_reads_to_the_end() {
+        assert_eq!(body_extent(b"HTTP/1.1 200 OK\r\nServer: oby\r\n\r\n{}"), None);
+        assert_eq!(body_extent(b"HTTP/1.1 204 No Content\r\n\r\n"), Some((27, 0)));
+    }
+
+}
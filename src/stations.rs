@@ -0,0 +1,115 @@
+use std::sync::RwLock;
+
+use crate::audit::{AuditAction, AuditEntry};
+use crate::error::{self, error, function_message, ErrorKind};
+use crate::outbox::QueuedChange;
+use crate::shared::{dbt, req_resp};
+
+/// Stations this terminal shows, kept in the data directory
+/// since they belong to the machine, not to who is logged in.
+const STATIONS_FILE: &str = "stations.json";
+
+/// Empty shows every station.
+static STATIONS: RwLock<Vec<dbt::StationID>> = RwLock::new(Vec::new());
+
+fn file() -> std::path::PathBuf {
+    crate::storage::data_dir().join(STATIONS_FILE)
+}
+
+/// Loads the stations of this terminal, after `storage::init`.
+pub fn init() {
+    let file = file();
+    let Ok(raw) = std::fs::read_to_string(&file) else {
+        return
+    };
+    match serde_json::from_str(&raw) {
+        Ok(stations) => *STATIONS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = stations,
+        Err(err) => log::error!("Ignoring the invalid `{}`, showing all stations: {}", file.display(), err),
+    }
+}
+
+pub fn current() -> Vec<dbt::StationID> {
+    STATIONS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// Parses a comma separated list, e.g. `bar, grill`.
+pub fn parse(stations: &str) -> Vec<dbt::StationID> {
+    let mut parsed: Vec<dbt::StationID> = Vec::new();
+    for station in stations.split(',').map(|station| station.trim().to_lowercase()) {
+        if !station.is_empty() && !parsed.contains(&station) {
+            parsed.push(station);
+        }
+    }
+    parsed
+}
+
+/// Shows only `stations` on this terminal from now on.
+pub fn set(stations: Vec<dbt::StationID>) -> Result<(), error::Error> {
+
+    let raw = match serde_json::to_string_pretty(&stations) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the stations",
+            message: function_message!("serde_json::to_string_pretty", err.to_string())
+        )
+    };
+
+    let file = file();
+    if let Err(err) = crate::storage::write_atomic(&file, &raw) {
+        return error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("storage::write_atomic", err.to_string())
+        )
+    }
+
+    *STATIONS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = stations;
+    Ok(())
+
+}
+
+pub fn shows(station: &str) -> bool {
+    let stations = STATIONS.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    stations.is_empty() || stations.iter().any(|shown| shown == station)
+}
+
+/// Stations of `order` still to prepare on this terminal.
+pub fn open_here(order: &dbt::Order) -> Vec<dbt::StationID> {
+    order.open_stations()
+        .into_iter()
+        .filter(|station| shows(station))
+        .cloned()
+        .collect()
+}
+
+/// Marks the part of `order` prepared at `stations` as done.
+/// Returns the request with its idempotency key and the change
+/// to queue if the server is unreachable.
+pub fn done_request(order: &dbt::Order, stations: Vec<dbt::StationID>, username: &str) -> Result<(req_resp::Request, String, Box<QueuedChange>), error::Error> {
+
+    let mut after = order.clone();
+    after.stations_done.extend(stations.iter().cloned());
+
+    let audit = AuditEntry::new(
+        username,
+        AuditAction::StationDone,
+        &format!("{} #{} ({})", order.id.table, order.id.count, stations.join(", ")),
+        Some(order),
+        Some(&after)
+    );
+
+    let request = req_resp::Request::encode(req_resp::RequestKind::OrdersStationDone, &req_resp::OrdersStationDoneRequestData {
+        order: dbt::Order {
+            id: order.id.clone(),
+            version: order.version,
+            ..Default::default()
+        },
+        stations
+    })?;
+
+    let key = req_resp::new_idempotency_key();
+    let change = Box::new(QueuedChange::new(key.clone(), &request, "".to_string(), audit));
+    Ok((request, key, change))
+
+}
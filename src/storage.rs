@@ -0,0 +1,398 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::error::{self, error, error_forward, function_message, ErrorKind};
+use crate::user::{UserData, UserEntry, UserPrivilege};
+
+/// Environment variable that overrides the data directory.
+pub const DATA_DIR_ENV: &str = "OBY_DATA_DIR";
+
+/// Name of the application folder inside the platform data directory.
+const APP_FOLDER: &str = "oby-desktop";
+
+/// Username of the account created on first run.
+const BOOTSTRAP_USERNAME: &str = "admin";
+
+/// File inside the data directory that holds the first run password.
+const BOOTSTRAP_NOTE: &str = "first-run.txt";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where the data directory came from, used for logging.
+#[derive(Debug, Clone, Copy)]
+pub enum DataDirSource {
+    CommandLine,
+    Environment,
+    Platform,
+    WorkingDirectory
+}
+
+impl std::fmt::Display for DataDirSource {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataDirSource::CommandLine      => write!(f, "command line"),
+            DataDirSource::Environment      => write!(f, "${}", DATA_DIR_ENV),
+            DataDirSource::Platform         => write!(f, "platform data directory"),
+            DataDirSource::WorkingDirectory => write!(f, "working directory"),
+        }
+    }
+
+}
+
+/// Picks the data directory.
+///
+/// The order is: `--data-dir`, `$OBY_DATA_DIR`, the
+/// platform data directory (`$XDG_DATA_HOME/oby-desktop`
+/// or `~/.local/share/oby-desktop` on Linux) and lastly
+/// `./oby-desktop` if the platform has none.
+pub fn resolve(cli: Option<PathBuf>) -> (PathBuf, DataDirSource) {
+
+    if let Some(path) = cli {
+        return (path, DataDirSource::CommandLine)
+    }
+
+    match std::env::var_os(DATA_DIR_ENV) {
+        Some(path) if !path.is_empty() => return (PathBuf::from(path), DataDirSource::Environment),
+        _ => {}
+    }
+
+    match dirs::data_dir() {
+        Some(path) => (path.join(APP_FOLDER), DataDirSource::Platform),
+        None => (PathBuf::from(APP_FOLDER), DataDirSource::WorkingDirectory)
+    }
+
+}
+
+/// Resolves the data directory, creates it, migrates an old
+/// `users/` tree into it and creates the first admin account
+/// if there are no users.
+///
+/// Must be called once before [`data_dir`] is used.
+pub fn init(cli: Option<PathBuf>) -> Result<(), error::Error> {
+
+    let (path, source) = resolve(cli);
+
+    log::info!("Using data directory `{}` ({}).", path.display(), source);
+
+    // Set before anything can fail, later file access then
+    // reports its own errors instead of panicking.
+    if DATA_DIR.set(path.clone()).is_err() {
+        return error!(
+            kind: ErrorKind::Storage,
+            name: "Data directory already set",
+            message: "`storage::init` was called more than once"
+        );
+    }
+
+    if let Err(err) = std::fs::create_dir_all(&path) {
+        return error!(
+            kind: ErrorKind::Storage,
+            name: "Failed to create the data directory",
+            message: function_message!("std::fs::create_dir_all", format!("{}: {}", path.display(), err))
+        );
+    }
+
+    if !users_dir().exists() {
+        if let Err(err) = migrate_legacy_users() {
+            return Err(error_forward!(err));
+        }
+    }
+
+    if let Err(err) = bootstrap_admin() {
+        return Err(error_forward!(err));
+    }
+
+    Ok(())
+
+}
+
+/// The data directory chosen by [`init`].
+pub fn data_dir() -> &'static Path {
+    DATA_DIR.get().expect("`storage::init` must run before the data directory is used")
+}
+
+/// Uses a fresh folder in the temporary directory as the data
+/// directory, for tests that touch files in it.
+#[cfg(test)]
+pub fn init_temporary() -> &'static Path {
+    DATA_DIR.get_or_init(|| {
+        let path = std::env::temp_dir().join(format!("oby-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    })
+}
+
+/// Folder holding `<username>.toml` entries and a folder per user.
+pub fn users_dir() -> PathBuf {
+    data_dir().join("users")
+}
+
+/// Folder with `user.toml`, `settings.toml` and the user image.
+pub fn user_folder(username: &str) -> PathBuf {
+    users_dir().join(username)
+}
+
+/// Folder with the cache and outbox of the selected server, so
+/// the data of one server is never shown or replayed on another.
+/// Without a picked server the local fallback uses `default`.
+pub fn server_dir() -> PathBuf {
+    let name = match crate::shared::discovery::selected() {
+        Some(server) => server.address.to_string().replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        None => "default".to_string()
+    };
+    data_dir().join("servers").join(name)
+}
+
+/// Location of the credentials of `username`.
+pub fn user_entry_file(username: &str) -> PathBuf {
+    users_dir().join(format!("{username}.toml"))
+}
+
+/// Usernames that have a `<username>.toml` entry, sorted.
+pub fn usernames() -> Vec<String> {
+
+    let Ok(entries) = std::fs::read_dir(users_dir()) else {
+        return Vec::new()
+    };
+
+    let mut usernames: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .collect();
+
+    usernames.sort();
+    usernames
+
+}
+
+/// Writes `contents` to a temporary file next to `file` and
+/// renames it over `file`, so readers never see a half
+/// written file.
+pub fn write_atomic(file: &Path, contents: &str) -> std::io::Result<()> {
+    write_atomic_with(file, contents, std::fs::OpenOptions::new())
+}
+
+/// Like `write_atomic`, readable by the owner only, for secrets.
+pub fn write_private(file: &Path, contents: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    write_atomic_with(file, contents, options)
+}
+
+fn write_atomic_with(file: &Path, contents: &str, mut options: std::fs::OpenOptions) -> std::io::Result<()> {
+
+    let mut temporary_name = file.file_name().unwrap_or_default().to_os_string();
+    temporary_name.push(".tmp");
+    let temporary = file.with_file_name(temporary_name);
+
+    // A leftover keeps its mode, start over.
+    let _ = std::fs::remove_file(&temporary);
+    let mut handle = options.write(true).create_new(true).open(&temporary)?;
+    handle.write_all(contents.as_bytes())?;
+    handle.sync_all()?;
+    drop(handle);
+
+    std::fs::rename(&temporary, file)
+
+}
+
+/// Appends `value` as one JSON line to `file`, used for the
+/// append-only logs.
+pub fn append_json_line<T: serde::Serialize>(file: &Path, value: &T) -> Result<(), error::Error> {
+
+    let line = match serde_json::to_string(value) {
+        Ok(line) => line,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: format!("Failed to serialize a line of `{}`", file.display()),
+            message: function_message!("serde_json::to_string", err.to_string())
+        )
+    };
+
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .and_then(|mut handle| writeln!(handle, "{}", line));
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("writeln", err.to_string())
+        )
+    }
+
+}
+
+/// Every line of `file` parsed as JSON, oldest first. Lines
+/// that fail to parse are skipped, a missing file is empty.
+pub fn read_json_lines<T: serde::de::DeserializeOwned>(file: &Path) -> Result<Vec<T>, error::Error> {
+
+    if !file.exists() {
+        return Ok(Vec::new())
+    }
+
+    match std::fs::read_to_string(file) {
+        Ok(raw) => Ok(raw
+            .lines()
+            .filter_map(|line| serde_json::from_str::<T>(line).ok())
+            .collect()),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to read `{}`", file.display()),
+            message: function_message!("std::fs::read_to_string", err.to_string())
+        )
+    }
+
+}
+
+/// Places where builds before the data directory kept users.
+fn legacy_users_dirs() -> Vec<PathBuf> {
+    vec![
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("users"),
+        PathBuf::from("users")
+    ]
+}
+
+/// Copies the first existing legacy `users/` tree into the
+/// data directory. The old tree is left untouched.
+fn migrate_legacy_users() -> Result<(), error::Error> {
+
+    let Some(legacy) = legacy_users_dirs().into_iter().find(|dir| dir.is_dir()) else {
+        return Ok(())
+    };
+
+    log::info!("Migrating users from `{}` to `{}`.", legacy.display(), users_dir().display());
+
+    match copy_dir(&legacy, &users_dir()) {
+        Ok(()) => Ok(()),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: "Failed to migrate the `users` folder",
+            message: function_message!("copy_dir", format!("{}: {}", legacy.display(), err))
+        )
+    }
+
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+
+    std::fs::create_dir_all(to)?;
+
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+
+}
+
+/// Random alphanumeric string used for salts and passwords.
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Creates an `admin` account with a random password if
+/// there are no users. The password is only written to
+/// `first-run.txt` in the data directory, readable by the
+/// owner alone.
+fn bootstrap_admin() -> Result<(), error::Error> {
+
+    if !usernames().is_empty() {
+        return Ok(())
+    }
+
+    let password = random_string(12);
+
+    let mut entry = UserEntry {
+        username: BOOTSTRAP_USERNAME.to_string(),
+        ..Default::default()
+    };
+    entry.calculate(password.clone(), random_string(12));
+
+    let user_data = UserData {
+        username: BOOTSTRAP_USERNAME.to_string(),
+        privilege: UserPrivilege::Admin,
+        name: "Admin".to_string(),
+        surname: String::new(),
+        user_image: None
+    };
+
+    let entry_raw = match toml::to_string(&entry) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the admin account",
+            message: function_message!("toml::to_string", err.to_string())
+        )
+    };
+
+    let user_data_raw = match toml::to_string(&user_data) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the admin account",
+            message: function_message!("toml::to_string", err.to_string())
+        )
+    };
+
+    let note = format!(
+        "An account was created on first run.\n\nUsername: {}\nPassword: {}\n\nDelete this file after logging in.\n",
+        BOOTSTRAP_USERNAME, password
+    );
+
+    let writes = [
+        (user_folder(BOOTSTRAP_USERNAME).join("user.toml"), user_data_raw, false),
+        (user_entry_file(BOOTSTRAP_USERNAME), entry_raw, false),
+        (data_dir().join(BOOTSTRAP_NOTE), note, true),
+    ];
+
+    if let Err(err) = std::fs::create_dir_all(user_folder(BOOTSTRAP_USERNAME)) {
+        return error!(
+            kind: ErrorKind::Storage,
+            name: "Failed to create the admin account",
+            message: function_message!("std::fs::create_dir_all", err.to_string())
+        );
+    }
+
+    for (file, contents, private) in writes {
+        let written = match private {
+            true => write_private(&file, &contents),
+            false => std::fs::write(&file, contents)
+        };
+        if let Err(err) = written {
+            return error!(
+                kind: ErrorKind::Storage,
+                name: "Failed to create the admin account",
+                message: function_message!("std::fs::write", format!("{}: {}", file.display(), err))
+            );
+        }
+    }
+
+    log::warn!(
+        "Created user `{}`, its password is in `{}`.",
+        BOOTSTRAP_USERNAME, data_dir().join(BOOTSTRAP_NOTE).display()
+    );
+
+    Ok(())
+
+}
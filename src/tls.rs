@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+use crate::error::{self, error, function_message, ErrorKind};
+use crate::shared::discovery::Server;
+
+/// Fingerprints trusted on first use, by server address.
+const KNOWN_FILE: &str = "known_certificates.json";
+/// Names of the files written by `generate_certificate`.
+pub const CERTIFICATE_FILE: &str = "cert.pem";
+pub const KEY_FILE: &str = "key.pem";
+
+/// SHA-256 of a DER certificate, lowercase hex.
+pub fn fingerprint(der: &[u8]) -> String {
+    hex::encode(Sha256::digest(der))
+}
+
+/// Fingerprint typed by hand, e.g. `AB:CD:..`, in the form
+/// of `fingerprint`.
+pub fn normalize(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn known_file() -> std::path::PathBuf {
+    crate::storage::data_dir().join(KNOWN_FILE)
+}
+
+fn known() -> HashMap<String, String> {
+    let file = known_file();
+    let Ok(raw) = std::fs::read_to_string(&file) else {
+        return HashMap::new()
+    };
+    match serde_json::from_str(&raw) {
+        Ok(known) => known,
+        Err(err) => {
+            log::error!("Ignoring the invalid `{}`: {}", file.display(), err);
+            HashMap::new()
+        }
+    }
+}
+
+fn save_known(known: &HashMap<String, String>) -> Result<(), error::Error> {
+
+    let raw = match serde_json::to_string_pretty(known) {
+        Ok(raw) => raw,
+        Err(err) => return error!(
+            kind: ErrorKind::Decode,
+            name: "Failed to serialize the known certificates",
+            message: function_message!("serde_json::to_string_pretty", err.to_string())
+        )
+    };
+
+    let file = known_file();
+    match crate::storage::write_atomic(&file, &raw) {
+        Ok(()) => Ok(()),
+        Err(err) => error!(
+            kind: ErrorKind::Storage,
+            name: format!("Failed to write `{}`", file.display()),
+            message: function_message!("storage::write_atomic", err.to_string())
+        )
+    }
+
+}
+
+/// Fingerprint trusted for `address` on the first connection.
+pub fn known_fingerprint(address: &SocketAddr) -> Option<String> {
+    known().remove(&address.to_string())
+}
+
+/// Forgets the certificate of `address`, the next connection
+/// trusts whatever certificate the server presents.
+pub fn forget(address: &SocketAddr) -> Result<(), error::Error> {
+    let mut known = known();
+    if known.remove(&address.to_string()).is_some() {
+        log::info!("Forgot the certificate of {}.", address);
+        return save_known(&known)
+    }
+    Ok(())
+}
+
+fn trust(address: &SocketAddr, fingerprint: &str) -> Result<(), error::Error> {
+    log::info!("Trusting {} with the certificate {} from now on.", address, fingerprint);
+    let mut known = known();
+    known.insert(address.to_string(), fingerprint.to_string());
+    save_known(&known)
+}
+
+/// Accepts the certificate with the expected fingerprint, or any
+/// certificate if none is expected yet. The chain and name are not
+/// checked, the servers run with self-signed certificates.
+#[derive(Debug)]
+struct FingerprintVerifier {
+    expected: Option<String>,
+    /// Fingerprint the server presented.
+    seen:     Arc<Mutex<Option<String>>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let presented = fingerprint(end_entity);
+        *self.seen.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(presented.clone());
+        match &self.expected {
+            Some(expected) if *expected != presented => Err(rustls::Error::General(
+                format!("certificate {} is not the expected {}", presented, expected)
+            )),
+            _ => Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+
+}
+
+/// Runs the TLS handshake with `server` over `stream`.
+///
+/// The certificate must match the fingerprint pinned in the
+/// server profile or, without a pin, the one trusted on the first
+/// connection to the address. A changed certificate fails with
+/// an `Auth` error until it is forgotten.
+pub async fn connect(server: &Server, stream: TcpStream) -> Result<TlsStream<TcpStream>, error::Error> {
+
+    let pinned = server.pin.as_deref().map(normalize);
+    let expected = pinned.clone().or_else(|| known_fingerprint(&server.address));
+    let seen = Arc::new(Mutex::new(None));
+    let provider = Arc::new(crypto::ring::default_provider());
+
+    let verifier = FingerprintVerifier {
+        expected: expected.clone(),
+        seen: seen.clone(),
+        provider: provider.clone(),
+    };
+
+    let config = match rustls::ClientConfig::builder_with_provider(provider).with_safe_default_protocol_versions() {
+        Ok(builder) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth(),
+        Err(err) => return error!(
+            kind: ErrorKind::Protocol,
+            name: "Cannot set up TLS",
+            message: function_message!("ClientConfig::with_safe_default_protocol_versions", err.to_string())
+        )
+    };
+
+    let name = ServerName::IpAddress(server.address.ip().into());
+    let result = TlsConnector::from(Arc::new(config)).connect(name, stream).await;
+    let seen = seen.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+
+    match (result, expected, seen) {
+        (Ok(stream), None, Some(seen)) => {
+            // Trusted without asking, the Connection tab shows the
+            // fingerprint to compare with the one the server printed.
+            // If it cannot be saved the connection works regardless,
+            // the certificate is trusted again on the next one.
+            if let Err(err) = trust(&server.address, &seen) {
+                log::error!("{}", err);
+            }
+            Ok(stream)
+        },
+        (Ok(stream), _, _) => Ok(stream),
+        (Err(err), Some(expected), Some(seen)) if expected != seen => error!(
+            kind: ErrorKind::Auth,
+            name: match pinned.is_some() {
+                true => "The server certificate does not match the pinned fingerprint",
+                false => "The server certificate changed since the first connection"
+            },
+            message: format!(
+                "expected {}, the server presented {}. Unless the server got a new certificate, someone may be intercepting the traffic ({})",
+                expected, seen, err
+            )
+        ),
+        (Err(err), _, _) => error!(
+            kind: ErrorKind::Network,
+            name: "TLS handshake failed",
+            message: function_message!("TlsConnector::connect", err.to_string())
+        ),
+    }
+
+}
+
+/// Writes a self-signed certificate and its key for the server
+/// into `folder`, valid for `names` (host names or addresses).
+/// Returns the fingerprint to pin on the clients.
+pub fn generate_certificate(folder: &Path, names: Vec<String>) -> Result<String, error::Error> {
+
+    let certified = match rcgen::generate_simple_self_signed(names) {
+        Ok(certified) => certified,
+        Err(err) => return error!(
+            kind: ErrorKind::Protocol,
+            name: "Cannot generate the certificate",
+            message: function_message!("rcgen::generate_simple_self_signed", err.to_string())
+        )
+    };
+
+    // Only the server should read its key.
+    let files = [
+        (folder.join(CERTIFICATE_FILE), certified.cert.pem(), false),
+        (folder.join(KEY_FILE), certified.key_pair.serialize_pem(), true),
+    ];
+
+    for (file, contents, private) in files.iter() {
+        let written = std::fs::create_dir_all(folder).and_then(|_| match private {
+            true => crate::storage::write_private(file, contents),
+            false => crate::storage::write_atomic(file, contents)
+        });
+        if let Err(err) = written {
+            return error!(
+                kind: ErrorKind::Storage,
+                name: format!("Failed to write `{}`", file.display()),
+                message: function_message!("storage::write_atomic", err.to_string())
+            )
+        }
+    }
+
+    Ok(fingerprint(certified.cert.der()))
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::PrivateKeyDer;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn normalize_accepts_typed_fingerprints() {
+        assert_eq!(normalize("AB:cd:01"), "abcd01");
+        assert_eq!(normalize(" ab cd\n"), "abcd");
+        assert_eq!(normalize("xyz"), "");
+    }
+
+    #[test]
+    fn fingerprint_is_lowercase_sha256() {
+        assert_eq!(
+            fingerprint(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(normalize(&fingerprint(b"abc").to_uppercase()), fingerprint(b"abc"));
+    }
+
+    /// Serves TLS with a fresh `generate_certificate` certificate on
+    /// localhost, returns the address and the certificate fingerprint.
+    async fn serve(name: &str) -> (SocketAddr, String) {
+
+        let folder = crate::storage::init_temporary().join(name);
+        let pin = generate_certificate(&folder, vec!["127.0.0.1".to_string()]).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(folder.join(KEY_FILE)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let certificate = CertificateDer::from_pem_file(folder.join(CERTIFICATE_FILE)).unwrap();
+        let key = PrivateKeyDer::from_pem_file(folder.join(KEY_FILE)).unwrap();
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                // Rejected handshakes fail here too, that is expected.
+                let _ = acceptor.accept(stream).await;
+            }
+        });
+
+        (address, pin)
+
+    }
+
+    async fn handshake(address: SocketAddr, pin: Option<String>) -> Result<(), error::Error> {
+        let server = Server {
+            name: "test".to_string(),
+            address,
+            html_port: 0,
+            tls: true,
+            pin,
+        };
+        let stream = TcpStream::connect(address).await.unwrap();
+        connect(&server, stream).await.map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn pinned_certificate_is_accepted() {
+        let (address, pin) = serve("pinned").await;
+        // Typed with colons and in upper case.
+        let typed = pin
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| String::from_utf8_lossy(pair).to_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert!(handshake(address, Some(typed)).await.is_ok());
+        // A pin is not remembered as trusted on first use.
+        assert_eq!(known_fingerprint(&address), None);
+    }
+
+    #[tokio::test]
+    async fn other_pinned_certificate_is_refused() {
+        let (address, _) = serve("mismatch").await;
+        let err = handshake(address, Some("00".repeat(32))).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.name, "The server certificate does not match the pinned fingerprint");
+    }
+
+    #[tokio::test]
+    async fn first_certificate_is_trusted_and_then_required() {
+        let (address, pin) = serve("tofu").await;
+
+        assert!(handshake(address, None).await.is_ok());
+        assert_eq!(known_fingerprint(&address), Some(pin));
+        assert!(handshake(address, None).await.is_ok());
+
+        // As if the server presented a new certificate.
+        trust(&address, &"00".repeat(32)).unwrap();
+        let err = handshake(address, None).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.name, "The server certificate changed since the first connection");
+
+        forget(&address).unwrap();
+        assert!(handshake(address, None).await.is_ok());
+    }
+
+}
@@ -0,0 +1,224 @@
+use std::time::{Duration, Instant};
+
+use iced::{widget::{button, center, column, text}, Task};
+
+use crate::error::Error;
+use crate::shared::{discovery, req_resp};
+use crate::tls;
+use super::Tab;
+
+const TITLE: &str = "Connection";
+
+/// Time between health checks while the server answers.
+const ONLINE_INTERVAL: Duration = Duration::from_secs(10);
+/// First retry after the server went away, doubled on every
+/// failed check up to `MAX_BACKOFF`.
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default)]
+pub enum ConnectionStatus {
+    #[default]
+    Unknown,
+    Online,
+    Offline {
+        /// Failed checks in a row.
+        failures: u32,
+        error: Error,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum ConnectionMessage {
+    CheckNow,
+    Checked(Result<(), Error>),
+    /// Trusts the next certificate the server presents, after
+    /// it legitimately got a new one.
+    ForgetCertificate,
+}
+
+impl Into<crate::Message> for ConnectionMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::UI(super::UIMessage::Connection(self))
+    }
+}
+
+/// What changed with the last health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    None,
+    /// First successful check after login.
+    Connected,
+    WentOffline,
+    CameBack,
+}
+
+/// Periodic health check of the server, shown as the
+/// status badge in the tab bar.
+#[derive(Debug, Default)]
+pub struct ConnectionMonitor {
+    pub status: ConnectionStatus,
+    /// A check is running, don't start another.
+    checking:   bool,
+    last_check: Option<Instant>,
+    /// Fingerprint the server is checked against, and whether it
+    /// was pinned rather than trusted on first use.
+    certificate: Option<(String, bool)>,
+}
+
+impl ConnectionMonitor {
+
+    /// Time until the next health check, the subscription
+    /// in `App::subscription` runs on this.
+    pub fn interval(&self) -> Duration {
+        match &self.status {
+            ConnectionStatus::Unknown => BASE_BACKOFF,
+            ConnectionStatus::Online => ONLINE_INTERVAL,
+            ConnectionStatus::Offline { failures, .. } => BASE_BACKOFF
+                .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+                .min(MAX_BACKOFF),
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        matches!(self.status, ConnectionStatus::Offline { .. })
+    }
+
+    /// Text of the tab bar badge.
+    pub fn badge(&self) -> String {
+        match &self.status {
+            ConnectionStatus::Unknown => "● Connecting".to_string(),
+            ConnectionStatus::Online => "● Online".to_string(),
+            ConnectionStatus::Offline { .. } => "● Offline".to_string(),
+        }
+    }
+
+    pub fn update(&mut self, message: ConnectionMessage) -> (Task<crate::Message>, Transition) {
+        match message {
+            ConnectionMessage::CheckNow => {
+                if self.checking {
+                    return (Task::none(), Transition::None)
+                }
+                self.checking = true;
+                (
+                    Task::perform(
+                        req_resp::check_connection(),
+                        |result| ConnectionMessage::Checked(result).into()
+                    ),
+                    Transition::None
+                )
+            },
+            ConnectionMessage::ForgetCertificate => {
+                if let Ok(server) = discovery::server() {
+                    if let Err(err) = tls::forget(&server.address) {
+                        log::error!("{}", err);
+                    }
+                }
+                self.certificate = None;
+                (Task::done(ConnectionMessage::CheckNow.into()), Transition::None)
+            },
+            ConnectionMessage::Checked(result) => {
+                self.checking = false;
+                self.last_check = Some(Instant::now());
+                self.certificate = discovery::server().ok()
+                    .filter(|server| server.tls)
+                    .and_then(|server| match server.pin {
+                        Some(pin) => Some((tls::normalize(&pin), true)),
+                        None => tls::known_fingerprint(&server.address).map(|known| (known, false))
+                    });
+                let was_offline = self.is_offline();
+                let was_unknown = matches!(self.status, ConnectionStatus::Unknown);
+                let transition = match result {
+                    Ok(()) => {
+                        self.status = ConnectionStatus::Online;
+                        match (was_offline, was_unknown) {
+                            (true, _) => Transition::CameBack,
+                            (_, true) => Transition::Connected,
+                            _ => Transition::None
+                        }
+                    },
+                    Err(error) => {
+                        let failures = match &self.status {
+                            ConnectionStatus::Offline { failures, .. } => failures + 1,
+                            _ => 1
+                        };
+                        log::warn!("Health check failed ({}): {}", failures, error.summary());
+                        self.status = ConnectionStatus::Offline { failures, error };
+                        match was_offline {
+                            true => Transition::None,
+                            false => Transition::WentOffline
+                        }
+                    }
+                };
+                (Task::none(), transition)
+            },
+        }
+    }
+
+}
+
+impl Tab for ConnectionMonitor {
+
+    type Message = crate::Message;
+
+    fn title(&self) -> String {
+        TITLE.to_string()
+    }
+
+    fn inner_title(&self) -> iced::Element<'_, Self::Message> {
+        text!("{}", self.title()).size(26).into()
+    }
+
+    fn tab_label(&self) -> iced_aw::TabLabel {
+        iced_aw::TabLabel::Text(self.badge())
+    }
+
+    fn content(&self) -> iced::Element<'_, Self::Message> {
+
+        let status = match &self.status {
+            ConnectionStatus::Unknown => text!("Checking the server..."),
+            ConnectionStatus::Online => text!("The server is reachable.").style(text::success),
+            ConnectionStatus::Offline { failures, error } => text!(
+                "The server is not reachable ({} failed checks).\n{}",
+                failures,
+                error.summary()
+            ).style(text::danger),
+        };
+
+        let last_check = match self.last_check {
+            Some(instant) => format!("Last check {} seconds ago, next in {} seconds.",
+                instant.elapsed().as_secs(),
+                self.interval().as_secs()
+            ),
+            None => "Not checked yet.".to_string()
+        };
+
+        let certificate = self.certificate.as_ref().map(|(fingerprint, pinned)| column![
+            text!("Server certificate ({}):\n{}",
+                if *pinned { "pinned" } else { "trusted on first use" },
+                fingerprint
+            ).size(14),
+        ].push_maybe((!pinned).then(||
+            button("Forget certificate")
+                .style(button::secondary)
+                .on_press(ConnectionMessage::ForgetCertificate.into())
+        ))
+        .spacing(10)
+        .align_x(iced::Alignment::Center));
+
+        center(
+            column![
+                status,
+                text!("{}", last_check),
+                button("Check now").on_press_maybe(
+                    (!self.checking).then(|| ConnectionMessage::CheckNow.into())
+                ),
+            ]
+            .push_maybe(certificate)
+            .spacing(10)
+            .align_x(iced::Alignment::Center)
+        ).into()
+
+    }
+
+}
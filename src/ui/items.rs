@@ -10,11 +10,11 @@ const ICON:  char = '\u{e9ba}';
 
 #[derive(Debug, Clone)]
 pub enum ItemManagerMessage {
-    FetchedItems(Result<crate::server::FetchVirtualTables, String>),
+    FetchedItems(Result<crate::server::FetchVirtualTables, crate::error::Error>),
     DeleteItem(database_types::ItemID),
-    DeleteItemPost(Result<(), String>),
+    DeleteItemPost(Result<(), crate::error::Error>),
     AddItem(database_types::VirtualTableID),
-    AddItemPost(Result<(), String>),
+    AddItemPost(Result<(), crate::error::Error>),
     TextNameInput(String),
     TextDescriptionInput(String),
     TextPriceInput(String)
@@ -28,14 +28,14 @@ impl Into<crate::Message> for VirtualTableManagerMessage {
 
 
 pub struct VirtualTableManager {
-    pub fetch_vtables: Result<crate::server::FetchVirtualTables, String>,
+    pub fetch_vtables: Result<crate::server::FetchVirtualTables, crate::error::Error>,
     pub table_name: String
 }
 
 impl Default for VirtualTableManager {
     fn default() -> Self {
         Self { 
-            fetch_vtables: Err("Fetching data...".to_string()),
+            fetch_vtables: Err(crate::error::Error::pending("Fetching data...")),
             table_name: String::new()
         }
     }
@@ -207,7 +207,7 @@ impl Tab for VirtualTableManager {
     fn content(&self) -> iced::Element<'_, Self::Message> {
         
         if self.fetch_vtables.is_err() {
-            text!("{}", self.fetch_vtables.clone().expect_err("").to_string()).into()
+            text!("{}", self.fetch_vtables.as_ref().unwrap_err().summary()).into()
         } else {
 
             let mut col: Column<'_, crate::Message> = Column::new().spacing(20).padding(30);
@@ -0,0 +1,468 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use iced::{
+    border::Radius, keyboard, widget::{button, center, column, container, horizontal_space, row, scrollable, text, Column, Row}, Border, Length, Task, Theme
+};
+
+use crate::alerts::Alert;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::cache;
+use crate::error::{error_forward, Error, ErrorKind};
+use crate::outbox::QueuedChange;
+use crate::shared::{dbt, req_resp};
+use crate::user::{Age, Ageing};
+use crate::Message;
+use super::notify::Notifications;
+use super::orders::OpenOrders;
+use super::Tab;
+
+const TITLE: &str = "Kitchen";
+const ICON:  char = '\u{e9ba}';
+
+/// Tickets per row of the grid.
+const TICKETS_PER_ROW: usize = 4;
+/// Tickets that can be bumped with the number keys.
+const NUMBERED_TICKETS: usize = 9;
+/// How often the ages are redrawn, and the orders polled while
+/// the live sync is down.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub enum KitchenMessage {
+    Fetched(u64, Result<serde_json::Value, Error>),
+    /// Redraws the ages, and polls if `true`.
+    Refresh(bool),
+    Bump(dbt::OrderID),
+    /// Bumps the ticket at this place of the grid, from the keyboard.
+    BumpNth(usize),
+    Bumped(dbt::OrderID, Result<serde_json::Value, Error>, Box<QueuedChange>),
+}
+
+impl Into<crate::Message> for KitchenMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::UI(super::UIMessage::Kitchen(self))
+    }
+}
+
+/// Every open order of all tables as tickets, oldest first,
+/// for the screen in the kitchen. Only the items of the stations
+/// this terminal shows are listed, bumping marks them done.
+#[derive(Debug)]
+pub struct KitchenDisplay {
+    pub tickets: Result<Vec<dbt::Order>, Error>,
+    /// When each order was first shown, the age of orders the
+    /// server did not timestamp.
+    first_seen: HashMap<(dbt::VirtualTableID, u32), Instant>,
+    /// Overdue orders already alerted about.
+    alerted: HashSet<(dbt::VirtualTableID, u32)>,
+    /// Colours the tickets and alerts, from the user settings.
+    pub ageing: Ageing,
+    /// Save time of the cached orders shown while offline.
+    pub cached: Option<String>,
+    pub request: super::RequestSlot,
+}
+
+impl Default for KitchenDisplay {
+    fn default() -> Self {
+        Self {
+            tickets: Err(Error::pending("Fetching orders...")),
+            first_seen: HashMap::new(),
+            alerted: HashSet::new(),
+            ageing: Ageing::default(),
+            cached: None,
+            request: super::RequestSlot::default(),
+        }
+    }
+}
+
+fn key(id: &dbt::OrderID) -> (dbt::VirtualTableID, u32) {
+    (id.table.clone(), id.count)
+}
+
+impl KitchenDisplay {
+
+    /// Redraws the ages, and polls unless the live sync keeps the
+    /// tickets up to date. The number keys bump the numbered
+    /// tickets, space or enter the oldest.
+    pub fn subscription(&self, live: bool) -> iced::Subscription<Message> {
+        iced::Subscription::batch([
+            iced::time::every(REFRESH_INTERVAL).with(live).map(|(live, _)| KitchenMessage::Refresh(!live).into()),
+            keyboard::on_key_press(|key, modifiers| {
+                if modifiers.command() || modifiers.alt() {
+                    return None
+                }
+                match key.as_ref() {
+                    keyboard::Key::Named(keyboard::key::Named::Space) |
+                    keyboard::Key::Named(keyboard::key::Named::Enter) => Some(KitchenMessage::BumpNth(0).into()),
+                    keyboard::Key::Character(c) => match c.parse::<usize>() {
+                        Ok(n) if (1..=NUMBERED_TICKETS).contains(&n) => Some(KitchenMessage::BumpNth(n - 1).into()),
+                        _ => None
+                    },
+                    _ => None
+                }
+            }),
+        ])
+    }
+
+    pub fn fetch(&mut self) -> Task<Message> {
+        self.request.start(|generation| {
+            let request = req_resp::Request::encode(req_resp::RequestKind::Orders, &req_resp::OrdersRequestData {
+                new: true,
+                table: None
+            });
+            Task::perform(
+                async move {request?.send_request("".to_string()).await},
+                move |result| KitchenMessage::Fetched(generation, result).into()
+            )
+        })
+    }
+
+    /// Replaces the tickets, keeping the age of those already shown.
+    fn show(&mut self, mut orders: Vec<dbt::Order>) {
+        let now = Instant::now();
+        self.first_seen.retain(|seen, _| orders.iter().any(|order| key(&order.id) == *seen));
+        self.alerted.retain(|seen| orders.iter().any(|order| key(&order.id) == *seen));
+        for order in orders.iter() {
+            self.first_seen.entry(key(&order.id)).or_insert(now);
+        }
+        orders.retain(|order| !order.finished);
+        // Stable, orders of the same age keep the server's order.
+        orders.sort_by_key(|order| Reverse(self.age(order)));
+        self.tickets = Ok(orders);
+    }
+
+    /// Time since the server took the order or, without its
+    /// timestamp, since the ticket was first shown.
+    fn age(&self, order: &dbt::Order) -> Duration {
+        order.age().unwrap_or_else(||
+            self.first_seen.get(&key(&order.id)).map_or(Duration::ZERO, Instant::elapsed)
+        )
+    }
+
+    /// Alerts once about every ticket that became overdue.
+    fn alert_overdue(&mut self, notify: &mut Notifications) {
+        let overdue: Vec<(dbt::VirtualTableID, u32)> = self.visible()
+            .into_iter()
+            .filter(|order| self.ageing.of(self.age(order)) == Age::Overdue)
+            .map(|order| key(&order.id))
+            .filter(|order| !self.alerted.contains(order))
+            .collect();
+        for (table, count) in overdue {
+            self.alerted.insert((table.clone(), count));
+            notify.alert(Alert::Overdue {
+                order: dbt::OrderID { table, count },
+                minutes: self.ageing.overdue.as_secs() / 60
+            });
+        }
+    }
+
+    /// Tickets with items left for the stations of this terminal.
+    fn visible(&self) -> Vec<&dbt::Order> {
+        let all = crate::stations::current().is_empty();
+        match &self.tickets {
+            Ok(tickets) => tickets
+                .iter()
+                .filter(|order| all || !crate::stations::open_here(order).is_empty())
+                .collect(),
+            Err(_) => Vec::new()
+        }
+    }
+
+    /// Shows `order` as changed by a bump until the server confirms.
+    fn replace(&mut self, order: dbt::Order) {
+        if order.finished {
+            return self.remove(&order.id)
+        }
+        if let Ok(tickets) = &mut self.tickets {
+            if let Some(ticket) = tickets.iter_mut().find(|ticket| key(&ticket.id) == key(&order.id)) {
+                *ticket = order;
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &dbt::OrderID) {
+        if let Ok(tickets) = &mut self.tickets {
+            tickets.retain(|order| key(&order.id) != key(id));
+        }
+        self.first_seen.remove(&key(id));
+        self.alerted.remove(&key(id));
+    }
+
+    /// Applies a change made on the server to the tickets.
+    pub fn apply_change(&mut self, change: &req_resp::Change) {
+        match change {
+            req_resp::Change::OrderAdded { order } |
+            req_resp::Change::OrderChanged { order } if !order.finished && !order.pending => {
+                self.first_seen.entry(key(&order.id)).or_insert_with(Instant::now);
+                if let Ok(tickets) = &mut self.tickets {
+                    match tickets.iter_mut().find(|ticket| key(&ticket.id) == key(&order.id)) {
+                        Some(ticket) => *ticket = order.clone(),
+                        None => tickets.push(order.clone())
+                    }
+                }
+            },
+            req_resp::Change::OrderAdded { order } |
+            req_resp::Change::OrderChanged { order } => self.remove(&order.id),
+            req_resp::Change::OrderFinished { order } |
+            req_resp::Change::OrderDenied { order, .. } => self.remove(order),
+            req_resp::Change::OrderMoved { from, order } => {
+                self.remove(from);
+                self.apply_change(&req_resp::Change::OrderChanged { order: order.clone() });
+            },
+            req_resp::Change::TableDeleted { table } => {
+                if let Ok(tickets) = &mut self.tickets {
+                    tickets.retain(|order| &order.id.table != table);
+                }
+                self.first_seen.retain(|(seen, _), _| seen != table);
+            },
+            _ => {}
+        }
+    }
+
+    pub fn update(&mut self, message: KitchenMessage, username: &str, notify: &mut Notifications) -> Task<Message> {
+
+        match message {
+            KitchenMessage::Fetched(generation, result) => {
+                if !self.request.finish(generation) {
+                    return Task::none()
+                }
+                let fetched = result
+                    .and_then(req_resp::decode::<req_resp::OrdersResponseData>)
+                    .map(|payload| {
+                        let mut open = OpenOrders::new();
+                        for order in payload.orders {
+                            open.entry(order.id.table.clone()).or_default().push(order);
+                        }
+                        open
+                    });
+                let (open, saved) = cache::fallback(cache::ORDERS, fetched);
+                self.cached = saved;
+                match open {
+                    Ok(open) => self.show(open.into_values().flatten().collect()),
+                    Err(err) => {
+                        notify.error(&err, Some(super::UIMessage::TabSelected(super::UITabID::Kitchen).into()));
+                        self.tickets = Err(err);
+                    }
+                }
+            },
+            KitchenMessage::Refresh(poll) => {
+                self.alert_overdue(notify);
+                if poll && !self.request.is_running() {
+                    return self.fetch()
+                }
+            },
+            KitchenMessage::BumpNth(n) => {
+                if let Some(order) = self.visible().get(n) {
+                    return Task::done(KitchenMessage::Bump(order.id.clone()).into())
+                }
+            },
+            KitchenMessage::Bump(order_id) => {
+                let Some(before) = self.tickets.as_ref().ok()
+                    .and_then(|tickets| tickets.iter().find(|order| key(&order.id) == key(&order_id)))
+                    .cloned() else {
+                    return Task::none()
+                };
+                // A terminal for some stations only marks its part done,
+                // the server finishes the order after the last one.
+                if !crate::stations::current().is_empty() {
+                    let stations = crate::stations::open_here(&before);
+                    if stations.is_empty() {
+                        return Task::none()
+                    }
+                    let (mut request, key, change) = match crate::stations::done_request(&before, stations.clone(), username) {
+                        Ok(done) => done,
+                        Err(err) => {
+                            notify.error(&err, None);
+                            return Task::none()
+                        }
+                    };
+                    let mut after = before;
+                    after.stations_done.extend(stations);
+                    self.replace(after);
+                    return Task::perform(
+                        async move {request.send_request_with_key("".to_string(), Some(key)).await},
+                        move |result| KitchenMessage::Bumped(order_id.clone(), result, change.clone()).into()
+                    )
+                }
+                let after = dbt::Order { finished: true, ..before.clone() };
+                let audit = AuditEntry::new(
+                    username,
+                    AuditAction::FinishOrder,
+                    &format!("{} #{}", order_id.table, order_id.count),
+                    Some(&before),
+                    Some(&after)
+                );
+                let mut request = match req_resp::Request::encode(req_resp::RequestKind::OrdersFinish, &req_resp::OrdersFinishRequestData {
+                    order: dbt::Order {
+                        id: order_id.clone(),
+                        version: before.version,
+                        ..Default::default()
+                    }
+                }) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        notify.error(&err, None);
+                        return Task::none()
+                    }
+                };
+                let key = req_resp::new_idempotency_key();
+                let change = Box::new(QueuedChange::new(key.clone(), &request, "".to_string(), audit));
+                // Gone from the grid right away, a bump must not
+                // need a second press.
+                self.remove(&order_id);
+                return Task::perform(
+                    async move {request.send_request_with_key("".to_string(), Some(key)).await},
+                    move |result| KitchenMessage::Bumped(order_id.clone(), result, change.clone()).into()
+                )
+            },
+            KitchenMessage::Bumped(order_id, result, change) => match result {
+                Ok(_) => change.audit.commit(),
+                Err(err) if err.kind == ErrorKind::Network => {
+                    if !super::orders::queue_offline(change, &order_id, notify) {
+                        return self.fetch()
+                    }
+                },
+                Err(err) if err.kind == ErrorKind::Conflict => {
+                    notify.error(&super::orders::conflict(err, &order_id, "The tickets"), None);
+                    return self.fetch()
+                },
+                Err(err) => {
+                    // Back on the grid, it is still open.
+                    notify.error(&error_forward!(err), None);
+                    return self.fetch()
+                },
+            },
+        }
+
+        Task::none()
+
+    }
+
+    fn ticket<'a>(&self, place: usize, order: &'a dbt::Order) -> iced::Element<'a, Message> {
+
+        let age = self.age(order);
+        let ageing = self.ageing;
+
+        let items = order.items.iter().filter(|item| crate::stations::shows(&item.station)).fold(
+            Column::new().spacing(6),
+            |items, item| items.push(match item.status {
+                dbt::ItemStatus::Queued => text!("{} × {}", item.count, item.id),
+                status => text!("{} × {} ({})", item.count, item.id, status),
+            }.size(22))
+        );
+
+        let others: Vec<String> = order.stations()
+            .into_iter()
+            .filter(|station| !crate::stations::shows(station))
+            .map(|station| match order.stations_done.contains(station) {
+                true => format!("{} ✓", station),
+                false => station.clone()
+            })
+            .collect();
+
+        let bump = match place < NUMBERED_TICKETS {
+            true => format!("Bump [{}]", place + 1),
+            false => "Bump".to_string()
+        };
+
+        container(
+            column![
+                row![
+                    text!("{} #{}", order.id.table, order.id.count).size(24).font(iced::font::Font {
+                        weight: iced::font::Weight::Bold,
+                        ..Default::default()
+                    }),
+                    horizontal_space(),
+                    text!("{}:{:02}", age.as_secs() / 60, age.as_secs() % 60).size(24),
+                ],
+                items,
+            ]
+            .push_maybe((!others.is_empty()).then(|| text!("Also: {}", others.join(", ")).size(16)))
+            .push(
+                button(text(bump).size(20))
+                    .width(Length::Fill)
+                    .style(button::secondary)
+                    .on_press(KitchenMessage::Bump(order.id.clone()).into())
+            )
+            .spacing(12)
+        )
+        .padding(14)
+        .width(Length::FillPortion(1))
+        .style(move |theme: &Theme| ticket_style(theme, ageing.of(age)))
+        .into()
+
+    }
+
+}
+
+impl Tab for KitchenDisplay {
+
+    type Message = crate::Message;
+
+    fn title(&self) -> String {
+        TITLE.to_string()
+    }
+
+    fn inner_title(&self) -> iced::Element<'_, Self::Message> {
+        let stations = crate::stations::current();
+        match stations.is_empty() {
+            true => text!("{} open orders", self.visible().len()),
+            false => text!("{} open orders for {}", self.visible().len(), stations.join(", ")),
+        }.size(26).into()
+    }
+
+    fn tab_label(&self) -> iced_aw::TabLabel {
+        iced_aw::TabLabel::IconText(ICON, self.title())
+    }
+
+    fn content(&self) -> iced::Element<'_, Self::Message> {
+
+        if let Err(err) = &self.tickets {
+            return center(text!("{}", err.summary())).into()
+        }
+
+        let tickets = self.visible();
+
+        if tickets.is_empty() {
+            return center(text!("Nothing to cook.").size(26)).into()
+        }
+
+        let mut grid: Column<'_, Message> = Column::new().spacing(14).padding(10);
+        for (row_index, chunk) in tickets.chunks(TICKETS_PER_ROW).enumerate() {
+            let mut tickets_row: Row<'_, Message> = Row::new().spacing(14);
+            for (index, order) in chunk.iter().enumerate() {
+                tickets_row = tickets_row.push(self.ticket(row_index * TICKETS_PER_ROW + index, order));
+            }
+            // Keep the tickets of a short last row the same width.
+            for _ in chunk.len()..TICKETS_PER_ROW {
+                tickets_row = tickets_row.push(horizontal_space().width(Length::FillPortion(1)));
+            }
+            grid = grid.push(tickets_row);
+        }
+
+        column![]
+            .push_maybe(self.cached.as_deref().map(super::offline_banner))
+            .push(scrollable(grid).height(Length::Fill))
+            .spacing(10)
+            .into()
+
+    }
+
+}
+
+fn ticket_style(theme: &Theme, age: Age) -> container::Style {
+
+    container::Style {
+        text_color: Some(theme.extended_palette().background.base.text),
+        background: Some(iced::Background::Color(theme.extended_palette().background.weak.color)),
+        border: Border {
+            color: super::age_color(theme, age),
+            width: 4.0,
+            radius: Radius::new(7)
+        },
+        ..Default::default()
+    }
+
+}
@@ -0,0 +1,267 @@
+use iced::{border::Radius, widget::{button, center, column, container, horizontal_space, pick_list, row, scrollable, text, text_input, Column}, Border, Length, Theme};
+
+use crate::audit::{self, AuditAction, AuditEntry};
+use crate::security::{self, SecurityEvent};
+use crate::error::Error;
+use super::Tab;
+
+const TITLE: &str = "Logs";
+const ICON:  char = '\u{e9ba}';
+
+/// Which log the tab shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogSource {
+    #[default]
+    Audit,
+    Security,
+}
+
+/// Audit entries shown, all or one action type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ActionFilter {
+    #[default]
+    All,
+    Only(AuditAction),
+}
+
+impl ActionFilter {
+    fn options() -> Vec<ActionFilter> {
+        std::iter::once(ActionFilter::All)
+            .chain(AuditAction::ALL.into_iter().map(ActionFilter::Only))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for ActionFilter {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionFilter::All => write!(f, "All actions"),
+            ActionFilter::Only(action) => write!(f, "{}", action),
+        }
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub enum LogViewerMessage {
+    Refresh,
+    SourceSelected(LogSource),
+    UsernameFilterChanged(String),
+    ActionFilterChanged(ActionFilter),
+}
+
+impl Into<crate::Message> for LogViewerMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::UI(super::UIMessage::LogViewer(self))
+    }
+}
+
+/// Admin only tab for reviewing the audit and security logs.
+pub struct LogViewer {
+    pub source: LogSource,
+    pub audit_entries: Result<Vec<AuditEntry>, Error>,
+    pub security_events: Result<Vec<SecurityEvent>, Error>,
+    pub username_filter: String,
+    pub action_filter: ActionFilter,
+}
+
+impl Default for LogViewer {
+    fn default() -> Self {
+        Self {
+            source: LogSource::default(),
+            audit_entries: Err(Error::pending("Fetching data...")),
+            security_events: Err(Error::pending("Fetching data...")),
+            username_filter: String::new(),
+            action_filter: ActionFilter::default(),
+        }
+    }
+}
+
+impl LogViewer {
+
+    pub fn update(&mut self, message: LogViewerMessage) {
+        match message {
+            LogViewerMessage::Refresh => {
+                self.audit_entries = audit::read_log();
+                self.security_events = security::read_log();
+            },
+            LogViewerMessage::SourceSelected(source) => self.source = source,
+            LogViewerMessage::UsernameFilterChanged(username) => self.username_filter = username,
+            LogViewerMessage::ActionFilterChanged(filter) => self.action_filter = filter,
+        }
+    }
+
+    fn matches_username(&self, username: &str) -> bool {
+        self.username_filter.is_empty() || username.contains(&self.username_filter)
+    }
+
+    fn audit_list(&self) -> iced::Element<'_, crate::Message> {
+
+        let entries = match &self.audit_entries {
+            Ok(entries) => entries,
+            Err(err) => return center(text!("{}", err.summary())).into()
+        };
+
+        let shown = entries
+            .iter()
+            .rev()
+            .filter(|entry| self.matches_username(&entry.username))
+            .filter(|entry| match self.action_filter {
+                ActionFilter::All => true,
+                ActionFilter::Only(action) => entry.action == action,
+            });
+
+        let mut col: Column<'_, crate::Message> = Column::new().spacing(10).padding(30);
+
+        for entry in shown {
+            col = col.push(
+                container(
+                    column![
+                        row![
+                            text!("{}", entry.action).font(iced::font::Font {
+                                weight: iced::font::Weight::Bold,
+                                ..Default::default()
+                            }),
+                            horizontal_space(),
+                            text!("{}", entry.time)
+                        ],
+                        text!("User: `{}`, target: `{}`", entry.username, entry.target),
+                        text!("Before: {}", payload(&entry.before)),
+                        text!("After: {}", payload(&entry.after)),
+                    ]
+                )
+                .padding(10)
+                .style(log_entry_container_style)
+                .width(Length::Fill)
+            );
+        }
+
+        scrollable(col).width(Length::Fill).height(Length::Fill).into()
+
+    }
+
+    fn security_list(&self) -> iced::Element<'_, crate::Message> {
+
+        let events = match &self.security_events {
+            Ok(events) => events,
+            Err(err) => return center(text!("{}", err.summary())).into()
+        };
+
+        let shown = events
+            .iter()
+            .rev()
+            .filter(|event| self.matches_username(&event.username));
+
+        let mut col: Column<'_, crate::Message> = Column::new().spacing(10).padding(30);
+
+        for event in shown {
+            col = col.push(
+                container(
+                    column![
+                        row![
+                            text!("{}", event.kind).font(iced::font::Font {
+                                weight: iced::font::Weight::Bold,
+                                ..Default::default()
+                            }),
+                            horizontal_space(),
+                            text!("{}", event.time)
+                        ],
+                        text!("User: `{}`", event.username),
+                        text!("{}", event.detail)
+                    ]
+                )
+                .padding(10)
+                .style(log_entry_container_style)
+                .width(Length::Fill)
+            );
+        }
+
+        scrollable(col).width(Length::Fill).height(Length::Fill).into()
+
+    }
+
+}
+
+fn payload(value: &Option<serde_json::Value>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".to_string()
+    }
+}
+
+fn log_entry_container_style(theme: &Theme) -> iced::widget::container::Style {
+
+    iced::widget::container::Style {
+        text_color: Some(theme.extended_palette().secondary.base.text),
+        background: Some(iced::Background::Color(theme.extended_palette().secondary.base.color)),
+        border: Border {
+            color: theme.extended_palette().primary.base.color,
+            width: 0.0,
+            radius: Radius::new(7)
+        },
+        ..Default::default()
+    }
+
+}
+
+impl Tab for LogViewer {
+
+    type Message = crate::Message;
+
+    fn title(&self) -> String {
+        TITLE.to_string()
+    }
+
+    fn inner_title(&self) -> iced::Element<'_, Self::Message> {
+        text!("{}", match self.source {
+            LogSource::Audit => "Audit log",
+            LogSource::Security => "Security log",
+        }).size(26).into()
+    }
+
+    fn tab_label(&self) -> iced_aw::TabLabel {
+        iced_aw::TabLabel::IconText(ICON, self.title())
+    }
+
+    fn content(&self) -> iced::Element<'_, Self::Message> {
+
+        let source_button = |label, source| {
+            button(label)
+                .style(if self.source == source { button::primary } else { button::secondary })
+                .on_press(LogViewerMessage::SourceSelected(source).into())
+        };
+
+        let filters = row![
+            source_button("Audit", LogSource::Audit),
+            source_button("Security", LogSource::Security),
+            text_input("Filter by username", &self.username_filter)
+                .on_input(|s| LogViewerMessage::UsernameFilterChanged(s).into()),
+        ]
+        .push_maybe(match self.source {
+            LogSource::Audit => Some(pick_list(
+                ActionFilter::options(),
+                Some(self.action_filter),
+                |filter| LogViewerMessage::ActionFilterChanged(filter).into()
+            )),
+            LogSource::Security => None,
+        })
+        .push(
+            button("Refresh")
+                .on_press(LogViewerMessage::Refresh.into())
+        )
+        .spacing(10);
+
+        column![
+            filters,
+            match self.source {
+                LogSource::Audit => self.audit_list(),
+                LogSource::Security => self.security_list(),
+            }
+        ]
+        .spacing(10)
+        .into()
+
+    }
+
+}
@@ -1,5 +1,9 @@
+use crate::alerts::Alert;
+use crate::error::{Error, ErrorKind};
+use crate::outbox::{self, QueuedChange, Replayed};
 use crate::shared::req_resp;
 use crate::table::{self, VirtualTable};
+use crate::user::UserPrivilege;
 use crate::Message;
 use iced::{widget::{center, container, pane_grid::{self, Axis, Pane}, text, Column}, Alignment::Center, Color, Element, Font, Length::Fill, Task, Theme};
 use iced_aw::{direction::{Horizontal, Vertical}, style::tab_bar::dark, tab_bar::{self, Style}, tabs::tab_bar_position, TabBarPosition, TabLabel, Tabs};
@@ -9,11 +13,21 @@ use iced_aw::{direction::{Horizontal, Vertical}, style::tab_bar::dark, tab_bar::
 
 pub mod settings;
 pub mod orders;
+pub mod kitchen;
 pub mod offers;
 pub mod vtable;
+pub mod logs;
+pub mod notify;
+pub mod connection;
+pub mod sync;
 // pub mod items;
 
 use offers::{OfferManager, OfferManagerMessage};
+use kitchen::{KitchenDisplay, KitchenMessage};
+use logs::{LogViewer, LogViewerMessage};
+use notify::{Notifications, NotificationMessage};
+use connection::{ConnectionMessage, ConnectionMonitor, Transition};
+use sync::{LiveSync, Received, SyncMessage};
 use settings::{
     Settings,
     SettingsMessage
@@ -48,8 +62,11 @@ pub enum UITabID {
     #[default]  
     Settings,
     Orders,
+    Kitchen,
     VirtualTableManager,
-    OfferManager
+    OfferManager,
+    LogViewer,
+    Connection
 }
 
 #[derive(Debug, Clone)]
@@ -57,8 +74,16 @@ pub enum UIMessage {
     TabSelected(UITabID),
     Settings(SettingsMessage),
     Orders(OrderListMessage),
+    Kitchen(KitchenMessage),
     VirtualTableManager(VirtualTableManagerMessage),
-    OfferManager(OfferManagerMessage)
+    OfferManager(OfferManagerMessage),
+    LogViewer(LogViewerMessage),
+    Notifications(NotificationMessage),
+    Connection(ConnectionMessage),
+    Sync(SyncMessage),
+    /// Sends the next change queued while offline.
+    ReplayOutbox,
+    Replayed(Box<QueuedChange>, Result<serde_json::Value, Error>)
 }
 
 impl Into<crate::Message> for UIMessage {
@@ -74,7 +99,16 @@ pub struct UI {
     pub vtable: VirtualTableManager,
     pub offers: OfferManager,
     pub settings: Settings,
-    pub orders: OrderList
+    pub orders: OrderList,
+    pub kitchen: KitchenDisplay,
+    pub logs: LogViewer,
+    pub notifications: Notifications,
+    pub connection: ConnectionMonitor,
+    pub sync: LiveSync,
+    /// A queued change is being sent.
+    replaying: bool,
+    /// Queued changes sent since the connection came back.
+    replayed: u32
 }
 
 pub fn tab_style(theme: &Theme, status: iced_aw::style::status::Status) -> Style {
@@ -116,43 +150,28 @@ pub fn tab_style(theme: &Theme, status: iced_aw::style::status::Status) -> Style
 
 impl UI {
 
+    /// Hands the user settings the tabs work with to them, after
+    /// the login and when they change.
+    pub fn apply_settings(&mut self) {
+        let ageing = self.settings.user_settings.ageing();
+        self.orders.ageing = ageing;
+        self.kitchen.ageing = ageing;
+        self.orders.tax = self.settings.user_settings.tax();
+        self.notifications.alerts = self.settings.user_settings.alerts();
+    }
+
     pub fn update(&mut self, message: UIMessage) -> Task<Message> {
         match message {
             UIMessage::TabSelected(tab_id) => {
+                if self.active_tab != tab_id {
+                    let previous = self.active_tab.clone();
+                    self.cancel_tab(&previous);
+                }
                 self.active_tab = tab_id.clone();
-                match tab_id {
-                    UITabID::Orders => {
-                        let mut request = req_resp::Request {
-                            kind: req_resp::RequestKind::OffersTables,
-                            payload: None
-                        };
-                        return Task::perform(
-                            async move {request.send_request("".to_string()).await}, 
-                            |value| {OrderListMessage::FetchedVirtualTablesAndItems(value).into()}
-                        )
-                    },
-                    UITabID::VirtualTableManager => {
-                        let mut request = req_resp::Request {
-                            kind: req_resp::RequestKind::Tables,
-                            payload: None
-                        };
-                        return Task::perform(
-                            async move {request.send_request("".to_string()).await}, 
-                            |value| {VirtualTableManagerMessage::FetchedVirtualTables(value).into()}
-                        )
-                    },
-                    UITabID::OfferManager => {
-                        let mut request = req_resp::Request {
-                            kind: req_resp::RequestKind::Offers,
-                            payload: None
-                        };
-                        return Task::perform(
-                            async move {request.send_request("".to_string()).await}, 
-                            |value| {OfferManagerMessage::FetchedOffers(value).into()}
-                        )
-                    }
-                    _ => {}
+                if tab_id == UITabID::LogViewer {
+                    self.logs.update(LogViewerMessage::Refresh);
                 }
+                return self.fetch_tab(&tab_id)
             }
             UIMessage::Settings(setting_message) => {
                 match setting_message {
@@ -162,28 +181,285 @@ impl UI {
                     SettingsMessage::TabPositionChanged(tab_bar_position) => {
                         self.settings.user_settings.tab_bar_position = tab_bar_position
                     },
+                    SettingsMessage::AutoLockChanged(minutes) => {
+                        self.settings.user_settings.auto_lock_minutes = minutes
+                    },
+                    SettingsMessage::StartModeChanged(mode) => {
+                        self.settings.user_settings.start_mode = mode
+                    },
+                    // Overdue stays after late, the other moves along.
+                    SettingsMessage::LateAfterChanged(minutes) => {
+                        let settings = &mut self.settings.user_settings;
+                        settings.late_after_minutes = minutes;
+                        if settings.overdue_after_minutes <= minutes {
+                            settings.overdue_after_minutes = crate::user::AGEING_CHOICES
+                                .into_iter()
+                                .find(|choice| *choice > minutes)
+                                .unwrap_or(minutes);
+                        }
+                        self.apply_settings();
+                    },
+                    SettingsMessage::OverdueAfterChanged(minutes) => {
+                        let settings = &mut self.settings.user_settings;
+                        settings.overdue_after_minutes = minutes;
+                        if settings.late_after_minutes >= minutes {
+                            settings.late_after_minutes = crate::user::AGEING_CHOICES
+                                .into_iter()
+                                .rev()
+                                .find(|choice| *choice < minutes)
+                                .unwrap_or(minutes);
+                        }
+                        self.apply_settings();
+                    },
+                    SettingsMessage::OverdueAlertToggled(alert) => {
+                        self.settings.user_settings.overdue_alert = alert;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::NewOrderAlertToggled(alert) => {
+                        self.settings.user_settings.new_order_alert = alert;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::DeniedAlertToggled(alert) => {
+                        self.settings.user_settings.denied_alert = alert;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::DesktopNotificationsToggled(desktop) => {
+                        self.settings.user_settings.desktop_notifications = desktop;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::MutedToggled(muted) => {
+                        self.settings.user_settings.muted = muted;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::VolumeChanged(volume) => {
+                        self.settings.user_settings.sound_volume = volume;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::QuietHoursToggled(quiet) => {
+                        self.settings.user_settings.quiet_hours = quiet;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::QuietFromChanged(hour) => {
+                        self.settings.user_settings.quiet_from_hour = hour;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::QuietUntilChanged(hour) => {
+                        self.settings.user_settings.quiet_until_hour = hour;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::TaxChanged(percent) => {
+                        self.settings.user_settings.tax_percent = percent;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::PricesIncludeTaxToggled(included) => {
+                        self.settings.user_settings.prices_include_tax = included;
+                        self.apply_settings();
+                    },
+                    SettingsMessage::TestSound => {
+                        crate::alerts::play(
+                            &Alert::NewOrder { table: String::new(), count: None },
+                            self.settings.user_settings.sound_volume
+                        );
+                        return Task::none()
+                    },
+                    SettingsMessage::PinFieldChanged(pin) => {
+                        if pin.chars().all(|c| c.is_ascii_digit()) {
+                            self.settings.pin_text_input = pin;
+                        }
+                        return Task::none()
+                    },
+                    SettingsMessage::SetPin => {
+                        self.settings.set_pin();
+                        return Task::none()
+                    },
+                    SettingsMessage::StationsFieldChanged(stations) => {
+                        self.settings.stations_text_input = stations;
+                        return Task::none()
+                    },
+                    SettingsMessage::SetStations => {
+                        self.settings.set_stations();
+                        return Task::none()
+                    },
+                }
+                if let Err(err) = self.settings.save() {
+                    log::error!("{}", err);
+                    self.settings.warnings.push(format!(
+                        "Your settings could not be saved.\n{}: {}",
+                        err.name, err.message
+                    ));
                 }
             }
             UIMessage::Orders(orders_message) => {
-                let task = OrderList::update(&mut self.orders, orders_message);
+                let task = OrderList::update(&mut self.orders, orders_message, &self.settings.user_data.username, &mut self.notifications);
                 return task;
             },
+            UIMessage::Kitchen(message) => {
+                return self.kitchen.update(message, &self.settings.user_data.username, &mut self.notifications)
+            },
             UIMessage::VirtualTableManager(message) => {
-                let task = VirtualTableManager::update(&mut self.vtable, message);
+                let task = VirtualTableManager::update(&mut self.vtable, message, &self.settings.user_data.username, &mut self.notifications);
                 return task;
             },
             UIMessage::OfferManager(message) => {
-                let task = OfferManager::update(&mut self.offers, message);
+                let task = OfferManager::update(&mut self.offers, message, &self.settings.user_data.username, &mut self.notifications);
                 return task;
             },
+            UIMessage::LogViewer(message) => self.logs.update(message),
+            UIMessage::Connection(message) => {
+                let (task, transition) = self.connection.update(message);
+                match (transition, &self.connection.status) {
+                    (Transition::WentOffline, connection::ConnectionStatus::Offline { error, .. }) => {
+                        self.notifications.error(error, Some(ConnectionMessage::CheckNow.into()));
+                    },
+                    (Transition::CameBack, _) => {
+                        self.notifications.success("Connection restored");
+                        // Everything is fetched anew, follow from now on.
+                        self.sync.restart();
+                        return Task::batch([
+                            task,
+                            Task::done(UIMessage::ReplayOutbox.into()),
+                            self.refetch_all(),
+                            self.sync.follow(),
+                        ])
+                    },
+                    (Transition::Connected, _) => {
+                        return Task::batch([task, Task::done(UIMessage::ReplayOutbox.into()), self.sync.follow()])
+                    },
+                    _ => {}
+                }
+                return task
+            },
+            UIMessage::Sync(SyncMessage::Follow) => {
+                if self.connection.is_offline() {
+                    return Task::none()
+                }
+                return self.sync.follow()
+            },
+            UIMessage::Sync(SyncMessage::Received(result)) => {
+                match self.sync.received(result) {
+                    Received::Changes(changes) => {
+                        let mut tasks = vec![self.sync.follow()];
+                        for change in changes.iter() {
+                            match change {
+                                req_resp::Change::OrderAdded { order } if !order.finished => self.notifications.alert(Alert::NewOrder {
+                                    table: order.id.table.clone(),
+                                    count: Some(order.id.count)
+                                }),
+                                req_resp::Change::OrderDenied { order, reason } => self.notifications.alert(Alert::Denied {
+                                    order: order.clone(),
+                                    reason: reason.clone()
+                                }),
+                                _ => {}
+                            }
+                            self.vtable.apply_change(change);
+                            self.offers.apply_change(change);
+                            self.kitchen.apply_change(change);
+                            tasks.push(self.orders.apply_change(change));
+                        }
+                        return Task::batch(tasks)
+                    },
+                    Received::Gap => return Task::batch([self.refetch_all(), self.sync.follow()]),
+                    // The health check reports the server being away,
+                    // the subscription follows again once it is back.
+                    Received::Failed(err) if err.kind == ErrorKind::Network => {
+                        log::warn!("Live sync interrupted: {}", err.summary());
+                    },
+                    Received::Failed(err) => {
+                        self.notifications.error(&err, Some(SyncMessage::Follow.into()));
+                    },
+                }
+            },
+            UIMessage::ReplayOutbox => {
+                if self.replaying || self.connection.is_offline() {
+                    return Task::none()
+                }
+                let next = match outbox::next() {
+                    Ok(next) => next,
+                    Err(err) => {
+                        self.notifications.error(&err, Some(UIMessage::ReplayOutbox.into()));
+                        return Task::none()
+                    }
+                };
+                let Some(change) = next else {
+                    if self.replayed == 0 {
+                        return Task::none()
+                    }
+                    self.notifications.success(format!("{} queued changes sent", self.replayed));
+                    self.replayed = 0;
+                    return self.refetch_all()
+                };
+                self.replaying = true;
+                return Task::perform(
+                    change.clone().send(),
+                    move |result| UIMessage::Replayed(Box::new(change.clone()), result).into()
+                )
+            },
+            UIMessage::Replayed(change, result) => {
+                self.replaying = false;
+                match outbox::settle(&change, result) {
+                    Ok(Replayed::Sent) => {
+                        change.audit.commit();
+                        self.replayed += 1;
+                    },
+                    // Still offline, try again once the connection is back.
+                    Ok(Replayed::Offline) => return Task::none(),
+                    Ok(Replayed::Rejected(mut err)) => {
+                        err.message = format!("{} (queued at {})", err.summary(), change.queued);
+                        err.name = format!("Queued change rejected: {} `{}`", change.audit.action, change.audit.target);
+                        self.notifications.error(&err, None);
+                    },
+                    Err(err) => {
+                        self.notifications.error(&err, None);
+                        return Task::none()
+                    }
+                }
+                return Task::done(UIMessage::ReplayOutbox.into())
+            },
+            UIMessage::Notifications(message) => {
+                if let Some(retry) = self.notifications.update(message) {
+                    return Task::done(retry)
+                }
+            },
         }
 
         return Task::none()
     }
 
+    fn refetch_all(&mut self) -> Task<Message> {
+        Task::batch([
+            self.fetch_tab(&UITabID::Orders),
+            self.fetch_tab(&UITabID::Kitchen),
+            self.fetch_tab(&UITabID::VirtualTableManager),
+            self.fetch_tab(&UITabID::OfferManager),
+        ])
+    }
+
+    /// Fetches the server data shown by `tab`.
+    fn fetch_tab(&mut self, tab: &UITabID) -> Task<Message> {
+        match tab {
+            UITabID::Orders => self.orders.fetch(),
+            UITabID::Kitchen => self.kitchen.fetch(),
+            UITabID::VirtualTableManager => self.vtable.fetch(),
+            UITabID::OfferManager => self.offers.fetch(),
+            _ => Task::none()
+        }
+    }
+
+    /// Aborts the running fetches of a tab that was left, their
+    /// responses would only overwrite what the next visit fetches.
+    fn cancel_tab(&mut self, tab: &UITabID) {
+        match tab {
+            UITabID::Orders => self.orders.cancel_requests(),
+            UITabID::Kitchen => self.kitchen.request.cancel(),
+            UITabID::VirtualTableManager => self.vtable.request.cancel(),
+            UITabID::OfferManager => self.offers.request.cancel(),
+            _ => {}
+        }
+    }
+
     pub fn view(&self) -> Element<'_, Message> {
 
-        Tabs::new(|id| UIMessage::TabSelected(id).into())
+        let tabs = Tabs::new(|id| UIMessage::TabSelected(id).into())
             .tab_icon_position(iced_aw::tabs::Position::Bottom)
             .push(
                 UITabID::Settings,
@@ -195,6 +471,11 @@ impl UI {
                 self.orders.tab_label(),
                 self.orders.view(),
             )
+            .push(
+                UITabID::Kitchen,
+                self.kitchen.tab_label(),
+                self.kitchen.view(),
+            )
             .push(
                 UITabID::VirtualTableManager,
                 self.vtable.tab_label(),
@@ -204,18 +485,101 @@ impl UI {
                 UITabID::OfferManager,
                 self.offers.tab_label(),
                 self.offers.view(),
+            );
+
+        let tabs = if self.settings.user_data.privilege == UserPrivilege::Admin {
+            tabs.push(
+                UITabID::LogViewer,
+                self.logs.tab_label(),
+                self.logs.view(),
+            )
+        } else {
+            tabs
+        };
+
+        let tabs = tabs
+            .push(
+                UITabID::Connection,
+                self.connection.tab_label(),
+                self.connection.view(),
             )
             .set_active_tab(&self.active_tab)
             .icon_font(ICON)
             .tab_bar_style(Box::new(tab_style))
-            .tab_bar_position(self.settings.user_settings.tab_bar_position.clone().into())
-            .into()
+            .tab_bar_position(self.settings.user_settings.tab_bar_position.clone().into());
+
+        self.notifications.view(tabs.into())
             
     }
 
 }
 
 
+/// One kind of fetch of a tab. A new fetch or leaving the
+/// tab aborts the running one, responses carry the generation
+/// they were started with and stale ones are dropped.
+#[derive(Debug, Default)]
+pub struct RequestSlot {
+    generation: u64,
+    handle:     Option<iced::task::Handle>,
+}
+
+impl RequestSlot {
+
+    /// Starts a fetch built by `start` from the new generation.
+    pub fn start<T: 'static>(&mut self, start: impl FnOnce(u64) -> Task<T>) -> Task<T> {
+        self.cancel();
+        let (task, handle) = start(self.generation).abortable();
+        self.handle = Some(handle);
+        task
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// `true` if the response of `generation` is still wanted.
+    pub fn finish(&mut self, generation: u64) -> bool {
+        if generation != self.generation {
+            log::info!("Dropping a stale response ({} != {}).", generation, self.generation);
+            return false
+        }
+        self.handle = None;
+        true
+    }
+
+    pub fn cancel(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+        self.generation += 1;
+    }
+
+}
+
+/// Border or text colour of an order by how long it waits.
+fn age_color(theme: &Theme, age: crate::user::Age) -> Color {
+    match age {
+        crate::user::Age::Overdue => theme.extended_palette().danger.base.color,
+        crate::user::Age::Late => Color::from_rgb8(0xE0, 0x9A, 0x1A),
+        crate::user::Age::Fresh => theme.extended_palette().success.base.color,
+    }
+}
+
+/// Shown above cached data while the server is unreachable.
+fn offline_banner<'a>(saved: &str) -> Element<'a, Message> {
+    let saved = chrono::DateTime::parse_from_rfc3339(saved)
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| saved.to_string());
+    container(
+        text!("Offline, showing data from {}. Changes are disabled or queued until the server is back.", saved)
+            .style(text::danger)
+    )
+    .width(Fill)
+    .align_x(Center)
+    .into()
+}
+
 trait Tab {
     type Message;
 
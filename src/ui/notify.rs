@@ -0,0 +1,412 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use iced::{border::Radius, widget::{button, column, container, horizontal_space, row, scrollable, stack, text, Column}, Border, Element, Length, Theme};
+
+use crate::alerts::{Alert, AlertSettings};
+use crate::error::Error;
+
+/// How long a toast stays on screen.
+const SUCCESS_LIFETIME: Duration = Duration::from_secs(4);
+const ERROR_LIFETIME:   Duration = Duration::from_secs(8);
+/// How long the window title flashes after an alert.
+const FLASH_DURATION: Duration = Duration::from_secs(20);
+/// How often toasts are checked for expiry, and the title flashes.
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Errors kept in the error center, older ones are dropped.
+const MAX_ERRORS: usize = 50;
+const TOAST_WIDTH: f32 = 340.0;
+const CENTER_WIDTH: f32 = 460.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastLevel {
+    Success,
+    /// Needs attention but nothing failed, e.g. an overdue order.
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Toast {
+    id:      u64,
+    level:   ToastLevel,
+    title:   String,
+    detail:  String,
+    /// Error center entry with the retry, if any.
+    error:   Option<u64>,
+    shown:   Instant,
+}
+
+/// Entry of the error center.
+#[derive(Debug, Clone)]
+pub struct ErrorRecord {
+    id:    u64,
+    /// RFC 3339 local time of the last occurrence.
+    time:  String,
+    /// How often the same error happened in a row.
+    count: u32,
+    error: Error,
+    /// Message that repeats the failed action.
+    retry: Option<crate::Message>,
+}
+
+#[derive(Debug, Clone)]
+pub enum NotificationMessage {
+    DismissToast(u64),
+    Retry(u64),
+    ToggleCenter,
+    ClearErrors,
+    Tick,
+    /// The window got the focus, the title stops flashing.
+    Attended,
+}
+
+impl Into<crate::Message> for NotificationMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::UI(super::UIMessage::Notifications(self))
+    }
+}
+
+/// Toasts and the error center, drawn over the tabs.
+#[derive(Debug, Default)]
+pub struct Notifications {
+    toasts:      Vec<Toast>,
+    errors:      VecDeque<ErrorRecord>,
+    center_open: bool,
+    next_id:     u64,
+    /// From the user settings.
+    pub alerts:  AlertSettings,
+    /// Title of the last alert and when it came.
+    flash:       Option<(String, Instant)>,
+}
+
+impl Notifications {
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn has_toasts(&self) -> bool {
+        !self.toasts.is_empty()
+    }
+
+    pub fn success(&mut self, title: impl Into<String>) {
+        let id = self.next_id();
+        self.toasts.push(Toast {
+            id,
+            level: ToastLevel::Success,
+            title: title.into(),
+            detail: String::new(),
+            error: None,
+            shown: Instant::now(),
+        });
+    }
+
+    /// Shows a toast that stays as long as an error one.
+    pub fn warning(&mut self, title: impl Into<String>, detail: impl Into<String>) {
+        let id = self.next_id();
+        self.toasts.push(Toast {
+            id,
+            level: ToastLevel::Warning,
+            title: title.into(),
+            detail: detail.into(),
+            error: None,
+            shown: Instant::now(),
+        });
+    }
+
+    /// Shows `alert` as a toast, flashes the window title and plays
+    /// its sound and desktop notification as far as the settings allow.
+    pub fn alert(&mut self, alert: Alert) {
+        if !self.alerts.wants(&alert) {
+            return
+        }
+        log::info!("Alert: {}", alert.title());
+        crate::alerts::sound_and_notify(&alert, &self.alerts);
+        self.flash = Some((alert.title(), Instant::now()));
+        self.warning(alert.title(), alert.detail());
+    }
+
+    pub fn is_flashing(&self) -> bool {
+        self.flash.is_some()
+    }
+
+    /// `title`, every other second with the last alert in front
+    /// while the title flashes.
+    pub fn title(&self, title: &str) -> String {
+        match &self.flash {
+            Some((alert, since)) if since.elapsed().as_secs() % 2 == 0 => format!("● {} – {}", alert, title),
+            _ => title.to_string()
+        }
+    }
+
+    /// Logs `err`, shows a toast and keeps it in the error center.
+    /// `retry` is sent again when the user presses Retry.
+    ///
+    /// The same error repeating, e.g. while polling a server that
+    /// is down, only refreshes the existing toast and entry.
+    pub fn error(&mut self, err: &Error, retry: Option<crate::Message>) {
+
+        log::error!("{}", err);
+
+        let summary = err.summary();
+        let time = chrono::Local::now().to_rfc3339();
+
+        let record_id = match self.errors.front_mut() {
+            Some(last) if last.error.summary() == summary => {
+                last.count += 1;
+                last.time = time;
+                last.retry = retry;
+                last.id
+            },
+            _ => {
+                let id = self.next_id();
+                self.errors.push_front(ErrorRecord { id, time, count: 1, error: err.clone(), retry });
+                self.errors.truncate(MAX_ERRORS);
+                id
+            }
+        };
+
+        if let Some(toast) = self.toasts.iter_mut().find(|toast| toast.error == Some(record_id)) {
+            toast.shown = Instant::now();
+            return
+        }
+
+        let id = self.next_id();
+        self.toasts.push(Toast {
+            id,
+            level: ToastLevel::Error,
+            title: match err.name.is_empty() {
+                true => err.kind.to_string(),
+                false => err.name.clone()
+            },
+            detail: err.message.clone(),
+            error: Some(record_id),
+            shown: Instant::now(),
+        });
+
+    }
+
+    /// Returns the message to send again for a retry.
+    pub fn update(&mut self, message: NotificationMessage) -> Option<crate::Message> {
+        match message {
+            NotificationMessage::DismissToast(id) => self.toasts.retain(|toast| toast.id != id),
+            NotificationMessage::Retry(id) => {
+                self.toasts.retain(|toast| toast.error != Some(id));
+                return self.errors
+                    .iter()
+                    .find(|record| record.id == id)
+                    .and_then(|record| record.retry.clone())
+            },
+            NotificationMessage::ToggleCenter => self.center_open = !self.center_open,
+            NotificationMessage::ClearErrors => {
+                self.errors.clear();
+                self.toasts.retain(|toast| toast.error.is_none());
+            },
+            NotificationMessage::Attended => self.flash = None,
+            NotificationMessage::Tick => {
+                let now = Instant::now();
+                if self.flash.as_ref().is_some_and(|(_, since)| now.duration_since(*since) >= FLASH_DURATION) {
+                    self.flash = None;
+                }
+                self.toasts.retain(|toast| now.duration_since(toast.shown) < match toast.level {
+                    ToastLevel::Success => SUCCESS_LIFETIME,
+                    ToastLevel::Warning |
+                    ToastLevel::Error => ERROR_LIFETIME,
+                });
+            },
+        }
+        None
+    }
+
+    fn has_retry(&self, record_id: Option<u64>) -> Option<u64> {
+        let id = record_id?;
+        self.errors
+            .iter()
+            .find(|record| record.id == id && record.retry.is_some())
+            .map(|record| record.id)
+    }
+
+    fn toast_list(&self) -> Element<'_, crate::Message> {
+
+        let mut col: Column<'_, crate::Message> = Column::new().spacing(8).width(Length::Fixed(TOAST_WIDTH));
+
+        for toast in self.toasts.iter() {
+            col = col.push(
+                container(
+                    column![
+                        row![
+                            text!("{}", toast.title).font(iced::font::Font {
+                                weight: iced::font::Weight::Bold,
+                                ..Default::default()
+                            }),
+                            horizontal_space(),
+                            button("x")
+                                .style(button::text)
+                                .on_press(NotificationMessage::DismissToast(toast.id).into())
+                        ]
+                    ]
+                    .push_maybe((!toast.detail.is_empty()).then(|| text!("{}", toast.detail).size(14)))
+                    .push_maybe(self.has_retry(toast.error).map(|id|
+                        button("Retry")
+                            .style(button::secondary)
+                            .on_press(NotificationMessage::Retry(id).into())
+                    ))
+                    .spacing(4)
+                )
+                .padding(10)
+                .width(Length::Fill)
+                .style(match toast.level {
+                    ToastLevel::Success => toast_success_style,
+                    ToastLevel::Warning => toast_warning_style,
+                    ToastLevel::Error => toast_error_style,
+                })
+            );
+        }
+
+        col.into()
+
+    }
+
+    fn error_center(&self) -> Element<'_, crate::Message> {
+
+        let mut list: Column<'_, crate::Message> = Column::new().spacing(10);
+
+        if self.errors.is_empty() {
+            list = list.push(text!("No errors so far."));
+        }
+
+        for record in self.errors.iter() {
+            list = list.push(
+                container(
+                    column![
+                        row![
+                            text!("{}", record.error.kind).font(iced::font::Font {
+                                weight: iced::font::Weight::Bold,
+                                ..Default::default()
+                            }),
+                            horizontal_space(),
+                            text!("{}", record.time).size(12)
+                        ],
+                        text!("{}", match record.count {
+                            1 => record.error.summary(),
+                            count => format!("{} (x{})", record.error.summary(), count)
+                        }),
+                        text!("{}", record.error).size(12),
+                    ]
+                    .push_maybe(record.retry.as_ref().map(|_|
+                        button("Retry")
+                            .style(button::secondary)
+                            .on_press(NotificationMessage::Retry(record.id).into())
+                    ))
+                    .spacing(4)
+                )
+                .padding(10)
+                .width(Length::Fill)
+                .style(error_record_style)
+            );
+        }
+
+        container(
+            column![
+                row![
+                    text!("Errors").size(22),
+                    horizontal_space(),
+                    button("Clear").on_press(NotificationMessage::ClearErrors.into()),
+                    button("Close").on_press(NotificationMessage::ToggleCenter.into()),
+                ].spacing(10),
+                scrollable(list).height(Length::Fill),
+            ]
+            .spacing(10)
+        )
+        .padding(16)
+        .width(Length::Fixed(CENTER_WIDTH))
+        .height(Length::Fill)
+        .style(container::rounded_box)
+        .into()
+
+    }
+
+    /// Layers the toasts and the error center over `content`.
+    pub fn view<'a>(&'a self, content: Element<'a, crate::Message>) -> Element<'a, crate::Message> {
+
+        let center_button = (!self.errors.is_empty() && !self.center_open).then(|| {
+            container(
+                button(text!("Errors ({})", self.errors.len()))
+                    .style(button::danger)
+                    .on_press(NotificationMessage::ToggleCenter.into())
+            )
+            .width(Length::Fill)
+            .align_x(iced::alignment::Horizontal::Right)
+            .padding(10)
+        });
+
+        let toasts = container(self.toast_list())
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .align_x(iced::alignment::Horizontal::Right)
+            .align_y(iced::alignment::Vertical::Bottom)
+            .padding(16);
+
+        let center = self.center_open.then(|| {
+            container(self.error_center())
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .align_x(iced::alignment::Horizontal::Right)
+                .padding(10)
+        });
+
+        stack![content, toasts]
+            .push_maybe(center_button)
+            .push_maybe(center)
+            .into()
+
+    }
+
+}
+
+fn toast_success_style(theme: &Theme) -> container::Style {
+    toast_style(theme.extended_palette().success.base)
+}
+
+fn toast_warning_style(theme: &Theme) -> container::Style {
+    toast_style(iced::theme::palette::Pair::new(
+        super::age_color(theme, crate::user::Age::Late),
+        iced::Color::BLACK
+    ))
+}
+
+fn toast_error_style(theme: &Theme) -> container::Style {
+    toast_style(theme.extended_palette().danger.base)
+}
+
+fn toast_style(pair: iced::theme::palette::Pair) -> container::Style {
+
+    container::Style {
+        text_color: Some(pair.text),
+        background: Some(iced::Background::Color(pair.color)),
+        border: Border {
+            color: pair.color,
+            width: 0.0,
+            radius: Radius::new(7)
+        },
+        ..Default::default()
+    }
+
+}
+
+fn error_record_style(theme: &Theme) -> container::Style {
+
+    container::Style {
+        text_color: Some(theme.extended_palette().secondary.base.text),
+        background: Some(iced::Background::Color(theme.extended_palette().secondary.base.color)),
+        border: Border {
+            color: theme.extended_palette().danger.base.color,
+            width: 1.0,
+            radius: Radius::new(7)
+        },
+        ..Default::default()
+    }
+
+}
@@ -4,6 +4,10 @@ use serde_json::json;
 
 
 use crate::shared::{dbt::{self as dbt, Offer}, req_resp};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::error::{error, Error, ErrorKind};
+use crate::cache;
+use super::notify::Notifications;
 use super::Tab;
 
 const TITLE: &str = "Offers"; 
@@ -11,14 +15,15 @@ const ICON:  char = '\u{e9ba}';
 
 #[derive(Debug, Clone)]
 pub enum OfferManagerMessage {
-    FetchedOffers(Result<serde_json::Value, String>),
+    FetchedOffers(u64, Result<serde_json::Value, Error>),
     DeleteOffers(dbt::VirtualTableID),
-    DeleteOffersPost(Result<serde_json::Value, String>),
+    DeleteOffersPost(Result<serde_json::Value, Error>, AuditEntry),
     AddOffers,
-    AddOffersPost(Result<serde_json::Value, String>),
+    AddOffersPost(Result<serde_json::Value, Error>, AuditEntry),
     TextInputedName(String),
     TextInputedDescription(String),
     TextInputedPrice(String),
+    TextInputedStation(String),
 }
 
 impl Into<crate::Message> for OfferManagerMessage {
@@ -29,19 +34,30 @@ impl Into<crate::Message> for OfferManagerMessage {
 
 
 pub struct OfferManager {
-    pub fetch_offers: Result<Vec<dbt::Offer>, String>,
+    pub fetch_offers: Result<Vec<dbt::Offer>, Error>,
     pub offer_name_text_input: String,
     pub offer_description_text_input: String,
     pub offer_price_text_input: String,
+    pub offer_station_text_input: String,
+    /// Save time of the cached offers shown while offline.
+    pub cached: Option<String>,
+    pub request: super::RequestSlot,
+    /// Idempotency key of the offer being added, kept for retries
+    /// until the form changes so the offer is not added twice.
+    pub insert_key: Option<String>,
 }
 
 impl Default for OfferManager {
     fn default() -> Self {
         Self { 
-            fetch_offers: Err("Fetching data...".to_string()),
+            fetch_offers: Err(Error::pending("Fetching data...")),
             offer_name_text_input: String::new(),
             offer_description_text_input: String::new(),
             offer_price_text_input: String::new(),
+            offer_station_text_input: String::new(),
+            cached: None,
+            request: super::RequestSlot::default(),
+            insert_key: None,
         }
     }
 }
@@ -50,40 +66,73 @@ impl Default for OfferManager {
 
 impl OfferManager {
 
-    pub fn update(&mut self, message: OfferManagerMessage) -> Task<crate::Message> {
+    /// Applies a change made on the server to the shown offers.
+    pub fn apply_change(&mut self, change: &req_resp::Change) {
+        let Ok(offers) = &mut self.fetch_offers else {
+            return
+        };
+        match change {
+            req_resp::Change::OfferAdded { offer } |
+            req_resp::Change::OfferEdited { offer } => {
+                match offers.iter_mut().find(|o| o.name == offer.name) {
+                    Some(o) => *o = offer.clone(),
+                    None => offers.push(offer.clone())
+                }
+            },
+            req_resp::Change::OfferDeleted { offer } => offers.retain(|o| &o.name != offer),
+            _ => {}
+        }
+    }
+
+    pub fn fetch(&mut self) -> Task<crate::Message> {
+        self.request.start(|generation| {
+            let mut request = req_resp::Request {
+                kind: req_resp::RequestKind::Offers,
+                payload: None
+            };
+            Task::perform(
+                async move {request.send_request("".to_string()).await}, 
+                move |value| {OfferManagerMessage::FetchedOffers(generation, value).into()}
+            )
+        })
+    }
+
+    pub fn update(&mut self, message: OfferManagerMessage, username: &str, notify: &mut Notifications) -> Task<crate::Message> {
 
         match message {
-            OfferManagerMessage::FetchedOffers(response) => {
-                if response.is_ok() {
-                    match serde_json::from_value::<req_resp::OffersResponseData>(response.unwrap()) {
-                        Ok(payload) => self.fetch_offers = Ok(payload.offers),
-                        Err(err) => self.fetch_offers = Err(err.to_string())
-                    }
-                } else {
-                    self.fetch_offers = Err(response.unwrap_err().to_string())
+            OfferManagerMessage::FetchedOffers(generation, response) => {
+                if !self.request.finish(generation) {
+                    return Task::none()
+                }
+                (self.fetch_offers, self.cached) = cache::fallback(cache::OFFERS, response
+                    .and_then(req_resp::decode::<req_resp::OffersResponseData>)
+                    .map(|payload| payload.offers));
+                if let Err(err) = &self.fetch_offers {
+                    notify.error(err, Some(super::UIMessage::TabSelected(super::UITabID::OfferManager).into()));
                 }
             },
-            OfferManagerMessage::DeleteOffers(table) => {
+            OfferManagerMessage::DeleteOffers(offer) => {
+                let before = self.fetch_offers.as_ref().ok()
+                    .and_then(|offers| offers.iter().find(|o| o.name == offer))
+                    .cloned();
+                let audit = AuditEntry::new(username, AuditAction::DeleteOffer, &offer, before.as_ref(), None);
                 let mut request = req_resp::Request {
-                    kind: req_resp::RequestKind::TablesDelete,
+                    kind: req_resp::RequestKind::OffersDelete,
                     payload: None
                 };
                 return Task::perform(
-                    async move {request.send_request(table).await}, 
-                    |value| {
-                        OfferManagerMessage::DeleteOffersPost(value).into()}
+                    async move {request.send_request(offer).await}, 
+                    move |value| {
+                        OfferManagerMessage::DeleteOffersPost(value, audit.clone()).into()}
                 )
             },
-            OfferManagerMessage::DeleteOffersPost(result) => {
-                if result.is_ok() {
-                    let mut request = req_resp::Request {
-                        kind: req_resp::RequestKind::Offers,
-                        payload: None
-                    };
-                    return Task::perform(
-                        async move {request.send_request("".to_string()).await}, 
-                        |value| {OfferManagerMessage::FetchedOffers(value).into()}
-                    )
+            OfferManagerMessage::DeleteOffersPost(result, audit) => {
+                if let Err(err) = result {
+                    notify.error(&err, Some(OfferManagerMessage::DeleteOffers(audit.target.clone()).into()));
+                } else {
+                    audit.commit();
+                    notify.success(format!("Offer `{}` deleted", audit.target));
+                    return self.fetch()
                 }
             },
             OfferManagerMessage::AddOffers => {
@@ -93,62 +142,52 @@ impl OfferManager {
                     return Task::none()
                 }
 
-                let (integer, fraction) = {
-                    let price = self.offer_price_text_input.clone();
-                    let Some((left, right)) = price.split_once(",") else {
-                        log::error!("Invalid price!");
-                        return Task::none()
-                    };
-                    let Ok(integer) = left.parse::<u32>() else {
-                        log::error!("Invalid integer!");
-                        return Task::none()
-                    };
-                    let Ok(fraction) = right.parse::<u32>() else {
-                        log::error!("Invalid fraction!");
+                let (integer, fraction) = match parse_price(&self.offer_price_text_input) {
+                    Ok(price) => price,
+                    Err(err) => {
+                        notify.error(&err, None);
                         return Task::none()
-                    };
-                    if fraction >= 100 {
-                        log::error!("Invalid fraction!");
                     }
-                    (integer, fraction)
                 };
 
-                let mut request = req_resp::Request {
-                    kind: req_resp::RequestKind::OffersInsert,
-                    payload: Some(serde_json::to_value(req_resp::OffersInsertRequestData {
-                        offer: dbt::Offer {
-                            name: self.offer_description_text_input.clone(),
-                            description: self.offer_description_text_input.clone(), 
-                            price_integer: integer,
-                            price_fraction: fraction
-                        }
-                    }).unwrap())
+                let offer = dbt::Offer {
+                    name: self.offer_description_text_input.clone(),
+                    description: self.offer_description_text_input.clone(), 
+                    price_integer: integer,
+                    price_fraction: fraction,
+                    station: match self.offer_station_text_input.trim() {
+                        "" => dbt::DEFAULT_STATION.to_string(),
+                        station => station.to_lowercase()
+                    }
                 };
+                let audit = AuditEntry::new(username, AuditAction::AddOffer, &offer.name, None, Some(&offer));
+                let request = req_resp::Request::encode(req_resp::RequestKind::OffersInsert, &req_resp::OffersInsertRequestData {
+                    offer
+                });
+                let key = self.insert_key.get_or_insert_with(req_resp::new_idempotency_key).clone();
                 return Task::perform(
-                    async move {request.send_request("".to_string()).await}, 
-                    |value| {OfferManagerMessage::AddOffersPost(value).into()}
+                    async move {request?.send_request_with_key("".to_string(), Some(key)).await}, 
+                    move |value| {OfferManagerMessage::AddOffersPost(value, audit.clone()).into()}
                 )
             },
-            OfferManagerMessage::AddOffersPost(result) => {
-                if result.is_ok() {
+            OfferManagerMessage::AddOffersPost(result, audit) => {
+                if let Err(err) = result {
+                    notify.error(&err, Some(OfferManagerMessage::AddOffers.into()));
+                } else {
+                    audit.commit();
+                    notify.success(format!("Offer `{}` added", audit.target));
+                    self.insert_key = None;
                     self.offer_name_text_input = String::new();
                     self.offer_description_text_input = String::new();
                     self.offer_price_text_input = String::new();
-                    let mut request = req_resp::Request {
-                        kind: req_resp::RequestKind::Offers,
-                        payload: None
-                    };
-                    return Task::perform(
-                        async move {request.send_request("".to_string()).await}, 
-                        |value| {
-                            OfferManagerMessage::FetchedOffers(value).into()
-                        }
-                    )
+                    self.offer_station_text_input = String::new();
+                    return self.fetch()
                 }
             }
-            OfferManagerMessage::TextInputedName(text) => {self.offer_name_text_input = text}
-            OfferManagerMessage::TextInputedDescription(text) => {self.offer_description_text_input = text}
-            OfferManagerMessage::TextInputedPrice(text) => {self.offer_price_text_input = text}
+            OfferManagerMessage::TextInputedName(text) => {self.offer_name_text_input = text; self.insert_key = None}
+            OfferManagerMessage::TextInputedDescription(text) => {self.offer_description_text_input = text; self.insert_key = None}
+            OfferManagerMessage::TextInputedPrice(text) => {self.offer_price_text_input = text; self.insert_key = None}
+            OfferManagerMessage::TextInputedStation(text) => {self.offer_station_text_input = text; self.insert_key = None}
         }
         Task::none()
 
@@ -156,6 +195,34 @@ impl OfferManager {
 
 }
 
+/// Parses a price written as `integer,fraction`, e.g. `4,50`.
+fn parse_price(price: &str) -> Result<(u32, u32), Error> {
+
+    let Some((left, right)) = price.split_once(",") else {
+        return error!(
+            kind: ErrorKind::Input,
+            name: "Invalid price",
+            message: format!("`{}` is not written as `4,50`", price)
+        )
+    };
+    let Ok(integer) = left.parse::<u32>() else {
+        return error!(
+            kind: ErrorKind::Input,
+            name: "Invalid price",
+            message: format!("`{}` is not a whole number", left)
+        )
+    };
+    match right.parse::<u32>() {
+        Ok(fraction) if fraction < 100 => Ok((integer, fraction)),
+        _ => error!(
+            kind: ErrorKind::Input,
+            name: "Invalid price",
+            message: format!("`{}` is not a number between 0 and 99", right)
+        )
+    }
+
+}
+
 fn virtual_table_button_style(theme: &Theme, status: iced::widget::button::Status) -> button::Style {
 
     let mut table_button_style = iced::widget::button::Style { 
@@ -268,12 +335,16 @@ impl Tab for OfferManager {
 
     fn content(&self) -> iced::Element<'_, Self::Message> {
         
-        if self.fetch_offers.is_err() {
-            text!("{}", self.fetch_offers.clone().expect_err("").to_string()).into()
+        if let Err(err) = &self.fetch_offers {
+            text!("{}", err.summary()).into()
         } else {
 
             let mut col: Column<'_, crate::Message> = Column::new().spacing(20).padding(30);
 
+            if let Some(saved) = &self.cached {
+                col = col.push(super::offline_banner(saved));
+            }
+
             let offers = self.fetch_offers.clone().unwrap();
             for offer in offers.iter() {
                 col = col.push(
@@ -282,19 +353,22 @@ impl Tab for OfferManager {
                             column![
                                 text!("`{}`", offer.name.clone()),
                                 text!("{}", offer.description.clone()),
-                                text!("Price: {},{}", offer.price_integer, offer.price_fraction)
+                                text!("Price: {},{}", offer.price_integer, offer.price_fraction),
+                                text!("Station: {}", offer.station)
                             ],
                             iced::widget::horizontal_space(),
+                        ]
+                        .push_maybe(self.cached.is_none().then(|| 
                             button("X")
                                 .on_press(OfferManagerMessage::DeleteOffers(offer.name.clone()).into())
                                 .style(virtual_table_button_style)
-                        ]
+                        ))
                     )
                     .padding(10)
                     .style(vtable_container_style).width(Length::Fill)
                 );
             }
-            col = col.push(
+            col = col.push_maybe(self.cached.is_none().then(|| 
                 container(
                     row![
                         column![
@@ -305,6 +379,8 @@ impl Tab for OfferManager {
                                 .on_input(|s| OfferManagerMessage::TextInputedDescription(s).into()),
                             iced::widget::text_input("Price", &self.offer_price_text_input)
                                 .on_input(|s| OfferManagerMessage::TextInputedPrice(s).into()),
+                            iced::widget::text_input(&format!("Station, e.g. bar (default {})", dbt::DEFAULT_STATION), &self.offer_station_text_input)
+                                .on_input(|s| OfferManagerMessage::TextInputedStation(s).into()),
                             
                         ],
                         iced::widget::horizontal_space(),
@@ -315,7 +391,7 @@ impl Tab for OfferManager {
                 )
                 .padding(10)
                 .style(vtable_container_style).width(Length::Fill)
-            );
+            ));
         // let client = Client::new();
 
             center(scrollable(col).width(Length::Fill)).into()
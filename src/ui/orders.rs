@@ -1,31 +1,77 @@
 
-use std::{collections::{HashMap, HashSet}, thread::current};
+use std::{collections::HashMap, thread::current};
 
 use iced::{
     alignment::Horizontal, border::Radius, widget::{
-        button, center, column, container, horizontal_rule, horizontal_space, row, scrollable, text, Column
+        button, center, checkbox, column, container, horizontal_rule, horizontal_space, pick_list, row, scrollable, text, text_input, Column
     }, Border, Length, Task, Theme
 };
 
 use crate::{shared::req_resp, Message};
+use crate::alerts::Alert;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::bill::{self, Bill};
 use crate::shared::dbt;
+use crate::cache;
+use crate::error::{error, error_forward, function_message, Error, ErrorKind};
+use crate::outbox::QueuedChange;
+use super::notify::Notifications;
+use super::vtable::VirtualTableManagerMessage;
 use super::Tab;
 
 
 const TITLE: &str = "Orders"; 
 const ICON:  char = '\u{e9ba}';
+/// How often the waiting times of the orders are redrawn.
+const AGE_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
 
 #[derive(Debug, Clone)]
 pub enum OrderListMessage {
-    FetchedVirtualTablesAndItems(Result<serde_json::Value, String>),
-    UpdateOrders(Result<serde_json::Value, String>),
+    FetchedVirtualTablesAndItems(u64, Result<serde_json::Value, Error>),
+    UpdateOrders(dbt::OrderID, Result<serde_json::Value, Error>, Box<QueuedChange>),
     TablePressed(dbt::VirtualTableID),
-    FetchedOrders(Result<serde_json::Value, String>),
+    FetchedOrders(u64, Result<serde_json::Value, Error>),
     FinishOrder(dbt::OrderID),
+    /// Marks the part of the order prepared at the station done.
+    StationDone(dbt::OrderID, dbt::StationID),
+    StationDonePost(dbt::OrderID, Result<serde_json::Value, Error>, Box<QueuedChange>),
+    /// Moves the item at this place of the order to the status.
+    ItemStatus(dbt::OrderID, usize, dbt::ItemStatus),
+    ItemStatusPost(dbt::OrderID, Result<serde_json::Value, Error>, Box<QueuedChange>),
+    MoveOrder(dbt::OrderID, dbt::VirtualTableID),
+    MoveOrderPost(dbt::OrderID, Result<serde_json::Value, Error>, Box<AuditEntry>),
+    MergeTargetSelected(dbt::VirtualTableID),
+    /// Moves every open order of the current table to the merge target.
+    MergeTables,
+    MergeTablesPost(dbt::VirtualTableID, Result<serde_json::Value, Error>, Box<AuditEntry>),
+    /// Shows the bill of the current table instead of its orders.
+    ShowBill(bool),
+    FetchedBill(u64, Result<serde_json::Value, Error>),
+    ExportBill,
+    PrintBill,
+    BillPrinted(dbt::VirtualTableID, Result<std::path::PathBuf, Error>),
+    /// Shows the QR orders waiting for confirmation instead of
+    /// the orders of the current table.
+    ShowPending(bool),
+    FetchedPending(u64, Result<serde_json::Value, Error>),
+    RequireConfirmation(bool),
+    RequireConfirmationPost(Result<serde_json::Value, Error>),
+    /// Sets the count of the item at this place of a pending
+    /// order, `0` drops the item.
+    PendingItemCount(dbt::OrderID, usize, u32),
+    DenyReasonChanged(dbt::OrderID, String),
+    AcceptOrder(dbt::OrderID),
+    AcceptOrderPost(dbt::OrderID, Result<serde_json::Value, Error>, Box<AuditEntry>),
+    DenyOrder(dbt::OrderID),
+    DenyOrderPost(dbt::OrderID, Result<serde_json::Value, Error>, Box<AuditEntry>),
+    /// Lists the oldest orders first if `true`, by number otherwise.
+    SortByAge(bool),
+    /// Redraws the waiting times.
+    Tick,
     PollOrders,
-    FetchedOrderAndUnfinished(Result<serde_json::Value, String>),
+    FetchedOrderAndUnfinished(u64, Result<serde_json::Value, Error>),
     PollFetchedTablesWithUnfinishedOrders,
-    PollFetchedTablesWithUnfinishedOrdersPost(Result<serde_json::Value, String>),
+    PollFetchedTablesWithUnfinishedOrdersPost(u64, Result<serde_json::Value, Error>),
 }
 
 
@@ -36,6 +82,11 @@ impl OrderList {
             OrderListMessage::PollOrders.into() // Map the timer to `PollOrders` message
         })
     }
+
+    /// Keeps the waiting times current while nothing is polled.
+    pub fn clock(&self) -> iced::Subscription<Message> {
+        iced::time::every(AGE_REFRESH_INTERVAL).map(|_| OrderListMessage::Tick.into())
+    }
 }
 
 impl Into<crate::Message> for OrderListMessage {
@@ -47,22 +98,83 @@ impl Into<crate::Message> for OrderListMessage {
 #[derive(Debug)]
 pub struct OrderList {
 
-    pub fetch_vtables: Result<Vec<dbt::VirtualTable>, String>,
+    pub fetch_vtables: Result<Vec<dbt::VirtualTable>, Error>,
     pub current_vtable: Option<dbt::VirtualTableID>,
-    pub fetch_orders: Result<Vec<dbt::Order>, String>,
-    pub fetch_items: Result<Vec<dbt::Offer>, String>,
-    pub unfinished_tables: Result<Vec<dbt::VirtualTableID>, String>
+    pub fetch_orders: Result<Vec<dbt::Order>, Error>,
+    pub fetch_items: Result<Vec<dbt::Offer>, Error>,
+    pub unfinished_tables: Result<Vec<dbt::VirtualTableID>, Error>,
+    /// Save time of the cached data shown while offline.
+    pub cached: Option<String>,
+    pub tables_request: super::RequestSlot,
+    pub orders_request: super::RequestSlot,
+    pub unfinished_request: super::RequestSlot,
+    /// Highlights orders waiting too long, from the user settings.
+    pub ageing: crate::user::Ageing,
+    pub sort_by_age: bool,
+    /// Table the current one is merged into.
+    pub merge_target: Option<dbt::VirtualTableID>,
+    /// Bill of the current table while it is shown.
+    pub bill: Option<Result<Bill, Error>>,
+    pub bill_request: super::RequestSlot,
+    /// The print command of a bill is running.
+    pub printing: bool,
+    /// From the user settings.
+    pub tax: crate::user::Tax,
+    /// QR orders waiting for confirmation, of every table.
+    pub pending: Result<Vec<PendingOrder>, Error>,
+    pub pending_request: super::RequestSlot,
+    pub show_pending: bool,
+    /// Whether the server holds back QR orders for confirmation.
+    pub confirmation_required: bool
+
+}
+
+/// Open orders by table, as kept in the offline cache.
+pub type OpenOrders = HashMap<dbt::VirtualTableID, Vec<dbt::Order>>;
 
+/// A QR order waiting for confirmation, as staff edit it.
+#[derive(Debug, Clone)]
+pub struct PendingOrder {
+    /// As placed by the customer.
+    pub placed: dbt::Order,
+    /// Accepted in place of `placed`.
+    pub order: dbt::Order,
+    pub edited: bool,
+    /// Shown to the customer if the order is denied.
+    pub reason: String,
+    /// Accepting or denying it is on the way to the server.
+    pub sending: bool,
+}
+
+impl PendingOrder {
+    fn new(order: dbt::Order) -> Self {
+        Self { placed: order.clone(), order, edited: false, reason: String::new(), sending: false }
+    }
 }
 
 impl Default for OrderList {
     fn default() -> Self {
         Self { 
             current_vtable: None,
-            fetch_vtables:  Err("Fetching data...".to_string()),
-            fetch_orders:   Err("Click on a table first :)".to_string()),
-            fetch_items:    Err("Couldn't fetch Item's".to_string()),
-            unfinished_tables: Err("Couldn't fetch orders.".to_string())
+            fetch_vtables:  Err(Error::pending("Fetching data...")),
+            fetch_orders:   Err(Error::pending("Click on a table first :)")),
+            fetch_items:    Err(Error::pending("Couldn't fetch Item's")),
+            unfinished_tables: Err(Error::pending("Couldn't fetch orders.")),
+            cached: None,
+            tables_request: super::RequestSlot::default(),
+            orders_request: super::RequestSlot::default(),
+            unfinished_request: super::RequestSlot::default(),
+            ageing: crate::user::Ageing::default(),
+            sort_by_age: false,
+            merge_target: None,
+            bill: None,
+            bill_request: super::RequestSlot::default(),
+            printing: false,
+            tax: crate::user::Settings::default().tax(),
+            pending: Err(Error::pending("Fetching the orders to confirm...")),
+            pending_request: super::RequestSlot::default(),
+            show_pending: false,
+            confirmation_required: false
         }
     }
 }
@@ -71,160 +183,612 @@ impl Default for OrderList {
 impl OrderList {
 
 
-    pub fn update(&mut self, message: OrderListMessage) -> Task<Message> {
+    pub fn update(&mut self, message: OrderListMessage, username: &str, notify: &mut Notifications) -> Task<Message> {
 
         match message {
-            OrderListMessage::PollFetchedTablesWithUnfinishedOrders => {
-                let mut request = req_resp::Request {
-                    kind: req_resp::RequestKind::Orders,
-                    payload: Some(serde_json::to_value(req_resp::OrdersRequestData {
-                        new: true,
-                        table: None
-                    }).unwrap())                
+            OrderListMessage::SortByAge(sort_by_age) => {
+                self.sort_by_age = sort_by_age;
+                Task::none()
+            }
+            OrderListMessage::Tick => Task::none(),
+            OrderListMessage::MoveOrder(order_id, to) => {
+                let Some(before) = self.fetch_orders.as_ref().ok()
+                    .and_then(|orders| orders.iter().find(|order| order.id.table == order_id.table && order.id.count == order_id.count))
+                    .cloned() else {
+                    return Task::none()
                 };
-                return Task::perform(
-                    async move {request.send_request("".to_string()).await}, 
-                    |result| OrderListMessage::PollFetchedTablesWithUnfinishedOrdersPost(result).into(),
-                );
+                let audit = Box::new(AuditEntry::new(
+                    username,
+                    AuditAction::MoveOrder,
+                    &format!("{} #{} to {}", order_id.table, order_id.count, to),
+                    Some(&before),
+                    None
+                ));
+                let request = req_resp::Request::encode(req_resp::RequestKind::OrdersMove, &req_resp::OrdersMoveRequestData {
+                    order: dbt::Order {
+                        id: order_id.clone(),
+                        version: before.version,
+                        ..Default::default()
+                    },
+                    to
+                });
+                // Not queued while offline, the server picks the new number.
+                let key = req_resp::new_idempotency_key();
+                self.fetch_orders = Err(Error::pending("Moving the order..."));
+                Task::perform(
+                    async move {request?.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| OrderListMessage::MoveOrderPost(order_id.clone(), value, audit.clone()).into()
+                )
             }
-            OrderListMessage::PollFetchedTablesWithUnfinishedOrdersPost(fetch) => {
-                let value = match fetch {
-                    Ok(value) => value,
-                    Err(err) => {
-                        self.unfinished_tables = Err(err.to_string());
-                        return Task::none();
+            OrderListMessage::MoveOrderPost(order_id, result, mut audit) => {
+                let poll = Task::done(OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into());
+                match result.and_then(req_resp::decode::<req_resp::OrdersMoveResponseData>) {
+                    Ok(response) => {
+                        audit.after = serde_json::to_value(&response.order).ok();
+                        audit.commit();
+                        notify.success(format!(
+                            "Order {} #{} moved, it is {} #{} now",
+                            order_id.table, order_id.count, response.order.id.table, response.order.id.count
+                        ));
+                    },
+                    Err(err) if err.kind == ErrorKind::Conflict => {
+                        return Task::batch([self.report_conflict(err, &order_id, notify), poll])
+                    },
+                    Err(err) => notify.error(&error_forward!(err), None),
+                }
+                Task::batch([self.fetch_orders_of(order_id.table), poll])
+            }
+            OrderListMessage::ShowPending(true) => {
+                self.show_pending = true;
+                self.bill_request.cancel();
+                self.bill = None;
+                self.fetch_pending()
+            }
+            OrderListMessage::ShowPending(false) => {
+                self.show_pending = false;
+                Task::none()
+            }
+            OrderListMessage::FetchedPending(generation, result) => {
+                if !self.pending_request.finish(generation) {
+                    return Task::none()
+                }
+                match result.and_then(req_resp::decode::<req_resp::OrdersPendingResponseData>) {
+                    Ok(response) => {
+                        let mut pending = Vec::new();
+                        for order in response.orders {
+                            let shown = self.pending.as_ref().ok().and_then(|shown| shown
+                                .iter()
+                                .find(|shown| shown.order.id.table == order.id.table && shown.order.id.count == order.id.count)
+                            );
+                            match shown {
+                                // Keeps the edits unless the order changed meanwhile.
+                                Some(shown) if shown.placed.version == order.version => pending.push(shown.clone()),
+                                Some(_) => pending.push(PendingOrder::new(order)),
+                                None => {
+                                    // The live sync knows them already, unless it is down.
+                                    if self.pending.is_ok() {
+                                        notify.alert(Alert::NewOrder { table: order.id.table.clone(), count: Some(order.id.count) });
+                                    }
+                                    pending.push(PendingOrder::new(order));
+                                }
+                            }
+                        }
+                        self.pending = Ok(pending);
+                    },
+                    // Polled, the queue stays as it was until the next poll succeeds.
+                    Err(err) => if self.pending.is_err() {
+                        self.pending = Err(err);
                     }
-                };
-                let response = match serde_json::from_value::<req_resp::OrdersResponseData>(value) {
-                    Ok(payload) => payload,
-                    Err(err) => {
-                        self.unfinished_tables = Err(err.to_string());
-                        return Task::none()
+                }
+                Task::none()
+            }
+            OrderListMessage::RequireConfirmation(required) => {
+                let request = req_resp::Request::encode(req_resp::RequestKind::OrdersConfirmation, &req_resp::OrdersConfirmationRequestData {
+                    required
+                });
+                Task::perform(
+                    async move {request?.send_request("".to_string()).await},
+                    |result| OrderListMessage::RequireConfirmationPost(result).into()
+                )
+            }
+            OrderListMessage::RequireConfirmationPost(result) => {
+                match result.and_then(req_resp::decode::<req_resp::OrdersConfirmationResponseData>) {
+                    Ok(response) => {
+                        self.confirmation_required = response.required;
+                        notify.success(match response.required {
+                            true => "QR orders wait for confirmation now",
+                            false => "QR orders go straight to the open orders now"
+                        });
+                    },
+                    Err(err) => notify.error(&error_forward!(err), None),
+                }
+                Task::none()
+            }
+            OrderListMessage::PendingItemCount(order_id, index, count) => {
+                if let Some(shown) = self.pending_order(&order_id) {
+                    match count {
+                        0 if index < shown.order.items.len() => {
+                            shown.order.items.remove(index);
+                        },
+                        _ => if let Some(item) = shown.order.items.get_mut(index) {
+                            item.count = count;
+                        }
                     }
+                    shown.edited = true;
+                }
+                Task::none()
+            }
+            OrderListMessage::DenyReasonChanged(order_id, reason) => {
+                if let Some(shown) = self.pending_order(&order_id) {
+                    shown.reason = reason;
+                }
+                Task::none()
+            }
+            OrderListMessage::AcceptOrder(order_id) => {
+                let Some(shown) = self.pending_order(&order_id) else {
+                    return Task::none()
                 };
-                let mut unique = HashSet::new();
-                for order in response.orders {
-                    unique.insert(order.id.table);
+                shown.sending = true;
+                let audit = Box::new(AuditEntry::new(
+                    username,
+                    AuditAction::AcceptOrder,
+                    &format!("{} #{}", order_id.table, order_id.count),
+                    Some(&shown.placed),
+                    Some(&shown.order)
+                ));
+                let request = req_resp::Request::encode(req_resp::RequestKind::OrdersAccept, &req_resp::OrdersAcceptRequestData {
+                    order: shown.order.clone()
+                });
+                // Not queued while offline, the customer waits for an answer.
+                let key = req_resp::new_idempotency_key();
+                Task::perform(
+                    async move {request?.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| OrderListMessage::AcceptOrderPost(order_id.clone(), value, audit.clone()).into()
+                )
+            }
+            OrderListMessage::AcceptOrderPost(order_id, result, mut audit) => {
+                match result.and_then(req_resp::decode::<req_resp::OrdersAcceptResponseData>) {
+                    Ok(response) => {
+                        audit.after = serde_json::to_value(&response.order).ok();
+                        audit.commit();
+                        notify.success(format!("Order {} #{} accepted", order_id.table, order_id.count));
+                        self.apply_change(&req_resp::Change::OrderChanged { order: response.order })
+                    },
+                    Err(err) => self.pending_failed(&order_id, err, notify)
                 }
-                self.unfinished_tables = Ok(unique.into_iter().collect::<Vec<String>>());
+            }
+            OrderListMessage::DenyOrder(order_id) => {
+                let Some(shown) = self.pending_order(&order_id) else {
+                    return Task::none()
+                };
+                shown.sending = true;
+                let reason = shown.reason.trim().to_string();
+                let audit = Box::new(AuditEntry::new(
+                    username,
+                    AuditAction::DenyOrder,
+                    &format!("{} #{}: {}", order_id.table, order_id.count, reason),
+                    Some(&shown.placed),
+                    None
+                ));
+                let request = req_resp::Request::encode(req_resp::RequestKind::OrdersDeny, &req_resp::OrdersDenyRequestData {
+                    order: dbt::Order {
+                        id: order_id.clone(),
+                        version: shown.placed.version,
+                        ..Default::default()
+                    },
+                    reason
+                });
+                let key = req_resp::new_idempotency_key();
+                Task::perform(
+                    async move {request?.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| OrderListMessage::DenyOrderPost(order_id.clone(), value, audit.clone()).into()
+                )
+            }
+            OrderListMessage::DenyOrderPost(order_id, result, audit) => {
+                match result.and_then(req_resp::decode::<req_resp::OrdersDenyResponseData>) {
+                    Ok(_) => {
+                        audit.commit();
+                        notify.success(format!("Order {} #{} denied, the customer was told why", order_id.table, order_id.count));
+                        self.remove_pending(&order_id);
+                        Task::none()
+                    },
+                    Err(err) => self.pending_failed(&order_id, err, notify)
+                }
+            }
+            OrderListMessage::ShowBill(true) => {
+                self.show_pending = false;
+                self.fetch_bill()
+            }
+            OrderListMessage::ShowBill(false) => {
+                self.bill_request.cancel();
+                self.bill = None;
                 Task::none()
             }
-            OrderListMessage::FetchedVirtualTablesAndItems(fetch) => {
-                let value = match fetch {
-                    Ok(value) => value,
-                    Err(err) => {
-                        log::error!("err: {}", err);
-                        self.fetch_items = Err("Failed to fetch offers and tables. 1".to_string());
-                        self.fetch_vtables = Err("Failed to fetch offers and tables. 1".to_string());
-                        return Task::none();
-                    }
+            OrderListMessage::FetchedBill(generation, result) => {
+                if !self.bill_request.finish(generation) {
+                    return Task::none()
+                }
+                let Some(table) = self.current_vtable.clone() else {
+                    return Task::none()
                 };
-                let response = match serde_json::from_value::<req_resp::OffersTablesResponseData>(value) {
-                    Ok(payload) => payload,
+                let offers = self.fetch_items.as_deref().unwrap_or_default();
+                let bill = result
+                    .and_then(req_resp::decode::<req_resp::OrdersResponseData>)
+                    .map(|payload| Bill::new(&table, &payload.orders, offers, self.tax));
+                if let Err(err) = &bill {
+                    notify.error(err, Some(OrderListMessage::ShowBill(true).into()));
+                }
+                self.bill = Some(bill);
+                Task::none()
+            }
+            OrderListMessage::ExportBill => {
+                let Some(Ok(shown)) = &self.bill else {
+                    return Task::none()
+                };
+                match bill::export(shown) {
+                    Ok(file) => notify.success(format!("Bill of {} saved to `{}`", shown.table, file.display())),
+                    Err(err) => notify.error(&err, None),
+                }
+                Task::none()
+            }
+            OrderListMessage::PrintBill => {
+                let Some(Ok(shown)) = &self.bill else {
+                    return Task::none()
+                };
+                if self.printing {
+                    return Task::none()
+                }
+                self.printing = true;
+                let shown = shown.clone();
+                let table = shown.table.clone();
+                // The print command can take a while, e.g. to reach
+                // a network printer.
+                Task::perform(
+                    async move {
+                        match tokio::task::spawn_blocking(move || bill::print(&shown)).await {
+                            Ok(result) => result,
+                            Err(err) => error!(
+                                kind: ErrorKind::Storage,
+                                name: "Cannot print the bill",
+                                message: function_message!("tokio::task::spawn_blocking", err.to_string())
+                            )
+                        }
+                    },
+                    move |result| OrderListMessage::BillPrinted(table.clone(), result).into()
+                )
+            }
+            OrderListMessage::BillPrinted(table, result) => {
+                self.printing = false;
+                match result {
+                    Ok(file) => notify.success(format!("Bill of {} printed and saved to `{}`", table, file.display())),
+                    Err(err) => notify.error(&err, None),
+                }
+                Task::none()
+            }
+            OrderListMessage::MergeTargetSelected(table) => {
+                self.merge_target = Some(table);
+                Task::none()
+            }
+            OrderListMessage::MergeTables => {
+                let (Some(from), Some(into)) = (self.current_vtable.clone(), self.merge_target.take()) else {
+                    return Task::none()
+                };
+                let audit = Box::new(AuditEntry::new(
+                    username,
+                    AuditAction::MergeTables,
+                    &format!("{} into {}", from, into),
+                    self.fetch_orders.as_ref().ok(),
+                    None
+                ));
+                let request = req_resp::Request::encode(req_resp::RequestKind::TablesMerge, &req_resp::TablesMergeRequestData {
+                    from,
+                    into: into.clone()
+                });
+                let key = req_resp::new_idempotency_key();
+                self.fetch_orders = Err(Error::pending("Merging the tables..."));
+                Task::perform(
+                    async move {request?.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| OrderListMessage::MergeTablesPost(into.clone(), value, audit.clone()).into()
+                )
+            }
+            OrderListMessage::MergeTablesPost(into, result, mut audit) => {
+                match result.and_then(req_resp::decode::<req_resp::TablesMergeResponseData>) {
+                    Ok(response) => {
+                        audit.after = serde_json::to_value(&response.orders).ok();
+                        audit.commit();
+                        notify.success(format!("Merged {}, {} orders moved", audit.target, response.orders.len()));
+                        // The guests sit at the merged table now.
+                        self.current_vtable = Some(into.clone());
+                    },
+                    Err(err) => notify.error(&error_forward!(err), None),
+                }
+                let current = self.current_vtable.clone().unwrap_or(into);
+                Task::batch([
+                    self.fetch_orders_of(current),
+                    Task::done(OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into()),
+                ])
+            }
+            OrderListMessage::PollFetchedTablesWithUnfinishedOrders => {
+                if self.unfinished_request.is_running() {
+                    return Task::none()
+                }
+                self.unfinished_request.start(|generation| {
+                    let request = req_resp::Request::encode(req_resp::RequestKind::Orders, &req_resp::OrdersRequestData {
+                        new: true,
+                        table: None
+                    });
+                    Task::perform(
+                        async move {request?.send_request("".to_string()).await}, 
+                        move |result| OrderListMessage::PollFetchedTablesWithUnfinishedOrdersPost(generation, result).into(),
+                    )
+                })
+            }
+            OrderListMessage::PollFetchedTablesWithUnfinishedOrdersPost(generation, fetch) => {
+                if !self.unfinished_request.finish(generation) {
+                    return Task::none()
+                }
+                let fetched = fetch
+                    .and_then(req_resp::decode::<req_resp::OrdersResponseData>)
+                    .map(|payload| {
+                        let mut open = OpenOrders::new();
+                        for order in payload.orders {
+                            open.entry(order.id.table.clone()).or_default().push(order);
+                        }
+                        open
+                    });
+                let (open, saved) = cache::fallback(cache::ORDERS, fetched);
+                let fresh = saved.is_none();
+                self.cached = saved;
+                match open {
+                    Ok(open) => {
+                        let unfinished = open
+                            .into_iter()
+                            .filter(|(_, orders)| !orders.is_empty())
+                            .map(|(table, _)| table)
+                            .collect::<Vec<String>>();
+                        // Only the polled tables are known here, the live
+                        // sync alerts about the orders themselves.
+                        if let (Ok(known), true) = (&self.unfinished_tables, fresh) {
+                            for table in unfinished.iter().filter(|table| !known.contains(table)) {
+                                notify.alert(Alert::NewOrder { table: table.clone(), count: None });
+                            }
+                        }
+                        self.unfinished_tables = Ok(unfinished);
+                    },
                     Err(err) => {
-                        self.fetch_items = Err("Failed to fetch offers and tables. 2".to_string());
-                        self.fetch_vtables = Err("Failed to fetch offers and tables. 2".to_string());
-                        return Task::none();
+                        notify.error(&err, Some(OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into()));
+                        self.unfinished_tables = Err(err);
                     }
-                };
-                self.fetch_vtables = Ok(response.tables);
-                self.fetch_items = Ok(response.offers);
-                // eprintln!("Fetched data: {:#?}", self);
-                return Task::perform(
-                    async move {}, 
-                    |result| OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into(),
-                );    
+                }
+                Task::none()
+            }
+            OrderListMessage::FetchedVirtualTablesAndItems(generation, fetch) => {
+                if !self.tables_request.finish(generation) {
+                    return Task::none()
+                }
+                let fetched = fetch.and_then(req_resp::decode::<req_resp::OffersTablesResponseData>);
+                if let Ok(response) = &fetched {
+                    self.confirmation_required = response.confirmation_required;
+                }
+                let (tables, saved) = cache::fallback(cache::TABLES, fetched.as_ref().map(|response| response.tables.clone()).map_err(Clone::clone));
+                let (offers, _) = cache::fallback(cache::OFFERS, fetched.map(|response| response.offers));
+                self.cached = saved;
+                if let Err(err) = &tables {
+                    notify.error(err, Some(super::UIMessage::TabSelected(super::UITabID::Orders).into()));
+                    self.fetch_items = offers;
+                    self.fetch_vtables = tables;
+                    return Task::none();
+                }
+                self.fetch_vtables = tables;
+                self.fetch_items = offers;
+                return Task::batch([
+                    Task::perform(
+                        async move {}, 
+                        |result| OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into(),
+                    ),
+                    self.fetch_pending(),
+                ]);    
             },
             OrderListMessage::TablePressed(table) => {
                 self.current_vtable = Some(table.clone());
-                let mut request = req_resp::Request {
-                    kind: req_resp::RequestKind::Orders,
-                    payload: Some(serde_json::to_value(req_resp::OrdersRequestData {
-                        new: true,
-                        table: Some(table)
-                    }).unwrap())
-                };
-                return Task::perform(
-                    async move {request.send_request("".to_string()).await}, 
-                    |result| OrderListMessage::FetchedOrderAndUnfinished(result).into(),
-                );
+                self.merge_target = None;
+                if self.bill.is_some() {
+                    return Task::batch([self.fetch_bill(), self.fetch_orders_of(table)])
+                }
+                self.fetch_orders_of(table)
             }
             OrderListMessage::PollOrders => {
+                let pending = self.fetch_pending();
+                // Let a slow fetch finish instead of restarting it.
+                if self.orders_request.is_running() {
+                    return pending
+                }
                 if let Some(current_table) = &self.current_vtable {
                     // Fetch new orders for the currently selected table
-                    let table = current_table.clone();
-                    let mut request = req_resp::Request {
-                        kind: req_resp::RequestKind::Orders,
-                        payload: Some(serde_json::to_value(req_resp::OrdersRequestData {
-                            new: true,
-                            table: Some(table)
-                        }).unwrap())
-                    };
-                    return Task::perform(
-                        async move {request.send_request("".to_string()).await}, 
-                        |result| OrderListMessage::FetchedOrderAndUnfinished(result).into(),
-                    );
-                }
-                Task::none() // Do nothing if no table is selected
-            }
-            OrderListMessage::FetchedOrders(orders) => {
-                let response = match orders {
-                    Ok(value) => value,
-                    Err(err) => {
-                        self.fetch_orders = Err(err.to_string());
-                        return Task::none();
-                    }
-                };
-                match serde_json::from_value::<req_resp::OrdersResponseData>(response) {
-                    Ok(payload) => self.fetch_orders = Ok(payload.orders),
-                    Err(err) => self.fetch_orders = Err(err.to_string())
+                    return Task::batch([pending, self.fetch_orders_of(current_table.clone())]);
+                }
+                pending // Do nothing else if no table is selected
+            }
+            OrderListMessage::FetchedOrders(generation, orders) => {
+                if !self.orders_request.finish(generation) {
+                    return Task::none()
                 }
-                eprintln!("Fetched orders: {:?}", self.fetch_orders);
+                self.apply_orders(orders, notify);
                 return Task::none()
             }
-            OrderListMessage::FetchedOrderAndUnfinished(orders) => {
-                let response = match orders {
-                    Ok(value) => value,
-                    Err(err) => {
-                        self.fetch_orders = Err(err.to_string());
-                        return Task::none();
-                    }
-                };
-                match serde_json::from_value::<req_resp::OrdersResponseData>(response) {
-                    Ok(payload) => self.fetch_orders = Ok(payload.orders),
-                    Err(err) => self.fetch_orders = Err(err.to_string())
+            OrderListMessage::FetchedOrderAndUnfinished(generation, orders) => {
+                if !self.orders_request.finish(generation) {
+                    return Task::none()
                 }
-                eprintln!("Fetched orders: {:?}", self.fetch_orders);
+                self.apply_orders(orders, notify);
                 return Task::perform(
                     async move {}, 
                     |result| OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into(),
                 );            
             }
             OrderListMessage::FinishOrder(order_id) => {
-                let mut request = req_resp::Request {
-                    kind: req_resp::RequestKind::OrdersFinish,
-                    payload: Some(serde_json::to_value(req_resp::OrdersFinishRequestData {
-                        order: dbt::Order {
-                            id: order_id.clone(),
-                            ..Default::default()
-                        } 
-                    }).unwrap())
+                let before = self.fetch_orders.as_ref().ok()
+                    .and_then(|orders| orders.iter().find(|order| order.id.table == order_id.table && order.id.count == order_id.count))
+                    .cloned();
+                let after = before.clone().map(|order| dbt::Order { finished: true, ..order });
+                let audit = AuditEntry::new(
+                    username,
+                    AuditAction::FinishOrder,
+                    &format!("{} #{}", order_id.table, order_id.count),
+                    before.as_ref(),
+                    after.as_ref()
+                );
+                let mut request = match req_resp::Request::encode(req_resp::RequestKind::OrdersFinish, &req_resp::OrdersFinishRequestData {
+                    order: dbt::Order {
+                        id: order_id.clone(),
+                        // The server refuses the change if the order
+                        // moved on since it was fetched.
+                        version: before.as_ref().map_or(0, |order| order.version),
+                        ..Default::default()
+                    } 
+                }) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        notify.error(&err, None);
+                        return Task::none()
+                    }
                 };
-                self.fetch_orders = Err("Refetching orders...".to_string());
+                let key = req_resp::new_idempotency_key();
+                let change = Box::new(QueuedChange::new(key.clone(), &request, "".to_string(), audit));
+                self.fetch_orders = Err(Error::pending("Refetching orders..."));
                 return Task::perform(
-                    async move {request.send_request("".to_string()).await}, 
+                    async move {request.send_request_with_key("".to_string(), Some(key)).await}, 
                     move |value| {
-                        OrderListMessage::UpdateOrders(value).into()
+                        OrderListMessage::UpdateOrders(order_id.clone(), value, change.clone()).into()
+                    }
+                )
+            }
+            OrderListMessage::StationDone(order_id, station) => {
+                let Some(before) = self.fetch_orders.as_ref().ok()
+                    .and_then(|orders| orders.iter().find(|order| order.id.table == order_id.table && order.id.count == order_id.count))
+                    .cloned() else {
+                    return Task::none()
+                };
+                let (mut request, key, change) = match crate::stations::done_request(&before, vec![station], username) {
+                    Ok(done) => done,
+                    Err(err) => {
+                        notify.error(&err, None);
+                        return Task::none()
+                    }
+                };
+                self.fetch_orders = Err(Error::pending("Refetching orders..."));
+                return Task::perform(
+                    async move {request.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| OrderListMessage::StationDonePost(order_id.clone(), value, change.clone()).into()
+                )
+            }
+            OrderListMessage::StationDonePost(order_id, result, change) => {
+                match result {
+                    Ok(value) => {
+                        change.audit.commit();
+                        match req_resp::decode::<req_resp::OrdersStationDoneResponseData>(value) {
+                            Ok(response) if response.order.finished => notify.success(format!("Order {} finished, all stations are done", change.audit.target)),
+                            Ok(_) => notify.success(format!("Order {} done", change.audit.target)),
+                            Err(err) => notify.error(&error_forward!(err), None),
+                        }
+                        return self.fetch_orders_of(order_id.table);
+                    },
+                    Err(err) if err.kind == ErrorKind::Network => self.queue_offline(change, &order_id, err, notify),
+                    Err(err) if err.kind == ErrorKind::Conflict => self.report_conflict(err, &order_id, notify),
+                    Err(err) => {
+                        let err = error_forward!(err);
+                        notify.error(&err, None);
+                        self.fetch_orders = Err(err);
+                        return Task::none();
+                    }
+                }
+            }
+            OrderListMessage::ItemStatus(order_id, index, status) => {
+                let Some(before) = self.fetch_orders.as_ref().ok()
+                    .and_then(|orders| orders.iter().find(|order| order.id.table == order_id.table && order.id.count == order_id.count))
+                    .cloned() else {
+                    return Task::none()
+                };
+                let Some(item) = before.items.get(index).filter(|item| item.status != status) else {
+                    return Task::none()
+                };
+                let mut after = before.clone();
+                after.items[index].status = status;
+                let audit = AuditEntry::new(
+                    username,
+                    AuditAction::ItemStatus,
+                    &format!("{} #{} {} ({})", order_id.table, order_id.count, item.id, status),
+                    Some(&before),
+                    Some(&after)
+                );
+                let mut request = match req_resp::Request::encode(req_resp::RequestKind::OrdersItemStatus, &req_resp::OrdersItemStatusRequestData {
+                    order: dbt::Order {
+                        id: order_id.clone(),
+                        version: before.version,
+                        ..Default::default()
+                    },
+                    item: index,
+                    status
+                }) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        notify.error(&err, None);
+                        return Task::none()
                     }
+                };
+                let key = req_resp::new_idempotency_key();
+                let change = Box::new(QueuedChange::new(key.clone(), &request, "".to_string(), audit));
+                // Toggled right away, the server's answer replaces it.
+                self.replace_order(after);
+                return Task::perform(
+                    async move {request.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| OrderListMessage::ItemStatusPost(order_id.clone(), value, change.clone()).into()
                 )
             }
-            OrderListMessage::UpdateOrders(result) => {
+            OrderListMessage::ItemStatusPost(order_id, result, change) => {
+                match result {
+                    Ok(value) => {
+                        change.audit.commit();
+                        let response = match req_resp::decode::<req_resp::OrdersItemStatusResponseData>(value) {
+                            Ok(payload) => payload,
+                            Err(err) => {
+                                notify.error(&error_forward!(err), None);
+                                return self.fetch_orders_of(order_id.table);
+                            }
+                        };
+                        if response.order.finished {
+                            notify.success(format!("Order {} #{} finished, every item is served", order_id.table, order_id.count));
+                            return self.fetch_orders_of(order_id.table);
+                        }
+                        let served = response.order.all_served();
+                        self.replace_order(response.order);
+                        // Servers that do not finish served orders
+                        // on their own get asked to.
+                        if served {
+                            return Task::done(OrderListMessage::FinishOrder(order_id).into());
+                        }
+                        Task::none()
+                    },
+                    Err(err) if err.kind == ErrorKind::Network => self.queue_offline(change, &order_id, err, notify),
+                    Err(err) if err.kind == ErrorKind::Conflict => self.report_conflict(err, &order_id, notify),
+                    Err(err) => {
+                        // Refetched to undo the toggle.
+                        notify.error(&error_forward!(err), None);
+                        self.fetch_orders_of(order_id.table)
+                    }
+                }
+            }
+            OrderListMessage::UpdateOrders(order_id, result, change) => {
                 match result {
                     Ok(value) => {
-                        let response = match serde_json::from_value::<req_resp::OrdersFinishResponseData>(value) {
+                        change.audit.commit();
+                        notify.success(format!("Order {} finished", change.audit.target));
+                        let response = match req_resp::decode::<req_resp::OrdersFinishResponseData>(value) {
                             Ok(payload) => payload,
                             Err(err) => {
-                                self.fetch_orders = Err(format!("Something went wrong when deleting: {}", err));
+                                let err = error_forward!(err);
+                                notify.error(&err, None);
+                                self.fetch_orders = Err(err);
                                 return Task::none();
                             }
                         };
@@ -236,20 +800,14 @@ impl OrderList {
                             response.table
                         };
 
-                        let mut request = req_resp::Request {
-                            kind: req_resp::RequestKind::Orders,
-                            payload: Some(serde_json::to_value(req_resp::OrdersRequestData {
-                                new: true,
-                                table: Some(current_table)
-                            }).unwrap())
-                        };
-                        return Task::perform(
-                            async move {request.send_request("".to_string()).await}, 
-                            |result| OrderListMessage::FetchedOrderAndUnfinished(result).into(),
-                        );
+                        return self.fetch_orders_of(current_table);
                     },
+                    Err(err) if err.kind == ErrorKind::Network => self.queue_offline(change, &order_id, err, notify),
+                    Err(err) if err.kind == ErrorKind::Conflict => self.report_conflict(err, &order_id, notify),
                     Err(err) => {
-                        self.fetch_orders = Err(format!("Something went wrong when deleting: {}", err));
+                        let err = error_forward!(err);
+                        notify.error(&err, Some(OrderListMessage::FinishOrder(order_id).into()));
+                        self.fetch_orders = Err(err);
                         return Task::none();
                     }
                 }
@@ -258,6 +816,470 @@ impl OrderList {
 
     }
 
+    /// Applies a change made on the server to the shown tables,
+    /// offers and orders. Only when a table may have no open orders
+    /// left, which is not known here, are they fetched again.
+    pub fn apply_change(&mut self, change: &req_resp::Change) -> Task<Message> {
+
+        if self.apply_pending(change) {
+            return Task::none()
+        }
+
+        match change {
+            req_resp::Change::TableAdded { table } |
+            req_resp::Change::TableChanged { table } => {
+                if let Ok(vtables) = &mut self.fetch_vtables {
+                    match vtables.iter_mut().find(|vtable| vtable.name == table.name) {
+                        Some(vtable) => *vtable = table.clone(),
+                        None => vtables.push(table.clone())
+                    }
+                }
+            },
+            req_resp::Change::TableDeleted { table } => {
+                if let Ok(vtables) = &mut self.fetch_vtables {
+                    vtables.retain(|vtable| &vtable.name != table);
+                }
+                if let Ok(unfinished) = &mut self.unfinished_tables {
+                    unfinished.retain(|name| name != table);
+                }
+                if self.current_vtable.as_ref() == Some(table) {
+                    self.orders_request.cancel();
+                    self.current_vtable = None;
+                    self.fetch_orders = Err(Error::pending("Click on a table first :)"));
+                }
+            },
+            req_resp::Change::OfferAdded { offer } |
+            req_resp::Change::OfferEdited { offer } => {
+                if let Ok(items) = &mut self.fetch_items {
+                    match items.iter_mut().find(|item| item.name == offer.name) {
+                        Some(item) => *item = offer.clone(),
+                        None => items.push(offer.clone())
+                    }
+                }
+            },
+            req_resp::Change::OfferDeleted { offer } => {
+                if let Ok(items) = &mut self.fetch_items {
+                    items.retain(|item| &item.name != offer);
+                }
+            },
+            req_resp::Change::OrderAdded { order } |
+            req_resp::Change::OrderChanged { order } if !order.finished => {
+                if let Ok(unfinished) = &mut self.unfinished_tables {
+                    if !unfinished.contains(&order.id.table) {
+                        unfinished.push(order.id.table.clone());
+                    }
+                }
+                if self.current_vtable.as_ref() == Some(&order.id.table) {
+                    if let Ok(orders) = &mut self.fetch_orders {
+                        match orders.iter_mut().find(|o| o.id.count == order.id.count) {
+                            Some(o) => *o = order.clone(),
+                            None => orders.push(order.clone())
+                        }
+                    }
+                }
+            },
+            req_resp::Change::OrderMoved { from, order } => return Task::batch([
+                self.apply_change(&req_resp::Change::OrderFinished { order: from.clone() }),
+                self.apply_change(&req_resp::Change::OrderChanged { order: order.clone() }),
+            ]),
+            req_resp::Change::OrderAdded { order: dbt::Order { id, .. } } |
+            req_resp::Change::OrderChanged { order: dbt::Order { id, .. } } |
+            req_resp::Change::OrderFinished { order: id } |
+            req_resp::Change::OrderDenied { order: id, .. } => {
+                if self.current_vtable.as_ref() != Some(&id.table) {
+                    return Task::done(OrderListMessage::PollFetchedTablesWithUnfinishedOrders.into())
+                }
+                if let Ok(orders) = &mut self.fetch_orders {
+                    orders.retain(|order| order.id.count != id.count);
+                    if orders.is_empty() {
+                        if let Ok(unfinished) = &mut self.unfinished_tables {
+                            unfinished.retain(|name| name != &id.table);
+                        }
+                    }
+                }
+            },
+        }
+
+        Task::none()
+
+    }
+
+    /// Keeps the pending queue in step with `change`, `true` if
+    /// its order still waits for confirmation.
+    fn apply_pending(&mut self, change: &req_resp::Change) -> bool {
+        match change {
+            req_resp::Change::OrderAdded { order } |
+            req_resp::Change::OrderChanged { order } if order.pending => {
+                if let Ok(pending) = &mut self.pending {
+                    match pending.iter_mut().find(|shown| shown.order.id.table == order.id.table && shown.order.id.count == order.id.count) {
+                        Some(shown) if shown.placed.version != order.version => *shown = PendingOrder::new(order.clone()),
+                        Some(_) => {},
+                        None => pending.push(PendingOrder::new(order.clone()))
+                    }
+                }
+                return true
+            },
+            req_resp::Change::OrderAdded { order } |
+            req_resp::Change::OrderChanged { order } => self.remove_pending(&order.id),
+            req_resp::Change::OrderFinished { order } |
+            req_resp::Change::OrderDenied { order, .. } |
+            req_resp::Change::OrderMoved { from: order, .. } => self.remove_pending(order),
+            req_resp::Change::TableDeleted { table } => if let Ok(pending) = &mut self.pending {
+                pending.retain(|shown| &shown.order.id.table != table);
+            },
+            _ => {}
+        }
+        false
+    }
+
+    fn pending_order(&mut self, order_id: &dbt::OrderID) -> Option<&mut PendingOrder> {
+        self.pending.as_mut().ok()?
+            .iter_mut()
+            .find(|shown| shown.order.id.table == order_id.table && shown.order.id.count == order_id.count)
+    }
+
+    fn remove_pending(&mut self, order_id: &dbt::OrderID) {
+        if let Ok(pending) = &mut self.pending {
+            pending.retain(|shown| shown.order.id.table != order_id.table || shown.order.id.count != order_id.count);
+        }
+    }
+
+    /// Reports a refused accept or deny and lets staff try again.
+    fn pending_failed(&mut self, order_id: &dbt::OrderID, err: Error, notify: &mut Notifications) -> Task<Message> {
+        if let Some(shown) = self.pending_order(order_id) {
+            shown.sending = false;
+        }
+        match err.kind {
+            ErrorKind::Conflict => {
+                notify.error(&conflict(err, order_id, "The orders to confirm"), None);
+                self.fetch_pending()
+            },
+            _ => {
+                notify.error(&error_forward!(err), None);
+                Task::none()
+            }
+        }
+    }
+
+    /// Fetches the QR orders waiting for confirmation, unless
+    /// a fetch is running already.
+    fn fetch_pending(&mut self) -> Task<Message> {
+        if self.pending_request.is_running() {
+            return Task::none()
+        }
+        self.pending_request.start(|generation| {
+            let mut request = req_resp::Request {
+                kind: req_resp::RequestKind::OrdersPending,
+                payload: None
+            };
+            Task::perform(
+                async move {request.send_request("".to_string()).await},
+                move |result| OrderListMessage::FetchedPending(generation, result).into(),
+            )
+        })
+    }
+
+    fn pending_view(&self) -> iced::Element<'_, Message> {
+
+        let header = row![
+            checkbox("Confirm QR orders before they are prepared", self.confirmation_required)
+                .on_toggle(|required| OrderListMessage::RequireConfirmation(required).into()),
+            horizontal_space(),
+            button("Back to orders")
+                .style(button::secondary)
+                .on_press(OrderListMessage::ShowPending(false).into()),
+        ].spacing(10).align_y(iced::alignment::Vertical::Center);
+
+        let pending = match &self.pending {
+            Ok(pending) => pending,
+            Err(err) => return column![header, text!("{}", err.summary())].spacing(20).padding(20).width(Length::Fixed(ORDER_LIST_WIDTH)).into()
+        };
+        if pending.is_empty() {
+            return column![header, text("No orders waiting for confirmation")].spacing(20).padding(20).width(Length::Fixed(ORDER_LIST_WIDTH)).into()
+        }
+
+        let mut list = column![header].spacing(20).padding(20).width(Length::Fixed(ORDER_LIST_WIDTH));
+        for shown in pending.iter() {
+            let order_id = shown.order.id.clone();
+            let idle = !shown.sending;
+
+            let waiting = shown.order.age().map(|age| {
+                let ageing = self.ageing;
+                text!("Waiting {} min", age.as_secs() / 60)
+                    .style(move |theme: &Theme| text::Style {
+                        color: Some(super::age_color(theme, ageing.of(age)))
+                    })
+            });
+
+            let items = shown.order.items.iter().enumerate().fold(
+                Column::new().spacing(6),
+                |items, (index, item)| items.push(
+                    container(row![
+                        text!("{}", item.id).width(Length::Fill),
+                        button("−")
+                            .style(button::secondary)
+                            .on_press_maybe(idle.then(|| OrderListMessage::PendingItemCount(order_id.clone(), index, item.count.saturating_sub(1)).into())),
+                        text!("{}", item.count),
+                        button("+")
+                            .style(button::secondary)
+                            .on_press_maybe(idle.then(|| OrderListMessage::PendingItemCount(order_id.clone(), index, item.count + 1).into())),
+                    ].spacing(10).align_y(iced::alignment::Vertical::Center))
+                    .style(order_item_container_style)
+                    .padding(10)
+                )
+            );
+
+            let reason_id = order_id.clone();
+            list = list.push(
+                column![
+                    row![text!("{} #{}", order_id.table, order_id.count).size(24), horizontal_space()]
+                        .push_maybe(waiting)
+                        .align_y(iced::alignment::Vertical::Center),
+                    items,
+                ]
+                .push_maybe(shown.edited.then(|| text("Edited, the order is accepted as shown").size(14)))
+                .push(
+                    text_input("Why it is denied, shown to the customer", &shown.reason)
+                        .on_input(move |reason| OrderListMessage::DenyReasonChanged(reason_id.clone(), reason).into())
+                )
+                .push(row![
+                    horizontal_space(),
+                    button("Deny")
+                        .style(button::danger)
+                        .on_press_maybe((idle && !shown.reason.trim().is_empty()).then(|| OrderListMessage::DenyOrder(order_id.clone()).into())),
+                    button("Accept")
+                        .on_press_maybe((idle && !shown.order.items.is_empty()).then(|| OrderListMessage::AcceptOrder(order_id.clone()).into())),
+                ].spacing(10))
+                .push(horizontal_rule(2))
+                .spacing(10)
+            );
+        }
+        list.into()
+
+    }
+
+    /// Fetches the tables and offers, then the unfinished orders.
+    pub fn fetch(&mut self) -> Task<Message> {
+        self.tables_request.start(|generation| {
+            let mut request = req_resp::Request {
+                kind: req_resp::RequestKind::OffersTables,
+                payload: None
+            };
+            Task::perform(
+                async move {request.send_request("".to_string()).await}, 
+                move |value| {OrderListMessage::FetchedVirtualTablesAndItems(generation, value).into()}
+            )
+        })
+    }
+
+    /// Fetches every order of the current table, the unpaid ones
+    /// make up its bill.
+    fn fetch_bill(&mut self) -> Task<Message> {
+        let Some(table) = self.current_vtable.clone() else {
+            return Task::none()
+        };
+        self.bill = Some(Err(Error::pending("Adding up the bill...")));
+        self.bill_request.start(|generation| {
+            let request = req_resp::Request::encode(req_resp::RequestKind::Orders, &req_resp::OrdersRequestData {
+                new: false,
+                table: Some(table)
+            });
+            Task::perform(
+                async move {request?.send_request("".to_string()).await},
+                move |result| OrderListMessage::FetchedBill(generation, result).into(),
+            )
+        })
+    }
+
+    fn bill_view<'a>(&self, bill: &'a Bill) -> iced::Element<'a, Message> {
+
+        // Paid up, the guests no longer order from their phones.
+        let open = self.fetch_vtables.as_ref().is_ok_and(|vtables| vtables
+            .iter()
+            .any(|vtable| vtable.name == bill.table && vtable.session.as_ref().is_some_and(dbt::TableSession::is_open))
+        );
+
+        let lines = bill.lines.iter().fold(
+            Column::new().spacing(6),
+            |lines, line| lines.push(row![
+                text!("{}", line.offer).width(Length::Fill),
+                text!("{} × {}", line.count, bill::money(line.unit)).width(Length::Fixed(140.0)),
+                text!("{}", bill::money(line.total)).width(Length::Fixed(90.0)).align_x(Horizontal::Right),
+            ])
+        );
+
+        let sum = |label: String, cents: u64| row![
+            text(label).width(Length::Fill),
+            text!("{}", bill::money(cents)).width(Length::Fixed(90.0)).align_x(Horizontal::Right),
+        ];
+
+        column![
+            text!("{} unpaid orders", bill.orders),
+            lines,
+        ]
+        .push_maybe((!bill.unknown.is_empty()).then(|| text!(
+            "Not billed, no longer offered: {}", bill.unknown.join(", ")
+        ).style(text::danger)))
+        .push(horizontal_rule(2))
+        .push(sum(match bill.tax.included {
+            true => "Subtotal (without tax)".to_string(),
+            false => "Subtotal".to_string()
+        }, bill.subtotal))
+        .push(sum(format!("Tax {} %", bill.tax.percent), bill.tax_amount))
+        .push(sum("Total".to_string(), bill.total))
+        .push(row![
+            button("Back to orders")
+                .style(button::secondary)
+                .on_press(OrderListMessage::ShowBill(false).into()),
+            horizontal_space(),
+            button("Export").on_press(OrderListMessage::ExportBill.into()),
+            button(if self.printing { "Printing..." } else { "Print" })
+                .on_press_maybe((!self.printing).then(|| OrderListMessage::PrintBill.into())),
+        ]
+        .push_maybe(open.then(|| button("Close table")
+            .style(button::secondary)
+            .on_press(VirtualTableManagerMessage::CloseSession(bill.table.clone()).into())
+        ))
+        .spacing(10))
+        .spacing(12)
+        .padding(20)
+        .width(Length::Fixed(480.0))
+        .into()
+
+    }
+
+    /// Fetches the open orders of `table`, replacing a running
+    /// fetch, e.g. of the previously selected table.
+    fn fetch_orders_of(&mut self, table: dbt::VirtualTableID) -> Task<Message> {
+        self.orders_request.start(|generation| {
+            let request = req_resp::Request::encode(req_resp::RequestKind::Orders, &req_resp::OrdersRequestData {
+                new: true,
+                table: Some(table)
+            });
+            Task::perform(
+                async move {request?.send_request("".to_string()).await}, 
+                move |result| OrderListMessage::FetchedOrderAndUnfinished(generation, result).into(),
+            )
+        })
+    }
+
+    /// Aborts all fetches, e.g. when the tab is left.
+    pub fn cancel_requests(&mut self) {
+        self.bill_request.cancel();
+        self.pending_request.cancel();
+        self.tables_request.cancel();
+        self.orders_request.cancel();
+        self.unfinished_request.cancel();
+    }
+
+    /// Shows fetched orders of the current table and keeps them
+    /// in the offline cache, or the cached ones while offline.
+    fn apply_orders(&mut self, orders: Result<serde_json::Value, Error>, notify: &mut Notifications) {
+
+        let fetched = orders
+            .and_then(req_resp::decode::<req_resp::OrdersResponseData>)
+            .map(|payload| payload.orders);
+
+        let Some(table) = self.current_vtable.clone() else {
+            self.fetch_orders = fetched;
+            return
+        };
+
+        match fetched {
+            Ok(orders) => {
+                cache::modify::<OpenOrders>(cache::ORDERS, |open| {
+                    open.insert(table, orders.clone());
+                });
+                self.cached = None;
+                self.fetch_orders = Ok(orders);
+            },
+            Err(err) => self.show_cached_orders(&table, err, notify)
+        }
+
+    }
+
+    /// Shows `order` in place of the one with its id.
+    fn replace_order(&mut self, order: dbt::Order) {
+        if let Ok(orders) = &mut self.fetch_orders {
+            if let Some(shown) = orders.iter_mut().find(|shown| shown.id.table == order.id.table && shown.id.count == order.id.count) {
+                *shown = order;
+            }
+        }
+    }
+
+    /// Reports that someone else changed `order` first and
+    /// reloads the orders of its table.
+    fn report_conflict(&mut self, err: Error, order: &dbt::OrderID, notify: &mut Notifications) -> Task<Message> {
+        notify.error(&conflict(err, order, "The orders"), None);
+        self.fetch_orders_of(order.table.clone())
+    }
+
+    /// Queues `change` to `order` made while the server is
+    /// unreachable and shows the cached orders with it applied.
+    fn queue_offline(&mut self, change: Box<QueuedChange>, order: &dbt::OrderID, err: Error, notify: &mut Notifications) -> Task<Message> {
+        if !queue_offline(change, order, notify) {
+            return self.fetch_orders_of(order.table.clone())
+        }
+        self.show_cached_orders(&order.table, err, notify);
+        Task::none()
+    }
+
+    /// Falls back to the cached orders of `table` on a network
+    /// error, anything else is reported.
+    fn show_cached_orders(&mut self, table: &str, err: Error, notify: &mut Notifications) {
+
+        let cached = match err.kind {
+            ErrorKind::Network => cache::load::<OpenOrders>(cache::ORDERS),
+            _ => None
+        };
+
+        match cached {
+            Some(snapshot) => {
+                self.fetch_orders = Ok(snapshot.data.get(table).cloned().unwrap_or_default());
+                self.cached = Some(snapshot.saved);
+            },
+            None => {
+                notify.error(&err, Some(OrderListMessage::TablePressed(table.to_string()).into()));
+                self.fetch_orders = Err(err);
+            }
+        }
+
+    }
+
+}
+
+/// `err` of a change to `order` the server refused because someone
+/// else changed the order first, once `reloaded` were fetched again.
+pub(super) fn conflict(mut err: Error, order: &dbt::OrderID, reloaded: &str) -> Error {
+    err.message = format!("{}. {} were reloaded, check them before trying again", err.summary(), reloaded);
+    err.name = format!("Order {} #{} was already changed by someone else", order.table, order.count);
+    err
+}
+
+/// Queues `change` to `order` for when the server is back and
+/// puts the order as it is after the change into the offline
+/// cache, so it shows until the change is sent. `false` if it
+/// could not be queued, which was reported.
+pub(super) fn queue_offline(change: Box<QueuedChange>, order: &dbt::OrderID, notify: &mut Notifications) -> bool {
+
+    let after = change.audit.after.clone().and_then(|after| serde_json::from_value::<dbt::Order>(after).ok());
+    let queued = format!("Server unreachable, {} `{}` is queued", change.audit.action, change.audit.target);
+
+    if let Err(err) = change.enqueue() {
+        notify.error(&err, None);
+        return false
+    }
+    notify.success(queued);
+
+    cache::modify::<OpenOrders>(cache::ORDERS, |open| {
+        if let Some(orders) = open.get_mut(&order.table) {
+            orders.retain(|shown| shown.id.count != order.count);
+            orders.extend(after.filter(|after| !after.finished && !after.pending));
+        }
+    });
+
+    true
+
 }
 
 fn virtual_table_button_style_new_order(theme: &Theme, status: iced::widget::button::Status) -> button::Style {
@@ -382,8 +1404,28 @@ impl Tab for OrderList {
                 .width(VIRTUAL_TABLE_LIST_WIDTH)
                 .align_x(iced::alignment::Horizontal::Center);
 
+        let bill = self.current_vtable.as_ref().map(|_| {
+            button(match self.bill.is_some() {
+                true => "Orders",
+                false => "Bill"
+            })
+            .style(button::secondary)
+            .on_press(OrderListMessage::ShowBill(self.bill.is_none()).into())
+        });
+
+        let waiting = self.pending.as_ref().map(Vec::len).unwrap_or(0);
+        let pending = button(text(match self.show_pending {
+            true => "Orders".to_string(),
+            false => format!("To confirm ({})", waiting)
+        }))
+        .style(match waiting > 0 && !self.show_pending {
+            true => button::primary,
+            false => button::secondary
+        })
+        .on_press(OrderListMessage::ShowPending(!self.show_pending).into());
+
         row!(
-            center(text!("{}", order_label).size(26)).height(Length::Shrink),
+            center(row![text!("{}", order_label).size(26)].push_maybe(bill).push(pending).spacing(20).align_y(iced::alignment::Vertical::Center)).height(Length::Shrink),
             virt_table_label,
         ).into()    }
 
@@ -393,8 +1435,8 @@ impl Tab for OrderList {
 
     fn content(&self) -> iced::Element<'_, Self::Message> {
 
-        if self.fetch_vtables.is_err() {
-            return center(text(format!("{:?}", self.fetch_vtables))).into()
+        if let Err(err) = &self.fetch_vtables {
+            return center(text(err.summary())).into()
         }
         
         // TABLE PANE
@@ -437,13 +1479,28 @@ impl Tab for OrderList {
         // ORDER PANE
         let mut order_pane: iced::Element<'_, Self::Message> = text!("").into();
 
-        if self.fetch_orders.is_err() {
-            order_pane = center(text!("fetch_orders: {}", self.fetch_orders.clone().unwrap_err())).into();
-        } else if self.fetch_items.is_err() {
-            order_pane = center(text!("fetch_items: {}", self.fetch_items.clone().unwrap_err())).into();
+        if self.show_pending {
+            order_pane = scrollable(self.pending_view()).into();
+        } else if let Some(Ok(bill)) = &self.bill {
+            order_pane = scrollable(self.bill_view(bill)).into();
+        } else if let Some(Err(err)) = &self.bill {
+            order_pane = column![
+                text!("{}", err.summary()),
+                button("Back to orders")
+                    .style(button::secondary)
+                    .on_press(OrderListMessage::ShowBill(false).into()),
+            ].spacing(10).align_x(Horizontal::Center).into();
+        } else if let Err(err) = &self.fetch_orders {
+            order_pane = center(text!("{}", err.summary())).into();
+        } else if let Err(err) = &self.fetch_items {
+            order_pane = center(text!("{}", err.summary())).into();
         } else {
 
-            let fetched_orders = self.fetch_orders.clone().unwrap();
+            let mut fetched_orders = self.fetch_orders.clone().unwrap();
+            if self.sort_by_age {
+                // Orders without a timestamp last, by number.
+                fetched_orders.sort_by_key(|order| std::cmp::Reverse(order.age()));
+            }
             if fetched_orders.is_empty() {
                 order_pane = center(text!("No active orders for this table :(")).into()
             } else {
@@ -451,14 +1508,102 @@ impl Tab for OrderList {
                 for offer in self.fetch_items.clone().unwrap() {
                     item_map.insert(offer.clone().name, offer);
                 }
-                let mut order_list: Column<'_, Message> = Column::new();
+                // Moving needs the server to number the orders anew.
+                let other_tables: Vec<dbt::VirtualTableID> = match (&self.fetch_vtables, &self.cached) {
+                    (Ok(vtables), None) => vtables
+                        .iter()
+                        .map(|vtable| vtable.name.clone())
+                        .filter(|name| self.current_vtable.as_ref() != Some(name))
+                        .collect(),
+                    _ => Vec::new()
+                };
+                let merge = (!other_tables.is_empty()).then(|| row![
+                    pick_list(
+                        other_tables.clone(),
+                        self.merge_target.clone(),
+                        |table| OrderListMessage::MergeTargetSelected(table).into()
+                    ).placeholder("Merge into..."),
+                    button("Merge")
+                        .style(button::secondary)
+                        .on_press_maybe(self.merge_target.is_some().then(|| OrderListMessage::MergeTables.into())),
+                ].spacing(5));
+                let mut order_list: Column<'_, Message> = Column::new().push(
+                    row![]
+                        .push_maybe(merge)
+                        .push(horizontal_space())
+                        .push(
+                            button(match self.sort_by_age {
+                                true => "Sort by number",
+                                false => "Oldest first"
+                            })
+                            .style(button::secondary)
+                            .on_press(OrderListMessage::SortByAge(!self.sort_by_age).into())
+                        )
+                        .padding(10)
+                );
                 for order in fetched_orders {
+                    let waiting = order.age().map(|age| {
+                        let ageing = self.ageing;
+                        text!("Waiting {} min", age.as_secs() / 60)
+                            .style(move |theme: &Theme| text::Style {
+                                color: Some(super::age_color(theme, ageing.of(age)))
+                            })
+                    });
+
                     let mut total: (u32, u32) = (0,0); 
 
+                    let moved = (!order.transfers.is_empty()).then(|| text!(
+                        "Moved from {}",
+                        order.transfers
+                            .iter()
+                            .map(|transfer| format!("{} #{}", transfer.from.table, transfer.from.count))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ).size(14));
+
+                    let move_to = (!other_tables.is_empty()).then(|| {
+                        let order_id = order.id.clone();
+                        pick_list(
+                            other_tables.clone(),
+                            None::<dbt::VirtualTableID>,
+                            move |table| OrderListMessage::MoveOrder(order_id.clone(), table).into()
+                        ).placeholder("Move to...")
+                    });
+
+                    // Orders spanning stations are done by each on its own,
+                    // this terminal only for the stations it shows.
+                    let stations = order.stations();
+                    let station_row = (stations.len() > 1).then(|| stations.iter().fold(
+                        row![text("Stations:")].spacing(10).align_y(iced::alignment::Vertical::Center),
+                        |row, station| match order.stations_done.contains(station) {
+                            true => row.push(text!("{} ✓", station)),
+                            false => row.push(
+                                button(text!("{} done", station))
+                                    .style(button::secondary)
+                                    .on_press_maybe(crate::stations::shows(station).then(||
+                                        OrderListMessage::StationDone(order.id.clone(), (*station).clone()).into()
+                                    ))
+                            ),
+                        }
+                    ));
+
                     let mut item_list: Column<'_, Message> = Column::new()
                         .spacing(10);
 
-                    for item in order.items {
+                    for (index, item) in order.items.iter().enumerate() {
+                        let statuses = dbt::ItemStatus::ALL.iter().fold(
+                            row![].spacing(5),
+                            |statuses, status| statuses.push(
+                                button(text!("{}", status).size(14))
+                                    .style(match *status == item.status {
+                                        true => button::primary,
+                                        false => button::secondary
+                                    })
+                                    .on_press_maybe((*status != item.status).then(||
+                                        OrderListMessage::ItemStatus(order.id.clone(), index, *status).into()
+                                    ))
+                            )
+                        );
                         let actual_item = match item_map.get(&item.id) {
                             Some(result) => result,
                             None => {
@@ -475,7 +1620,8 @@ impl Tab for OrderList {
                                     row![text!("{}", actual_item.name), horizontal_space(), text!("Price: {}.{}", 
                                         actual_item.price_integer, 
                                         actual_item.price_fraction).height(Length::Shrink)],
-                                    row![horizontal_space(), text!("Quantity: {}", item.count)].height(Length::Shrink)
+                                    row![text!("Station: {}", item.station), horizontal_space(), text!("Quantity: {}", item.count)].height(Length::Shrink),
+                                    statuses
                                 ]
                             )
                             .height(Length::Shrink)
@@ -492,12 +1638,19 @@ impl Tab for OrderList {
                     order_list = order_list.push(
                         column![
                             column![
-                                row![text!("Order {}", order.id.count).size(24), horizontal_space()],
+                                row![text!("Order {}", order.id.count).size(24), horizontal_space()]
+                                    .push_maybe(waiting)
+                                    .align_y(iced::alignment::Vertical::Center),
                                 item_list,
-                                row![
+                            ]
+                            .push_maybe(moved)
+                            .push_maybe(station_row)
+                            .push(row![
                                     text!("Total: {}.{}", total.0 + total.1/100, total.1%100),
                                     horizontal_space(),
-                                    button(
+                                ]
+                                .push_maybe(move_to)
+                                .push(button(
                                         text!("Finish").font(iced::font::Font {
                                             weight: iced::font::Weight::Bold,
                                             ..Default::default()
@@ -505,10 +1658,10 @@ impl Tab for OrderList {
                                         .height(Length::Shrink)
                                     )
                                     .style(virtual_table_button_style)
-                                    .on_press(OrderListMessage::FinishOrder(order.id.clone()).into())
-                                ]
-                                .height(Length::Shrink),
-                                ].spacing(10),
+                                    .on_press(OrderListMessage::FinishOrder(order.id.clone()).into()))
+                                .spacing(10)
+                                .height(Length::Shrink))
+                            .spacing(10),
                                 horizontal_rule(2)
                         ]
                         .height(Length::Shrink)
@@ -550,6 +1703,10 @@ impl Tab for OrderList {
                 }
             ),
         ];
+
+        if let Some(saved) = &self.cached {
+            return column![super::offline_banner(saved), center(panes)].spacing(10).into()
+        }
         
         return center(panes).into()
 
@@ -1,14 +1,12 @@
-use std::path::PathBuf;
-
 use iced::alignment::Horizontal;
 use iced::widget::text::Style;
-use iced::widget::{image, center, column, horizontal_rule, vertical_rule, horizontal_space, radio, row, text, Radio};
+use iced::widget::{button, checkbox, image, center, column, horizontal_rule, vertical_rule, horizontal_space, pick_list, radio, row, scrollable, text, text_input, Column, Radio};
 use iced::{font, Length};
 use iced_aw::iced_fonts;
 use serde::de;
 
 use crate::{error, user};
-use crate::error::{error, function_message};
+use crate::error::{error, function_message, ErrorKind};
 
 use super::Tab;
 
@@ -18,7 +16,28 @@ const ICON:  char = '\u{E995}';
 #[derive(Debug, Clone)]
 pub enum SettingsMessage {
     ThemeChanged(crate::user::SettingTheme),
-    TabPositionChanged(crate::user::SettingTabPosition)
+    TabPositionChanged(crate::user::SettingTabPosition),
+    AutoLockChanged(u32),
+    StartModeChanged(crate::user::SettingStartMode),
+    LateAfterChanged(u32),
+    OverdueAfterChanged(u32),
+    OverdueAlertToggled(bool),
+    NewOrderAlertToggled(bool),
+    DeniedAlertToggled(bool),
+    DesktopNotificationsToggled(bool),
+    MutedToggled(bool),
+    VolumeChanged(u32),
+    QuietHoursToggled(bool),
+    QuietFromChanged(u32),
+    QuietUntilChanged(u32),
+    TaxChanged(u32),
+    PricesIncludeTaxToggled(bool),
+    /// Plays the new order alert at the set volume.
+    TestSound,
+    PinFieldChanged(String),
+    SetPin,
+    StationsFieldChanged(String),
+    SetStations
 }
 
 impl Into<crate::Message> for SettingsMessage {
@@ -27,10 +46,17 @@ impl Into<crate::Message> for SettingsMessage {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Debug, Clone)]
 pub struct Settings {
     pub user_data:     crate::user::UserData,
     pub user_settings: crate::user::Settings,
+    /// Problems with the user files, shown on top of the tab.
+    pub warnings:      Vec<String>,
+    pub pin_text_input: String,
+    pub pin_issue:     String,
+    /// Stations shown on this terminal, comma separated.
+    pub stations_text_input: String,
+    pub stations_issue: String,
 }
 
 impl Settings {
@@ -38,15 +64,14 @@ impl Settings {
     // Zasad primamo iz `users` datoteke, baza podataka je za 2. sprint
     pub fn from_database(user_entry: &crate::user::UserEntry) -> Result<Self, error::Error> {
 
-        let user_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join(PathBuf::from("users"))
-            .join(PathBuf::from(user_entry.username.clone()));
+        let user_folder = crate::storage::user_folder(&user_entry.username);
 
         let user_data_file = user_folder.clone().join("user.toml");
         let user_settings_file = user_folder.clone().join("settings.toml");
 
         if user_data_file.exists() == false {
             return error!(
+                kind: ErrorKind::Storage,
                 name: "Cannot find `user.toml`",
                 message: format!("No `user.toml` was found for username `{}`", user_entry.username)
             );
@@ -55,6 +80,7 @@ impl Settings {
         let user_data_contents_raw = match std::fs::read_to_string(user_data_file) {
             Ok(raw) => raw,
             Err(err) => return error!(
+                kind: ErrorKind::Storage,
                 name: "Failed to read `user.toml`",
                 message: function_message!("std::fs::read_to_string", err.to_string())
             )
@@ -63,38 +89,186 @@ impl Settings {
         let mut user_data_contents = match toml::from_str::<crate::user::UserData>(&user_data_contents_raw) {
             Ok(contents) => contents,
             Err(err) => return error!(
+                kind: ErrorKind::Decode,
                 name: "Failed to parse `user.toml`",
                 message: function_message!("toml::from_str", err.message())
             )
         };
 
-        let user_settings_contents = if user_settings_file.exists() == false {
-            crate::user::Settings::default()
-        } else {
-            let user_settings_contents_raw = match std::fs::read_to_string(user_settings_file) {
-                Ok(raw) => raw,
-                Err(err) => return error!(
-                    name: "Failed to read `user.toml`",
-                    message: function_message!("std::fs::read_to_string", err.to_string())
-                )
-            };
-            match toml::from_str::<crate::user::Settings>(&user_settings_contents_raw) {
-                Ok(contents) => contents,
-                Err(err) => return error!(
-                    name: "Failed to parse `user.toml`",
-                    message: function_message!("toml::from_str", err.message())
-                )
+        let mut warnings = Vec::new();
+
+        let user_settings_contents = match Settings::load_user_settings(&user_settings_file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log::error!("{}", err);
+                warnings.push(format!(
+                    "Your settings could not be loaded, the defaults are used instead.\n{}",
+                    err.message
+                ));
+                Settings::keep_invalid_file(&user_settings_file);
+                crate::user::Settings::default()
             }
         };
 
+        if user_settings_contents.version > crate::user::SETTINGS_VERSION {
+            warnings.push(format!(
+                "`settings.toml` was written by a newer version of OBY Desktop (version {}), unknown settings are kept but not used.",
+                user_settings_contents.version
+            ));
+        }
+
         user_data_contents.username = user_entry.username.clone();
 
         Ok(Self {
             user_data: user_data_contents,
             user_settings: user_settings_contents,
+            warnings,
+            stations_text_input: crate::stations::current().join(", "),
+            ..Default::default()
         })
 
     }
+
+    /// Used when `from_database` fails, so the user can still
+    /// work with the default settings.
+    pub fn fallback(user_entry: &crate::user::UserEntry, err: &error::Error) -> Self {
+        Self {
+            user_data: crate::user::UserData {
+                username: user_entry.username.clone(),
+                privilege: crate::user::UserPrivilege::Basic,
+                name: user_entry.username.clone(),
+                surname: String::new(),
+                user_image: None
+            },
+            user_settings: crate::user::Settings::default(),
+            warnings: vec![format!(
+                "Your user data could not be loaded, the defaults are used instead.\n{}: {}",
+                err.name, err.message
+            )],
+            stations_text_input: crate::stations::current().join(", "),
+            ..Default::default()
+        }
+    }
+
+    fn load_user_settings(file: &std::path::Path) -> Result<crate::user::Settings, error::Error> {
+
+        if !file.exists() {
+            return Ok(crate::user::Settings::default())
+        }
+
+        let raw = match std::fs::read_to_string(file) {
+            Ok(raw) => raw,
+            Err(err) => return error!(
+                kind: ErrorKind::Storage,
+                name: "Failed to read `settings.toml`",
+                message: function_message!("std::fs::read_to_string", err.to_string())
+            )
+        };
+
+        match toml::from_str::<crate::user::Settings>(&raw) {
+            Ok(contents) => Ok(contents),
+            Err(err) => error!(
+                kind: ErrorKind::Decode,
+                name: "Failed to parse `settings.toml`",
+                message: function_message!("toml::from_str", err.message())
+            )
+        }
+
+    }
+
+    /// Copies an unreadable `settings.toml` to `settings.toml.invalid`
+    /// so the next save does not destroy it.
+    fn keep_invalid_file(file: &std::path::Path) {
+        if file.exists() {
+            if let Err(err) = std::fs::copy(file, file.with_extension("toml.invalid")) {
+                log::error!("Failed to back up `{}`: {}", file.display(), err);
+            }
+        }
+    }
+
+    /// Stores the PIN from the text input as the quick-login
+    /// PIN of the current user.
+    pub fn set_pin(&mut self) {
+
+        if !crate::user::UserEntry::is_valid_pin(&self.pin_text_input) {
+            self.pin_issue = "A PIN has 4 to 8 digits.".to_string();
+            return
+        }
+
+        let mut entry = match crate::login::Login::fetch_username_from_database(&self.user_data.username) {
+            Ok(entry) => entry,
+            Err(err) => {
+                self.pin_issue = err.summary();
+                return
+            }
+        };
+
+        entry.set_pin(&self.pin_text_input);
+
+        self.pin_issue = match entry.save() {
+            Ok(()) => "PIN saved.".to_string(),
+            Err(err) => {
+                log::error!("{}", err);
+                format!("{}: {}", err.name, err.message)
+            }
+        };
+        self.pin_text_input = String::new();
+
+    }
+
+    /// Shows only the stations from the text input on this
+    /// terminal, all of them if it is empty.
+    pub fn set_stations(&mut self) {
+        let stations = crate::stations::parse(&self.stations_text_input);
+        self.stations_issue = match crate::stations::set(stations.clone()) {
+            Ok(()) if stations.is_empty() => "This terminal shows all stations.".to_string(),
+            Ok(()) => format!("This terminal shows {}.", stations.join(", ")),
+            Err(err) => {
+                log::error!("{}", err);
+                err.summary()
+            }
+        };
+        self.stations_text_input = stations.join(", ");
+    }
+
+    /// Atomically writes the user settings to `settings.toml`.
+    pub fn save(&self) -> Result<(), error::Error> {
+
+        let file = crate::storage::user_folder(&self.user_data.username).join("settings.toml");
+
+        // A newer version keeps its number, its settings are kept.
+        let settings = crate::user::Settings {
+            version: self.user_settings.version.max(crate::user::SETTINGS_VERSION),
+            ..self.user_settings.clone()
+        };
+
+        let raw = match toml::to_string(&settings) {
+            Ok(raw) => raw,
+            Err(err) => return error!(
+                kind: ErrorKind::Decode,
+                name: "Failed to serialize `settings.toml`",
+                message: function_message!("toml::to_string", err.to_string())
+            )
+        };
+
+        if let Err(err) = std::fs::create_dir_all(crate::storage::user_folder(&self.user_data.username)) {
+            return error!(
+                kind: ErrorKind::Storage,
+                name: "Failed to write `settings.toml`",
+                message: function_message!("std::fs::create_dir_all", err.to_string())
+            );
+        }
+
+        match crate::storage::write_atomic(&file, &raw) {
+            Ok(()) => Ok(()),
+            Err(err) => error!(
+                kind: ErrorKind::Storage,
+                name: "Failed to write `settings.toml`",
+                message: function_message!("storage::write_atomic", err.to_string())
+            )
+        }
+
+    }
  
 }
 
@@ -119,14 +293,17 @@ impl Tab for Settings {
 
     fn content(&self) -> iced::Element<'_, Self::Message> {
 
-        let user_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join(PathBuf::from("users"))
-            .join(PathBuf::from(self.user_data.username.clone()));
+        let user_folder = crate::storage::user_folder(&self.user_data.username);
 
+        let warnings = self.warnings.iter().fold(
+            Column::new().spacing(10),
+            |col, warning| col.push(text!("{}", warning).style(text::danger))
+        );
 
-        center(
+        let panes = center(
             row!(
-                center(
+                // Scrolls once the settings outgrow the window.
+                center(scrollable(
                     column!(
                         row!(
                             text!("Theme: ").font(
@@ -190,7 +367,229 @@ impl Tab for Settings {
                         ),
                         horizontal_rule(2), text!(" "),
 
-                    ).width(Length::Fixed(256.0)),   
+                        row!(
+                            text!("Auto-lock: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            crate::user::AUTO_LOCK_CHOICES.iter().fold(
+                                column!(),
+                                |col, minutes| col.push(row!(
+                                    text!("{} ", match minutes {
+                                        0 => "Off".to_string(),
+                                        _ => format!("{} min", minutes)
+                                    }),
+                                    radio(
+                                        "", 
+                                        *minutes, 
+                                        Some(self.user_settings.auto_lock_minutes), 
+                                        |message| {SettingsMessage::AutoLockChanged(message).into()}
+                                    ),
+                                ))
+                            ).align_x(Horizontal::Right)
+                        ),
+                        horizontal_rule(2), text!(" "),
+
+                        row!(
+                            text!("Start with: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            column!(
+                                row!(
+                                    text!("Tabs "),
+                                    radio(
+                                        "", 
+                                        crate::user::SettingStartMode::Tabs, 
+                                        Some(self.user_settings.start_mode), 
+                                        |message| {SettingsMessage::StartModeChanged(message).into()}
+                                    ),
+                                ),
+                                row!(
+                                    text!("Kitchen display "),
+                                    radio(
+                                        "", 
+                                        crate::user::SettingStartMode::KitchenDisplay, 
+                                        Some(self.user_settings.start_mode), 
+                                        |message| {SettingsMessage::StartModeChanged(message).into()}
+                                    )
+                                ),
+                            ).align_x(Horizontal::Right)
+                        ),
+                        horizontal_rule(2), text!(" "),
+
+                        row!(
+                            text!("Late after: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            crate::user::AGEING_CHOICES.iter().fold(
+                                column!(),
+                                |col, minutes| col.push(row!(
+                                    text!("{} min ", minutes),
+                                    radio(
+                                        "", 
+                                        *minutes, 
+                                        Some(self.user_settings.late_after_minutes), 
+                                        |message| {SettingsMessage::LateAfterChanged(message).into()}
+                                    ),
+                                ))
+                            ).align_x(Horizontal::Right)
+                        ),
+                        horizontal_rule(2), text!(" "),
+
+                        row!(
+                            text!("Overdue after: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            crate::user::AGEING_CHOICES.iter().fold(
+                                column!(),
+                                |col, minutes| col.push(row!(
+                                    text!("{} min ", minutes),
+                                    radio(
+                                        "", 
+                                        *minutes, 
+                                        Some(self.user_settings.overdue_after_minutes), 
+                                        |message| {SettingsMessage::OverdueAfterChanged(message).into()}
+                                    ),
+                                ))
+                            ).align_x(Horizontal::Right)
+                        ),
+                        horizontal_rule(2), text!(" "),
+
+                        text!("Alerts: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                        checkbox("New orders", self.user_settings.new_order_alert)
+                            .on_toggle(|alert| SettingsMessage::NewOrderAlertToggled(alert).into()),
+                        checkbox("Overdue orders", self.user_settings.overdue_alert)
+                            .on_toggle(|alert| SettingsMessage::OverdueAlertToggled(alert).into()),
+                        checkbox("Denied orders", self.user_settings.denied_alert)
+                            .on_toggle(|alert| SettingsMessage::DeniedAlertToggled(alert).into()),
+                        checkbox("Desktop notifications", self.user_settings.desktop_notifications)
+                            .on_toggle(|desktop| SettingsMessage::DesktopNotificationsToggled(desktop).into()),
+                        checkbox("Mute sounds", self.user_settings.muted)
+                            .on_toggle(|muted| SettingsMessage::MutedToggled(muted).into()),
+                        text!(" "),
+
+                        row!(
+                            text!("Volume: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            crate::user::VOLUME_CHOICES.iter().fold(
+                                column!(),
+                                |col, volume| col.push(row!(
+                                    text!("{} % ", volume),
+                                    radio(
+                                        "", 
+                                        *volume, 
+                                        Some(self.user_settings.sound_volume), 
+                                        |message| {SettingsMessage::VolumeChanged(message).into()}
+                                    ),
+                                ))
+                            ).align_x(Horizontal::Right)
+                        ),
+                        button("Test sound")
+                            .style(button::secondary)
+                            .on_press_maybe((!self.user_settings.muted).then(|| SettingsMessage::TestSound.into())),
+                        text!(" "),
+
+                        checkbox("Quiet hours, no sounds or desktop notifications", self.user_settings.quiet_hours)
+                            .on_toggle(|quiet| SettingsMessage::QuietHoursToggled(quiet).into()),
+                        row!(
+                            text!("From "),
+                            pick_list(
+                                (0..24).collect::<Vec<u32>>(),
+                                Some(self.user_settings.quiet_from_hour),
+                                |hour| SettingsMessage::QuietFromChanged(hour).into()
+                            ),
+                            text!(" until "),
+                            pick_list(
+                                (0..24).collect::<Vec<u32>>(),
+                                Some(self.user_settings.quiet_until_hour),
+                                |hour| SettingsMessage::QuietUntilChanged(hour).into()
+                            ),
+                        ).align_y(iced::alignment::Vertical::Center),
+                        horizontal_rule(2), text!(" "),
+
+                        row!(
+                            text!("Tax on bills: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            pick_list(
+                                crate::user::TAX_CHOICES,
+                                Some(self.user_settings.tax_percent),
+                                |percent| SettingsMessage::TaxChanged(percent).into()
+                            ),
+                            text!(" %"),
+                        ).align_y(iced::alignment::Vertical::Center),
+                        checkbox("Prices include the tax", self.user_settings.prices_include_tax)
+                            .on_toggle(|included| SettingsMessage::PricesIncludeTaxToggled(included).into()),
+                        horizontal_rule(2), text!(" "),
+
+                        row!(
+                            text!("Quick-login PIN: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            column!(
+                                text_input("4 to 8 digits", &self.pin_text_input)
+                                    .secure(true)
+                                    .on_input(|s| SettingsMessage::PinFieldChanged(s).into()),
+                                button("Set PIN")
+                                    .on_press(SettingsMessage::SetPin.into()),
+                            ).spacing(5).align_x(Horizontal::Right).width(Length::Fixed(128.0))
+                        ),
+                        text!("{}", self.pin_issue),
+                        horizontal_rule(2), text!(" "),
+
+                        row!(
+                            text!("Stations: ").font(
+                                font::Font {
+                                    weight: iced::font::Weight::Bold,
+                                    ..font::Font::default()
+                                }
+                            ),
+                            horizontal_space(),
+                            column!(
+                                text_input("All, or e.g. bar, grill", &self.stations_text_input)
+                                    .on_input(|s| SettingsMessage::StationsFieldChanged(s).into())
+                                    .on_submit(SettingsMessage::SetStations.into()),
+                                button("Set stations")
+                                    .on_press(SettingsMessage::SetStations.into()),
+                            ).spacing(5).align_x(Horizontal::Right).width(Length::Fixed(128.0))
+                        ),
+                        text!("{}", self.stations_issue),
+                        horizontal_rule(2), text!(" "),
+
+                    ).width(Length::Fixed(256.0))),
                 ),
                 vertical_rule(2),
                 center(
@@ -249,11 +648,20 @@ impl Tab for Settings {
                             horizontal_space(),
                             text!("{}", self.user_data.privilege)
                         ),
-                        horizontal_rule(2)
+                        horizontal_rule(2), text!(" "),
+                        row!(
+                            button("Switch user")
+                                .on_press(crate::Message::SwitchUser),
+                            horizontal_space(),
+                            button("Log out")
+                                .on_press(crate::Message::Logout)
+                        )
                     ).width(Length::Fixed(256.0))
                 )
             )
-        ).into()
+        );
+
+        column!(warnings, panes).spacing(20).into()
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use iced::Task;
+
+use crate::cache;
+use crate::error::Error;
+use crate::shared::{dbt, req_resp};
+use super::orders::OpenOrders;
+
+/// Wait before following the events again after a failed request.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub enum SyncMessage {
+    /// Starts the next long poll, unless one is running.
+    Follow,
+    Received(Result<serde_json::Value, Error>),
+}
+
+impl Into<crate::Message> for SyncMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::UI(super::UIMessage::Sync(self))
+    }
+}
+
+/// What a long poll brought.
+pub enum Received {
+    Changes(Vec<req_resp::Change>),
+    /// Events were missed, e.g. the server restarted or this
+    /// terminal fell behind, the data has to be fetched again.
+    Gap,
+    Failed(Error),
+}
+
+/// Follows the changes other terminals make through the server's
+/// event stream, so the tabs are updated without refetching.
+#[derive(Debug, Default)]
+pub struct LiveSync {
+    /// Last event applied, `None` until following started.
+    last:    Option<u64>,
+    running: bool,
+    /// The last long poll succeeded, polling is not needed.
+    live:    bool,
+}
+
+impl LiveSync {
+
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn follow(&mut self) -> Task<crate::Message> {
+        if self.running {
+            return Task::none()
+        }
+        self.running = true;
+        let request = req_resp::Request::encode(req_resp::RequestKind::Events, &req_resp::EventsRequestData {
+            since: self.last
+        });
+        Task::perform(
+            async move {request?.send_request("".to_string()).await},
+            |result| SyncMessage::Received(result).into()
+        )
+    }
+
+    /// Forgets the position, the next poll starts following
+    /// from now, e.g. after the data was fetched anew.
+    pub fn restart(&mut self) {
+        self.last = None;
+    }
+
+    pub fn received(&mut self, result: Result<serde_json::Value, Error>) -> Received {
+
+        self.running = false;
+
+        let response = match result.and_then(req_resp::decode::<req_resp::EventsResponseData>) {
+            Ok(response) => response,
+            Err(err) => {
+                self.live = false;
+                return Received::Failed(err)
+            }
+        };
+
+        self.live = true;
+
+        let Some(since) = self.last.replace(response.last) else {
+            return Received::Changes(Vec::new())
+        };
+
+        let continuous = response.last == since + response.events.len() as u64 && response.events
+            .iter()
+            .enumerate()
+            .all(|(i, event)| event.seq == since + 1 + i as u64);
+
+        if !continuous {
+            log::warn!("Missed events after {}, the server is at {}.", since, response.last);
+            return Received::Gap
+        }
+
+        let changes: Vec<_> = response.events.into_iter().map(|event| event.change).collect();
+        for change in changes.iter() {
+            store(change);
+        }
+        Received::Changes(changes)
+
+    }
+
+}
+
+/// Keeps the offline cache in step with `change`.
+fn store(change: &req_resp::Change) {
+    match change {
+        req_resp::Change::TableAdded { table } |
+        req_resp::Change::TableChanged { table } => cache::modify::<Vec<dbt::VirtualTable>>(cache::TABLES, |tables| {
+            tables.retain(|t| t.name != table.name);
+            tables.push(table.clone());
+        }),
+        req_resp::Change::TableDeleted { table } => {
+            cache::modify::<Vec<dbt::VirtualTable>>(cache::TABLES, |tables| tables.retain(|t| &t.name != table));
+            cache::modify::<OpenOrders>(cache::ORDERS, |open| { open.remove(table); });
+        },
+        req_resp::Change::OfferAdded { offer } |
+        req_resp::Change::OfferEdited { offer } => cache::modify::<Vec<dbt::Offer>>(cache::OFFERS, |offers| {
+            offers.retain(|o| o.name != offer.name);
+            offers.push(offer.clone());
+        }),
+        req_resp::Change::OfferDeleted { offer } => cache::modify::<Vec<dbt::Offer>>(cache::OFFERS, |offers| offers.retain(|o| &o.name != offer)),
+        req_resp::Change::OrderAdded { order } |
+        req_resp::Change::OrderChanged { order } => cache::modify::<OpenOrders>(cache::ORDERS, |open| {
+            let orders = open.entry(order.id.table.clone()).or_default();
+            orders.retain(|o| o.id.count != order.id.count);
+            if !order.finished && !order.pending {
+                orders.push(order.clone());
+            }
+        }),
+        req_resp::Change::OrderMoved { from, order } => {
+            store(&req_resp::Change::OrderFinished { order: from.clone() });
+            store(&req_resp::Change::OrderChanged { order: order.clone() });
+        },
+        req_resp::Change::OrderFinished { order } |
+        req_resp::Change::OrderDenied { order, .. } => cache::modify::<OpenOrders>(cache::ORDERS, |open| {
+            if let Some(orders) = open.get_mut(&order.table) {
+                orders.retain(|o| o.id.count != order.count);
+            }
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::error::{error, ErrorKind};
+
+    fn events(last: u64, seqs: &[u64]) -> Result<serde_json::Value, Error> {
+        let events = seqs
+            .iter()
+            .map(|&seq| req_resp::ChangeEvent {
+                seq,
+                change: req_resp::Change::OrderFinished {
+                    order: dbt::OrderID { table: "T1".to_string(), count: seq as u32 }
+                }
+            })
+            .collect();
+        Ok(serde_json::to_value(req_resp::EventsResponseData { last, events }).unwrap())
+    }
+
+    fn following(last: u64) -> LiveSync {
+        let mut sync = LiveSync::default();
+        assert!(matches!(sync.received(events(last, &[])), Received::Changes(changes) if changes.is_empty()));
+        sync
+    }
+
+    #[test]
+    fn first_poll_only_starts_following() {
+        let mut sync = LiveSync::default();
+        assert!(matches!(sync.received(events(7, &[5, 6, 7])), Received::Changes(changes) if changes.is_empty()));
+        assert_eq!(sync.last, Some(7));
+        assert!(sync.is_live());
+    }
+
+    #[test]
+    fn no_new_events_is_not_a_gap() {
+        let mut sync = following(5);
+        assert!(matches!(sync.received(events(5, &[])), Received::Changes(changes) if changes.is_empty()));
+    }
+
+    #[test]
+    fn skipped_event_is_a_gap() {
+        let mut sync = following(5);
+        assert!(matches!(sync.received(events(8, &[6, 8])), Received::Gap));
+        assert_eq!(sync.last, Some(8));
+    }
+
+    #[test]
+    fn events_beyond_the_response_are_a_gap() {
+        let mut sync = following(5);
+        assert!(matches!(sync.received(events(9, &[6, 7])), Received::Gap));
+    }
+
+    #[test]
+    fn server_restart_is_a_gap() {
+        let mut sync = following(5);
+        assert!(matches!(sync.received(events(0, &[])), Received::Gap));
+        assert_eq!(sync.last, Some(0));
+    }
+
+    #[test]
+    fn failed_poll_keeps_the_position() {
+        let mut sync = following(5);
+        let failed = error!(kind: ErrorKind::Network, name: "offline", message: "");
+        assert!(matches!(sync.received(failed), Received::Failed(_)));
+        assert!(!sync.is_live());
+        assert_eq!(sync.last, Some(5));
+    }
+
+}
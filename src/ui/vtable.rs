@@ -4,21 +4,37 @@ use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde_json::json;
 
 
-use crate::shared::{dbt as dbt, req_resp};
+use crate::shared::{dbt as dbt, discovery, req_resp};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::error::{error, error_forward, function_message, Error, ErrorKind};
+use crate::cache;
+use super::notify::Notifications;
 use super::Tab;
 
 const TITLE: &str = "Virtual Tables"; 
 const ICON:  char = '\u{e9ba}';
+/// How long a table stays open for ordering if it is not
+/// closed at payment.
+const SESSION_MINUTES: u32 = 180;
 
 #[derive(Debug, Clone)]
 pub enum VirtualTableManagerMessage {
-    FetchedVirtualTables(Result<serde_json::Value, String>),
+    FetchedVirtualTables(u64, Result<serde_json::Value, Error>),
     GenerateQRCode(dbt::VirtualTableID),
     CloseQRCode,
     DeleteVirtualTable(dbt::VirtualTableID),
-    DeleteVirtualTablePost(Result<serde_json::Value, String>),
+    DeleteVirtualTablePost(Result<serde_json::Value, Error>, AuditEntry),
     AddVirtualTable(dbt::VirtualTableID),
-    AddVirtualTablePost(Result<serde_json::Value, String>),
+    AddVirtualTablePost(Result<serde_json::Value, Error>, AuditEntry),
+    /// Starts a session so the guests can order, then shows its QR code.
+    OpenSession(dbt::VirtualTableID),
+    OpenSessionPost(Result<serde_json::Value, Error>, AuditEntry),
+    CloseSession(dbt::VirtualTableID),
+    CloseSessionPost(Result<serde_json::Value, Error>, AuditEntry),
+    FetchedKey(Result<serde_json::Value, Error>),
+    /// Replaces the signing key, the printed QR codes stop working.
+    RotateKey,
+    RotateKeyPost(Result<serde_json::Value, Error>, AuditEntry),
     TextInputed(String)
 }
 
@@ -30,17 +46,29 @@ impl Into<crate::Message> for VirtualTableManagerMessage {
 
 
 pub struct VirtualTableManager {
-    pub fetch_vtables: Result<Vec<dbt::VirtualTable>, String>,
-    pub qr_code: Option<(dbt::VirtualTableID, iced::widget::qr_code::Data)>,
-    pub table_name_text_input: String
+    pub fetch_vtables: Result<Vec<dbt::VirtualTable>, Error>,
+    pub qr_code: Option<(dbt::VirtualTableID, Result<iced::widget::qr_code::Data, Error>)>,
+    pub table_name_text_input: String,
+    /// Save time of the cached tables shown while offline.
+    pub cached: Option<String>,
+    pub request: super::RequestSlot,
+    /// Idempotency key of the table being added, kept for retries
+    /// until the name changes so the table is not added twice.
+    pub insert_key: Option<String>,
+    /// Key the server signs the QR codes with, for display.
+    pub signing_key: Result<dbt::SigningKey, Error>
 }
 
 impl Default for VirtualTableManager {
     fn default() -> Self {
         Self { 
-            fetch_vtables: Err("Fetching data...".to_string()),
+            fetch_vtables: Err(Error::pending("Fetching data...")),
             qr_code: None,
-            table_name_text_input: String::new()
+            table_name_text_input: String::new(),
+            cached: None,
+            request: super::RequestSlot::default(),
+            insert_key: None,
+            signing_key: Err(Error::pending("Fetching the key of the QR codes..."))
         }
     }
 }
@@ -49,7 +77,50 @@ impl Default for VirtualTableManager {
 
 impl VirtualTableManager {
 
-    pub fn update(&mut self, message: VirtualTableManagerMessage) -> Task<crate::Message> {
+    /// Applies a change made on the server to the shown tables.
+    pub fn apply_change(&mut self, change: &req_resp::Change) {
+        let Ok(vtables) = &mut self.fetch_vtables else {
+            return
+        };
+        match change {
+            req_resp::Change::TableAdded { table } |
+            req_resp::Change::TableChanged { table } => {
+                match vtables.iter_mut().find(|vtable| vtable.name == table.name) {
+                    Some(vtable) => *vtable = table.clone(),
+                    None => vtables.push(table.clone())
+                }
+            },
+            req_resp::Change::TableDeleted { table } => vtables.retain(|vtable| &vtable.name != table),
+            _ => {}
+        }
+    }
+
+    /// Fetches the tables and the signing key of their QR codes.
+    pub fn fetch(&mut self) -> Task<crate::Message> {
+        let tables = self.request.start(|generation| {
+            let mut request = req_resp::Request {
+                kind: req_resp::RequestKind::Tables,
+                payload: None
+            };
+            Task::perform(
+                async move {request.send_request("".to_string()).await}, 
+                move |value| {VirtualTableManagerMessage::FetchedVirtualTables(generation, value).into()}
+            )
+        });
+        let mut request = req_resp::Request {
+            kind: req_resp::RequestKind::TablesKey,
+            payload: None
+        };
+        Task::batch([
+            tables,
+            Task::perform(
+                async move {request.send_request("".to_string()).await},
+                |value| VirtualTableManagerMessage::FetchedKey(value).into()
+            ),
+        ])
+    }
+
+    pub fn update(&mut self, message: VirtualTableManagerMessage, username: &str, notify: &mut Notifications) -> Task<crate::Message> {
 
         const QUERY_ENCODE_SET: &AsciiSet = &CONTROLS
             .add(b' ')
@@ -64,88 +135,195 @@ impl VirtualTableManager {
 
         match message {
             VirtualTableManagerMessage::GenerateQRCode(table) => {
-                let IP = req_resp::get_local_ip_address().expect("Not connected to a network dummy!");
-
-                let format = format!(
-                    "http://{}:{}/{}", 
-                    IP, 
-                    req_resp::HTML_PORT, 
-                    utf8_percent_encode(
-                        table.clone().as_str(),
-                        QUERY_ENCODE_SET
-                    )
-                );
-                self.qr_code = Some((table.clone(), iced::widget::qr_code::Data::new(
-                    format.as_bytes()
-                ).expect("QRCodeData")));
+                let session = self.fetch_vtables.as_ref().ok()
+                    .and_then(|vtables| vtables.iter().find(|vtable| vtable.name == table))
+                    .and_then(|vtable| vtable.session.clone())
+                    .filter(dbt::TableSession::is_open);
+                let signed = match session {
+                    None => error!(
+                        kind: ErrorKind::Input,
+                        name: format!("Table `{}` is not open", table),
+                        message: "open it to let the guests order".to_string()
+                    ),
+                    Some(session) if session.sig.is_empty() => error!(
+                        kind: ErrorKind::Protocol,
+                        name: format!("The session of table `{}` is not signed", table),
+                        message: "the server sent no signature for the QR code, is it up to date?".to_string()
+                    ),
+                    Some(session) => Ok(session),
+                };
+                let data = match (discovery::web_address(), signed) {
+                    (Err(err), _) => Err(error_forward!(err)),
+                    (_, Err(err)) => Err(err),
+                    (Ok(address), Ok(session)) => {
+                        let format = format!(
+                            "http://{}/{}?session={}&key={}&sig={}", 
+                            address, 
+                            utf8_percent_encode(
+                                table.clone().as_str(),
+                                QUERY_ENCODE_SET
+                            ),
+                            utf8_percent_encode(&session.token, QUERY_ENCODE_SET),
+                            session.key_id,
+                            utf8_percent_encode(&session.sig, QUERY_ENCODE_SET)
+                        );
+                        match iced::widget::qr_code::Data::new(format.as_bytes()) {
+                            Ok(data) => Ok(data),
+                            Err(err) => error!(
+                                kind: ErrorKind::Input,
+                                name: "Cannot encode the QR code",
+                                message: function_message!("qr_code::Data::new", err.to_string())
+                            )
+                        }
+                    }
+                };
+                if let Err(err) = &data {
+                    notify.error(err, Some(VirtualTableManagerMessage::GenerateQRCode(table.clone()).into()));
+                }
+                self.qr_code = Some((table.clone(), data));
             }
             VirtualTableManagerMessage::CloseQRCode => {
                 self.qr_code = None;
             }
-            VirtualTableManagerMessage::FetchedVirtualTables(response) => {
-                if response.is_ok() {
-                    match serde_json::from_value::<req_resp::TablesResponseData>(response.unwrap()) {
-                        Ok(payload) => self.fetch_vtables = Ok(payload.tables),
-                        Err(err) => self.fetch_vtables = Err(err.to_string())
-                    }
-                } else {
-                    self.fetch_vtables = Err(response.unwrap_err().to_string())
+            VirtualTableManagerMessage::FetchedVirtualTables(generation, response) => {
+                if !self.request.finish(generation) {
+                    return Task::none()
+                }
+                (self.fetch_vtables, self.cached) = cache::fallback(cache::TABLES, response
+                    .and_then(req_resp::decode::<req_resp::TablesResponseData>)
+                    .map(|payload| payload.tables));
+                if let Err(err) = &self.fetch_vtables {
+                    notify.error(err, Some(super::UIMessage::TabSelected(super::UITabID::VirtualTableManager).into()));
                 }
             },
             VirtualTableManagerMessage::DeleteVirtualTable(table) => {
+                let before = self.fetch_vtables.as_ref().ok()
+                    .and_then(|vtables| vtables.iter().find(|vtable| vtable.name == table))
+                    .cloned();
+                let audit = AuditEntry::new(username, AuditAction::DeleteVirtualTable, &table, before.as_ref(), None);
                 let mut request = req_resp::Request {
                     kind: req_resp::RequestKind::TablesDelete,
                     payload: None
                 };
                 return Task::perform(
                     async move {request.send_request(table).await}, 
-                    |value| {
-                        VirtualTableManagerMessage::DeleteVirtualTablePost(value).into()}
+                    move |value| {
+                        VirtualTableManagerMessage::DeleteVirtualTablePost(value, audit.clone()).into()}
                 )
             },
-            VirtualTableManagerMessage::DeleteVirtualTablePost(result) => {
-                if result.is_ok() {
-                    let mut request = req_resp::Request {
-                        kind: req_resp::RequestKind::Tables,
-                        payload: None
-                    };
-                    return Task::perform(
-                        async move {request.send_request("".to_string()).await}, 
-                        |value| {VirtualTableManagerMessage::FetchedVirtualTables(value).into()}
-                    )
+            VirtualTableManagerMessage::DeleteVirtualTablePost(result, audit) => {
+                if let Err(err) = result {
+                    notify.error(&err, Some(VirtualTableManagerMessage::DeleteVirtualTable(audit.target.clone()).into()));
+                } else {
+                    audit.commit();
+                    notify.success(format!("Table `{}` deleted", audit.target));
+                    return self.fetch()
                 }
             },
             VirtualTableManagerMessage::AddVirtualTable(name) => {
                 if name.is_empty() {
                     return Task::none()
                 }
-                let mut request = req_resp::Request {
-                    kind: req_resp::RequestKind::TablesInsert,
-                    payload: Some(serde_json::to_value(req_resp::TablesInsertRequestData {
-                        table: dbt::VirtualTable { name, order_count: 0 }
-                    }).unwrap())
-                };
+                let table = dbt::VirtualTable { name, order_count: 0, session: None };
+                let audit = AuditEntry::new(username, AuditAction::AddVirtualTable, &table.name, None, Some(&table));
+                let request = req_resp::Request::encode(req_resp::RequestKind::TablesInsert, &req_resp::TablesInsertRequestData {
+                    table
+                });
+                let key = self.insert_key.get_or_insert_with(req_resp::new_idempotency_key).clone();
                 return Task::perform(
-                    async move {request.send_request("".to_string()).await}, 
-                    |value| {VirtualTableManagerMessage::AddVirtualTablePost(value).into()}
+                    async move {request?.send_request_with_key("".to_string(), Some(key)).await}, 
+                    move |value| {VirtualTableManagerMessage::AddVirtualTablePost(value, audit.clone()).into()}
                 )
             },
-            VirtualTableManagerMessage::AddVirtualTablePost(result) => {
-                if result.is_ok() {
+            VirtualTableManagerMessage::AddVirtualTablePost(result, audit) => {
+                if let Err(err) = result {
+                    notify.error(&err, Some(VirtualTableManagerMessage::AddVirtualTable(audit.target.clone()).into()));
+                } else {
+                    audit.commit();
+                    notify.success(format!("Table `{}` added", audit.target));
+                    self.insert_key = None;
                     self.table_name_text_input = String::new();
-                    let mut request = req_resp::Request {
-                        kind: req_resp::RequestKind::Tables,
-                        payload: None
-                    };
-                    return Task::perform(
-                        async move {request.send_request("".to_string()).await}, 
-                        |value| {
-                            VirtualTableManagerMessage::FetchedVirtualTables(value).into()
-                        }
-                    )
+                    return self.fetch()
                 }
             }
-            VirtualTableManagerMessage::TextInputed(text) => {self.table_name_text_input = text}
+            VirtualTableManagerMessage::OpenSession(table) => {
+                // The token stays out of the log, it lets anyone order.
+                let audit = AuditEntry::new::<dbt::VirtualTable>(username, AuditAction::OpenTable, &table, None, None);
+                let request = req_resp::Request::encode(req_resp::RequestKind::TablesOpen, &req_resp::TablesOpenRequestData {
+                    table,
+                    minutes: SESSION_MINUTES
+                });
+                return Task::perform(
+                    async move {request?.send_request("".to_string()).await},
+                    move |value| VirtualTableManagerMessage::OpenSessionPost(value, audit.clone()).into()
+                )
+            },
+            VirtualTableManagerMessage::OpenSessionPost(result, audit) => {
+                match result.and_then(req_resp::decode::<req_resp::TablesOpenResponseData>) {
+                    Ok(response) => {
+                        audit.commit();
+                        self.apply_change(&req_resp::Change::TableChanged { table: response.table });
+                        return Task::done(VirtualTableManagerMessage::GenerateQRCode(audit.target).into())
+                    },
+                    Err(err) => notify.error(&error_forward!(err), Some(VirtualTableManagerMessage::OpenSession(audit.target.clone()).into())),
+                }
+            },
+            VirtualTableManagerMessage::CloseSession(table) => {
+                let audit = AuditEntry::new::<dbt::VirtualTable>(username, AuditAction::CloseTable, &table, None, None);
+                let request = req_resp::Request::encode(req_resp::RequestKind::TablesClose, &req_resp::TablesCloseRequestData {
+                    table
+                });
+                return Task::perform(
+                    async move {request?.send_request("".to_string()).await},
+                    move |value| VirtualTableManagerMessage::CloseSessionPost(value, audit.clone()).into()
+                )
+            },
+            VirtualTableManagerMessage::CloseSessionPost(result, audit) => {
+                match result.and_then(req_resp::decode::<req_resp::TablesCloseResponseData>) {
+                    Ok(response) => {
+                        audit.commit();
+                        notify.success(format!("Table `{}` closed, its QR code and PIN no longer take orders", audit.target));
+                        if self.qr_code.as_ref().is_some_and(|(table, _)| table == &audit.target) {
+                            self.qr_code = None;
+                        }
+                        self.apply_change(&req_resp::Change::TableChanged { table: response.table });
+                    },
+                    Err(err) => notify.error(&error_forward!(err), Some(VirtualTableManagerMessage::CloseSession(audit.target.clone()).into())),
+                }
+            },
+            VirtualTableManagerMessage::FetchedKey(result) => {
+                self.signing_key = result
+                    .and_then(req_resp::decode::<req_resp::TablesKeyResponseData>)
+                    .map(|payload| payload.key);
+            },
+            VirtualTableManagerMessage::RotateKey => {
+                let before = self.signing_key.as_ref().ok().map(|key| key.id);
+                let audit = AuditEntry::new(username, AuditAction::RotateKey, "QR codes", before.as_ref(), None);
+                let mut request = req_resp::Request {
+                    kind: req_resp::RequestKind::TablesKeyRotate,
+                    payload: None
+                };
+                let key = req_resp::new_idempotency_key();
+                return Task::perform(
+                    async move {request.send_request_with_key("".to_string(), Some(key)).await},
+                    move |value| VirtualTableManagerMessage::RotateKeyPost(value, audit.clone()).into()
+                )
+            },
+            VirtualTableManagerMessage::RotateKeyPost(result, mut audit) => {
+                match result.and_then(req_resp::decode::<req_resp::TablesKeyResponseData>) {
+                    Ok(payload) => {
+                        audit.after = Some(json!(payload.key.id));
+                        audit.commit();
+                        notify.success(format!("QR codes are signed with key #{} now, print the codes of the open tables again", payload.key.id));
+                        self.qr_code = None;
+                        self.signing_key = Ok(payload.key);
+                        // The open sessions carry the new signatures.
+                        return self.fetch()
+                    },
+                    Err(err) => notify.error(&error_forward!(err), Some(VirtualTableManagerMessage::RotateKey.into())),
+                }
+            },
+            VirtualTableManagerMessage::TextInputed(text) => {self.table_name_text_input = text; self.insert_key = None}
         }
         Task::none()
 
@@ -265,14 +443,14 @@ impl Tab for VirtualTableManager {
 
     fn content(&self) -> iced::Element<'_, Self::Message> {
         
-        if self.fetch_vtables.is_err() {
-            text!("{}", self.fetch_vtables.clone().expect_err("").to_string()).into()
-        } else if self.qr_code.is_some() {
+        if let Err(err) = &self.fetch_vtables {
+            text!("{}", err.summary()).into()
+        } else if let Some((table, data)) = &self.qr_code {
             let mut col: Column<'_, crate::Message> = Column::new().spacing(20).padding(30);
 
             col = col.push(
                 center(
-                    text!("{}", self.qr_code.as_ref().unwrap().0),
+                    text!("{}", table),
                 )
                 .padding(10)
                 // .style(vtable_container_style)
@@ -280,9 +458,19 @@ impl Tab for VirtualTableManager {
                 .width(Length::Fill)
             );
 
+            let session = self.fetch_vtables.as_ref().ok()
+                .and_then(|vtables| vtables.iter().find(|vtable| &vtable.name == table))
+                .and_then(|vtable| vtable.session.as_ref());
+            col = col.push_maybe(session.map(|session| center(
+                text!("PIN {}, valid until {}", session.pin, session.expires_at()).size(20)
+            ).height(Length::Shrink)));
+
             col = col.push(
                 center(
-                    iced::widget::qr_code(&self.qr_code.as_ref().unwrap().1)
+                    match data {
+                        Ok(data) => iced::Element::from(iced::widget::qr_code(data)),
+                        Err(err) => text!("{}", err.summary()).into()
+                    }
                 )
                 .padding(10)
                 .style(vtable_container_style)
@@ -291,15 +479,15 @@ impl Tab for VirtualTableManager {
             );
 
             col = col.push(
-                
-            // center(
+                row![
                     button("Close")
                         .on_press(VirtualTableManagerMessage::CloseQRCode.into())
-                        .style(virtual_table_button_style_add)
-                // )
-                // .padding(10)
-                // .height(Length::Fixed(50.0))
-                // .width(Length::Fixed(100.0))
+                        .style(virtual_table_button_style_add),
+                    iced::widget::horizontal_space(),
+                    button("Close table")
+                        .on_press(VirtualTableManagerMessage::CloseSession(table.clone()).into())
+                        .style(virtual_table_button_style),
+                ]
             );
 
             center(col).width(Length::Fill).into()
@@ -308,28 +496,54 @@ impl Tab for VirtualTableManager {
 
             
             let mut col: Column<'_, crate::Message> = Column::new().spacing(20).padding(30);
+
+            if let Some(saved) = &self.cached {
+                col = col.push(super::offline_banner(saved));
+            }
             
             let vtables = self.fetch_vtables.clone().unwrap();
             for vtable in vtables.iter() {
                 // let svg_icon = iced::widget::Svg::from_path("../../image/qr-code-scan.svg").content_fit(iced::ContentFit::ScaleDown);
+                let session = vtable.session.as_ref().filter(|session| session.is_open());
+                // Sessions are opened on the server, not while offline.
+                let session_buttons = match (session, self.cached.is_none()) {
+                    (Some(_), _) => row![
+                        button("QR Code")
+                            .on_press(VirtualTableManagerMessage::GenerateQRCode(vtable.name.clone()).into())
+                            .style(virtual_table_button_style),
+                    ]
+                    .push_maybe(self.cached.is_none().then(|| 
+                        button("Close")
+                            .on_press(VirtualTableManagerMessage::CloseSession(vtable.name.clone()).into())
+                            .style(virtual_table_button_style)
+                    )),
+                    (None, true) => row![
+                        button("Open")
+                            .on_press(VirtualTableManagerMessage::OpenSession(vtable.name.clone()).into())
+                            .style(virtual_table_button_style),
+                    ],
+                    (None, false) => row![],
+                };
                 col = col.push(
                     container(
                         row![
                             text!("`{}`", vtable.name.clone()),
                             iced::widget::horizontal_space(),
-                            button("QR Code")
-                                .on_press(VirtualTableManagerMessage::GenerateQRCode(vtable.name.clone()).into())
-                                .style(virtual_table_button_style),
+                        ]
+                        .push_maybe(session.map(|session| text!("Open until {}, PIN {}", session.expires_at(), session.pin)))
+                        .push(session_buttons.spacing(5))
+                        .push_maybe(self.cached.is_none().then(|| 
                             button("X")
                                 .on_press(VirtualTableManagerMessage::DeleteVirtualTable(vtable.name.clone()).into())
                                 .style(virtual_table_button_style)
-                        ].spacing(5)
+                        ))
+                        .spacing(5)
                     )
                     .padding(10)
                     .style(vtable_container_style).width(Length::Fill)
                 );
             }
-            col = col.push(
+            col = col.push_maybe(self.cached.is_none().then(|| 
                 container(
                     row![
                         iced::widget::text_input("Table name", &self.table_name_text_input)
@@ -342,8 +556,32 @@ impl Tab for VirtualTableManager {
                 )
                 .padding(10)
                 .style(vtable_container_style).width(Length::Fill)
-            );
+            ));
         // let client = Client::new();
+            col = col.push_maybe(self.cached.is_none().then(|| 
+                container(
+                    row![
+                        text(match &self.signing_key {
+                            Ok(key) => format!(
+                                "QR codes signed with key #{} since {}",
+                                key.id,
+                                chrono::DateTime::parse_from_rfc3339(&key.created)
+                                    .map(|created| created.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                                    .unwrap_or_else(|_| key.created.clone())
+                            ),
+                            Err(err) => err.summary()
+                        }),
+                        iced::widget::horizontal_space(),
+                        button("Rotate key")
+                            .on_press(VirtualTableManagerMessage::RotateKey.into())
+                            .style(virtual_table_button_style),
+                    ]
+                    .spacing(5)
+                    .align_y(iced::alignment::Vertical::Center)
+                )
+                .padding(10)
+                .style(vtable_container_style).width(Length::Fill)
+            ));
 
             center(scrollable(col).width(Length::Fill)).into()
 
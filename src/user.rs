@@ -1,15 +1,20 @@
 use std::path::PathBuf;
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
 
 #[derive(Default, Debug, Clone)]
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct UserEntry {
 
-    #[serde(skip_deserializing)]
+    #[serde(skip)]
     pub username: String,
 
     pub md5:  String,
-    pub salt: String
+    pub salt: String,
+
+    /// md5 of the quick-login PIN with the same salt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin:  Option<String>
 
 }
 
@@ -29,13 +34,52 @@ impl UserEntry {
         self.md5 = format!("{:x}", md5::compute(format!("{password}{salt}")));
     }
 
+    pub fn set_pin(&mut self, pin: &str) {
+        self.pin = Some(format!("{:x}", md5::compute(format!("{pin}{}", self.salt))));
+    }
+
+    pub fn pin_matches(&self, pin: &str) -> bool {
+        match &self.pin {
+            Some(hash) => *hash == format!("{:x}", md5::compute(format!("{pin}{}", self.salt))),
+            None => false
+        }
+    }
+
+    /// PINs are 4 to 8 digits.
+    pub fn is_valid_pin(pin: &str) -> bool {
+        (4..=8).contains(&pin.len()) && pin.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// Atomically writes the entry to `users/<username>.toml`.
+    pub fn save(&self) -> Result<(), crate::error::Error> {
+
+        let raw = match toml::to_string(self) {
+            Ok(raw) => raw,
+            Err(err) => return crate::error::error!(
+                kind: crate::error::ErrorKind::Decode,
+                name: format!("Failed to serialize `{}.toml`", self.username),
+                message: crate::error::function_message!("toml::to_string", err.to_string())
+            )
+        };
+
+        match crate::storage::write_atomic(&crate::storage::user_entry_file(&self.username), &raw) {
+            Ok(()) => Ok(()),
+            Err(err) => crate::error::error!(
+                kind: crate::error::ErrorKind::Storage,
+                name: format!("Failed to write `{}.toml`", self.username),
+                message: crate::error::function_message!("storage::write_atomic", err.to_string())
+            )
+        }
+
+    }
+
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 #[derive(Serialize, Deserialize)]
 pub enum UserPrivilege {
-    Basic,
     #[default]
+    Basic,
     Admin
 }
 
@@ -57,7 +101,8 @@ pub struct UserData {
     #[serde(skip_deserializing, skip_serializing)]
     pub username:   String,
     
-    #[serde(skip_deserializing, skip_serializing)]
+    /// Missing in `user.toml` means `Basic`.
+    #[serde(default)]
     pub privilege: UserPrivilege,
 
     pub name:       String,
@@ -119,9 +164,159 @@ impl Into<iced_aw::TabBarPosition> for SettingTabPosition {
 
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 #[derive(Serialize, Deserialize)]
+pub enum SettingStartMode {
+    #[default]
+    Tabs,
+    /// Opens the kitchen display, for the screen in the kitchen.
+    KitchenDisplay,
+}
+
+/// Version of `settings.toml` written by this build.
+/// Bump it when a field changes meaning, new fields
+/// only need a default.
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// Choices for the idle auto-lock, in minutes. `0` turns it off.
+pub const AUTO_LOCK_CHOICES: [u32; 4] = [0, 5, 15, 30];
+
+/// Choices for the ageing thresholds of orders, in minutes.
+pub const AGEING_CHOICES: [u32; 5] = [5, 10, 15, 20, 30];
+
+/// Choices for the tax rate on the bills, in percent.
+pub const TAX_CHOICES: [u32; 8] = [0, 5, 7, 10, 13, 19, 20, 25];
+
+/// Choices for the volume of the alert sounds, in percent.
+pub const VOLUME_CHOICES: [u32; 4] = [25, 50, 75, 100];
+
+/// How long an order has been waiting, compared to the thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Age {
+    Fresh,
+    Late,
+    Overdue,
+}
+
+/// Tax on the bills, from the settings.
+#[derive(Debug, Clone, Copy)]
+pub struct Tax {
+    pub percent:  u32,
+    /// The offer prices already contain the tax.
+    pub included: bool,
+}
+
+/// Thresholds for orders waiting too long, from the settings.
+#[derive(Debug, Clone, Copy)]
+pub struct Ageing {
+    pub late:    Duration,
+    pub overdue: Duration,
+}
+
+impl Default for Ageing {
+    fn default() -> Self {
+        Settings::default().ageing()
+    }
+}
+
+impl Ageing {
+    pub fn of(&self, age: Duration) -> Age {
+        match age {
+            age if age >= self.overdue => Age::Overdue,
+            age if age >= self.late => Age::Late,
+            _ => Age::Fresh
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
+    pub version:      u32,
     pub theme:        SettingTheme,
-    pub tab_bar_position: SettingTabPosition
+    pub tab_bar_position: SettingTabPosition,
+    /// Minutes without input before returning to the login page.
+    pub auto_lock_minutes: u32,
+    /// What to show after logging in.
+    pub start_mode:   SettingStartMode,
+    /// Minutes after which an order is highlighted as late,
+    /// then as overdue.
+    pub late_after_minutes:    u32,
+    pub overdue_after_minutes: u32,
+    /// Alerts, see `alerts::AlertSettings`.
+    pub overdue_alert: bool,
+    pub new_order_alert: bool,
+    pub denied_alert: bool,
+    pub sound_volume: u32,
+    pub muted: bool,
+    pub desktop_notifications: bool,
+    pub quiet_hours: bool,
+    pub quiet_from_hour: u32,
+    pub quiet_until_hour: u32,
+    /// Tax rate on the bills, in percent.
+    pub tax_percent: u32,
+    pub prices_include_tax: bool,
+    /// Settings of newer versions, written back unchanged so
+    /// going back to such a version keeps them.
+    #[serde(flatten)]
+    pub extra: toml::Table
+}
+
+impl Default for Settings {
+
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            theme: SettingTheme::default(),
+            tab_bar_position: SettingTabPosition::default(),
+            auto_lock_minutes: 0,
+            start_mode: SettingStartMode::default(),
+            late_after_minutes: 5,
+            overdue_after_minutes: 10,
+            overdue_alert: true,
+            new_order_alert: true,
+            denied_alert: true,
+            sound_volume: 75,
+            muted: false,
+            desktop_notifications: true,
+            quiet_hours: false,
+            quiet_from_hour: 22,
+            quiet_until_hour: 7,
+            tax_percent: 25,
+            prices_include_tax: true,
+            extra: toml::Table::new()
+        }
+    }
+
+}
+
+impl Settings {
+
+    pub fn ageing(&self) -> Ageing {
+        Ageing {
+            late: Duration::from_secs(self.late_after_minutes as u64 * 60),
+            overdue: Duration::from_secs(self.overdue_after_minutes as u64 * 60),
+        }
+    }
+
+    pub fn tax(&self) -> Tax {
+        Tax {
+            percent: self.tax_percent,
+            included: self.prices_include_tax,
+        }
+    }
+
+    pub fn alerts(&self) -> crate::alerts::AlertSettings {
+        crate::alerts::AlertSettings {
+            new_orders: self.new_order_alert,
+            overdue: self.overdue_alert,
+            denied: self.denied_alert,
+            volume: self.sound_volume,
+            muted: self.muted,
+            desktop: self.desktop_notifications,
+            quiet_hours: self.quiet_hours.then_some((self.quiet_from_hour, self.quiet_until_hour)),
+        }
+    }
+
 }
\ No newline at end of file